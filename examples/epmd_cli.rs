@@ -7,7 +7,9 @@
 //! $ cargo run --example epmd_cli names
 //! $ cargo run --example epmd_cli node_entry foo
 //! ```
-use erl_dist::epmd::{EpmdClient, NodeEntry};
+use erl_dist::blocking::EpmdClient;
+use erl_dist::epmd::NodeEntry;
+use std::net::TcpStream;
 
 fn main() -> noargs::Result<()> {
     let mut args = noargs::raw_args();
@@ -39,12 +41,11 @@ fn main() -> noargs::Result<()> {
             return Ok(());
         }
 
-        smol::block_on(async {
-            let stream =
-                smol::net::TcpStream::connect(format!("{}:{}", epmd_host, epmd_port)).await?;
+        (|| {
+            let stream = TcpStream::connect(format!("{}:{}", epmd_host, epmd_port))?;
             let client = EpmdClient::new(stream);
 
-            let names = client.get_names().await?;
+            let names = client.get_names()?;
             let result = nojson::json(|f| {
                 f.set_indent_size(2);
                 f.set_spacing(true);
@@ -62,7 +63,7 @@ fn main() -> noargs::Result<()> {
             });
             println!("{result}");
             Ok::<(), Box<dyn std::error::Error>>(())
-        })?;
+        })()?;
     } else if noargs::cmd("dump")
         .doc("Dump all registered nodes")
         .take(&mut args)
@@ -73,15 +74,14 @@ fn main() -> noargs::Result<()> {
             return Ok(());
         }
 
-        smol::block_on(async {
-            let stream =
-                smol::net::TcpStream::connect(format!("{}:{}", epmd_host, epmd_port)).await?;
+        (|| {
+            let stream = TcpStream::connect(format!("{}:{}", epmd_host, epmd_port))?;
             let client = EpmdClient::new(stream);
 
-            let result = client.dump().await?;
+            let result = client.dump()?;
             println!("{}", result);
             Ok::<(), Box<dyn std::error::Error>>(())
-        })?;
+        })()?;
     } else if noargs::cmd("node_entry")
         .doc("Get node entry information")
         .take(&mut args)
@@ -97,12 +97,12 @@ fn main() -> noargs::Result<()> {
             return Ok(());
         }
 
-        smol::block_on(async {
-            let stream =
-                smol::net::TcpStream::connect(format!("{}:{}", epmd_host, epmd_port)).await?;
+        (|| {
+            let stream = TcpStream::connect(format!("{}:{}", epmd_host, epmd_port))?;
             let client = EpmdClient::new(stream);
 
-            let node_info = client.get_node(&node).await?.ok_or("node not found")?;
+            let node_info = client.get_node(&node)?.ok_or("node not found")?;
+            let protocol = u8::try_from(node_info.protocol)?;
             let result = nojson::json(|f| {
                 f.set_indent_size(2);
                 f.set_spacing(true);
@@ -120,12 +120,7 @@ fn main() -> noargs::Result<()> {
                     )?;
                     f.member(
                         "protocol",
-                        format!(
-                            "{:?} ({})",
-                            node_info.protocol,
-                            u8::from(node_info.protocol)
-                        )
-                        .as_str(),
+                        format!("{:?} ({})", node_info.protocol, protocol).as_str(),
                     )?;
                     f.member("highest_version", node_info.highest_version)?;
                     f.member("lowest_version", node_info.lowest_version)?;
@@ -134,7 +129,7 @@ fn main() -> noargs::Result<()> {
             });
             println!("{result}");
             Ok::<(), Box<dyn std::error::Error>>(())
-        })?;
+        })()?;
     } else if noargs::cmd("kill")
         .doc("Kill EPMD daemon")
         .take(&mut args)
@@ -145,12 +140,11 @@ fn main() -> noargs::Result<()> {
             return Ok(());
         }
 
-        smol::block_on(async {
-            let stream =
-                smol::net::TcpStream::connect(format!("{}:{}", epmd_host, epmd_port)).await?;
+        (|| {
+            let stream = TcpStream::connect(format!("{}:{}", epmd_host, epmd_port))?;
             let client = EpmdClient::new(stream);
 
-            let result = client.kill().await?;
+            let result = client.kill()?;
             let result = nojson::json(|f| {
                 f.set_indent_size(2);
                 f.set_spacing(true);
@@ -158,7 +152,7 @@ fn main() -> noargs::Result<()> {
             });
             println!("{result}");
             Ok::<(), Box<dyn std::error::Error>>(())
-        })?;
+        })()?;
     } else if noargs::cmd("register")
         .doc("Register a node with EPMD")
         .take(&mut args)
@@ -183,9 +177,8 @@ fn main() -> noargs::Result<()> {
             return Ok(());
         }
 
-        smol::block_on(async {
-            let stream =
-                smol::net::TcpStream::connect(format!("{}:{}", epmd_host, epmd_port)).await?;
+        (|| {
+            let stream = TcpStream::connect(format!("{}:{}", epmd_host, epmd_port))?;
             let client = EpmdClient::new(stream);
 
             let node = if hidden {
@@ -193,7 +186,7 @@ fn main() -> noargs::Result<()> {
             } else {
                 NodeEntry::new(&name, port)
             };
-            let (_, creation) = client.register(node).await?;
+            let (_, creation) = client.register(node)?;
             let result = nojson::json(|f| {
                 f.set_indent_size(2);
                 f.set_spacing(true);
@@ -201,7 +194,7 @@ fn main() -> noargs::Result<()> {
             });
             println!("{result}");
             Ok::<(), Box<dyn std::error::Error>>(())
-        })?;
+        })()?;
     } else if let Some(help) = args.finish()? {
         print!("{help}");
         return Ok(());
@@ -40,7 +40,7 @@ fn main() -> noargs::Result<()> {
             let stream = smol::net::TcpStream::connect(addr).await?;
             let epmd_client = erl_dist::epmd::EpmdClient::new(stream);
             epmd_client
-                .get_node(&peer_node.name())
+                .get_node(peer_node.name())
                 .await?
                 .ok_or("peer node not found")?
         };
@@ -104,29 +104,13 @@ async fn handle_client(
     let (stream, peer_node) = handshake.execute_rest(status).await?;
     println!("Connected: {:?}", peer_node);
 
-    let (mut tx, rx) = erl_dist::message::channel(stream, local_node.flags & peer_node.flags);
-    let mut timer = smol::Timer::after(std::time::Duration::from_secs(30));
-    let mut msg_future = Box::pin(rx.recv_owned());
+    let (mut tx, mut rx) = erl_dist::message::channel(stream, local_node.flags & peer_node.flags);
+    let mut keepalive = erl_dist::message::Keepalive::new(
+        erl_dist::message::DEFAULT_NET_TICKTIME,
+        smol::Timer::after,
+    );
     loop {
-        let result = futures::future::select(
-            msg_future,
-            smol::Timer::after(std::time::Duration::from_secs(10)),
-        )
-        .await;
-        match result {
-            futures::future::Either::Left((result, _)) => {
-                let (msg, rx) = result?;
-                println!("Recv: {:?}", msg);
-                msg_future = Box::pin(rx.recv_owned());
-            }
-            futures::future::Either::Right((_, f)) => {
-                msg_future = f;
-            }
-        }
-
-        if smol::future::poll_once(&mut timer).await.is_some() {
-            tx.send(erl_dist::message::Message::Tick).await?;
-            timer.set_after(std::time::Duration::from_secs(30));
-        }
+        let msg = keepalive.recv(&mut tx, &mut rx).await?;
+        println!("Recv: {:?}", msg);
     }
 }
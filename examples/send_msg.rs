@@ -49,30 +49,16 @@ fn main() -> noargs::Result<()> {
     }
 
     smol::block_on(async {
-        let peer_node_info = {
-            let addr = (peer_node.host(), erl_dist::epmd::DEFAULT_EPMD_PORT);
-            let stream = smol::net::TcpStream::connect(addr).await?;
-            let epmd_client = erl_dist::epmd::EpmdClient::new(stream);
-            epmd_client
-                .get_node(&peer_node.name())
-                .await?
-                .ok_or("peer node not found")?
-        };
-        println!("Got peer node info: {:?}", peer_node_info);
-
         let creation = erl_dist::node::Creation::random();
-        let stream = smol::net::TcpStream::connect((peer_node.host(), peer_node_info.port)).await?;
         let local_node = erl_dist::node::LocalNode::new(local_node, creation);
-        let mut handshake =
-            erl_dist::handshake::ClientSideHandshake::new(stream, local_node.clone(), &cookie);
-        let _status = handshake
-            .execute_send_name(erl_dist::LOWEST_DISTRIBUTION_PROTOCOL_VERSION)
-            .await?;
-        let (connection, peer_node) = handshake.execute_rest(true).await?;
+        let tcp_connect = |host: String, port| async move {
+            smol::net::TcpStream::connect((host.as_str(), port)).await
+        };
+        let (mut tx, _, peer_node) =
+            erl_dist::connection::connect(local_node.clone(), &peer_node, &cookie, tcp_connect)
+                .await?;
         println!("Handshake finished: peer={:?}", peer_node);
 
-        let (mut tx, _) =
-            erl_dist::message::channel(connection, local_node.flags & peer_node.flags);
         let pid = eetf::Pid::new(local_node.name.to_string(), 0, 0, creation.get());
         let msg = erl_dist::message::Message::reg_send(
             pid,
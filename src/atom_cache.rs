@@ -0,0 +1,276 @@
+use crate::etf::{self, AtomBytes, EtfError, VERSION_MAGIC};
+use crate::io::ByteReader;
+use eetf::{Atom, DecodeError};
+
+const SEGMENT_SIZE: usize = 256;
+const CACHE_SIZE: usize = 8 * SEGMENT_SIZE;
+const MAX_REFS: usize = 255;
+
+const NEW_CACHE_ENTRY_FLAG: u8 = 0b1000;
+const SEGMENT_INDEX_MASK: u8 = 0b0111;
+const LONG_ATOMS_FLAG: u8 = 0b0001;
+
+/// Atom cache of the receiving side of a connection.
+#[derive(Debug, Default)]
+pub struct RecvAtomCache {
+    entries: Vec<Option<Atom>>,
+}
+
+impl RecvAtomCache {
+    /// Reads the `NumberOfAtomCacheRefs`, `Flags` and `AtomCacheRefs` fields of a distribution header.
+    ///
+    /// Returns the atoms referred by the `ATOM_CACHE_REF` tags in the following terms.
+    pub fn read_refs(&mut self, reader: &mut ByteReader) -> Result<Vec<Atom>, EtfError> {
+        let n = usize::from(reader.read_u8()?);
+        if n == 0 {
+            return Ok(Vec::new());
+        }
+        if self.entries.is_empty() {
+            self.entries.resize(CACHE_SIZE, None);
+        }
+
+        let flags = reader.read_bytes(n / 2 + 1)?;
+        let half_byte = |i: usize| (flags[i / 2] >> (4 * (i % 2))) & 0b1111;
+        let long_atoms = half_byte(n) & LONG_ATOMS_FLAG != 0;
+
+        let mut refs = Vec::with_capacity(n);
+        for i in 0..n {
+            let flag = half_byte(i);
+            let segment_index = usize::from(flag & SEGMENT_INDEX_MASK);
+            let index = segment_index * SEGMENT_SIZE + usize::from(reader.read_u8()?);
+            if flag & NEW_CACHE_ENTRY_FLAG != 0 {
+                let len = if long_atoms {
+                    usize::from(reader.read_u16()?)
+                } else {
+                    usize::from(reader.read_u8()?)
+                };
+                let text = std::str::from_utf8(reader.read_bytes(len)?).map_err(|_| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "invalid UTF-8 in atom cache entry",
+                    )
+                })?;
+                let atom = Atom::from(text);
+                self.entries[index] = Some(atom.clone());
+                refs.push(atom);
+            } else {
+                let atom = self.entries[index]
+                    .clone()
+                    .ok_or(EtfError::MissingAtomCacheEntry { index })?;
+                refs.push(atom);
+            }
+        }
+        Ok(refs)
+    }
+}
+
+/// Converts terms following a distribution header into ones that can be decoded by [`eetf`].
+///
+/// `ATOM_CACHE_REF`s are replaced with the atoms in `refs` and [`VERSION_MAGIC`] is prepended to each term.
+/// Terms nested more than `max_depth` levels are rejected.
+pub fn resolve_refs(mut data: &[u8], refs: &[Atom], max_depth: usize) -> Result<Vec<u8>, EtfError> {
+    let mut buf = Vec::with_capacity(data.len() + 2);
+    while !data.is_empty() {
+        buf.push(VERSION_MAGIC);
+        let n = etf::copy_term(data, &mut buf, max_depth, |atom, out| {
+            if let AtomBytes::CacheRef(i) = atom {
+                let atom = refs.get(usize::from(i)).ok_or(DecodeError::OutOfRange {
+                    value: i32::from(i),
+                    range: 0..refs.len() as i32,
+                })?;
+                etf::write_atom_bytes(AtomBytes::Utf8(atom.name.as_bytes()), out);
+            } else {
+                etf::write_atom_bytes(atom, out);
+            }
+            Ok(())
+        })?;
+        data = &data[n..];
+    }
+    Ok(buf)
+}
+
+/// Atom cache of the sending side of a connection.
+#[derive(Debug, Default)]
+pub struct SendAtomCache {
+    entries: Vec<Option<Box<[u8]>>>,
+}
+
+impl SendAtomCache {
+    /// Converts terms prefixed by [`VERSION_MAGIC`] into the atom cache part of a distribution header
+    /// (`NumberOfAtomCacheRefs`, `Flags` and `AtomCacheRefs`) and the terms following the header.
+    ///
+    /// If `use_cache` is `false`, no atom is cached and the header part becomes a single zero byte.
+    pub fn encode(
+        &mut self,
+        mut buf: &[u8],
+        use_cache: bool,
+    ) -> Result<(Vec<u8>, Vec<u8>), EtfError> {
+        if use_cache && self.entries.is_empty() {
+            self.entries.resize(CACHE_SIZE, None);
+        }
+
+        // Each element is a cache index and whether it is a new entry.
+        let mut refs: Vec<(usize, bool)> = Vec::new();
+        let mut data = Vec::with_capacity(buf.len());
+        while !buf.is_empty() {
+            if buf[0] != VERSION_MAGIC {
+                return Err(DecodeError::UnsupportedVersion { version: buf[0] }.into());
+            }
+            let n = etf::copy_term(&buf[1..], &mut data, usize::MAX, |atom, out| {
+                if let (true, AtomBytes::Utf8(text)) = (use_cache, atom)
+                    && let Some(i) = self.lookup(text, &mut refs)
+                {
+                    etf::write_atom_bytes(AtomBytes::CacheRef(i), out);
+                } else {
+                    etf::write_atom_bytes(atom, out);
+                }
+                Ok(())
+            })?;
+            buf = &buf[1 + n..];
+        }
+
+        let mut header = vec![refs.len() as u8];
+        if refs.is_empty() {
+            return Ok((header, data));
+        }
+
+        let long_atoms = refs
+            .iter()
+            .any(|&(index, is_new)| is_new && self.text(index).len() > usize::from(u8::MAX));
+        let mut flags = vec![0; refs.len() / 2 + 1];
+        for (i, &(index, is_new)) in refs.iter().enumerate() {
+            let mut flag = (index / SEGMENT_SIZE) as u8;
+            if is_new {
+                flag |= NEW_CACHE_ENTRY_FLAG;
+            }
+            flags[i / 2] |= flag << (4 * (i % 2));
+        }
+        if long_atoms {
+            flags[refs.len() / 2] |= LONG_ATOMS_FLAG << (4 * (refs.len() % 2));
+        }
+        header.extend_from_slice(&flags);
+
+        for &(index, is_new) in &refs {
+            header.push((index % SEGMENT_SIZE) as u8);
+            if is_new {
+                let text = self.text(index);
+                if long_atoms {
+                    header.extend_from_slice(&(text.len() as u16).to_be_bytes());
+                } else {
+                    header.push(text.len() as u8);
+                }
+                header.extend_from_slice(text);
+            }
+        }
+        Ok((header, data))
+    }
+
+    fn lookup(&mut self, text: &[u8], refs: &mut Vec<(usize, bool)>) -> Option<u8> {
+        let index = cache_index(text);
+        let is_cached = self.entries[index].as_deref() == Some(text);
+        if let Some(i) = refs.iter().position(|&(x, _)| x == index) {
+            // Two different atoms can't share a cache entry in a single message.
+            return is_cached.then_some(i as u8);
+        }
+        if refs.len() == MAX_REFS {
+            return None;
+        }
+        if !is_cached {
+            self.entries[index] = Some(text.into());
+        }
+        refs.push((index, !is_cached));
+        Some((refs.len() - 1) as u8)
+    }
+
+    fn text(&self, index: usize) -> &[u8] {
+        self.entries[index].as_deref().expect("unreachable")
+    }
+}
+
+fn cache_index(text: &[u8]) -> usize {
+    // FNV-1a
+    let mut hash: u32 = 0x811c9dc5;
+    for &b in text {
+        hash ^= u32::from(b);
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    hash as usize % CACHE_SIZE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Message;
+    use crate::term::{Atom, FixInteger, InternalFun, Pid, Term};
+
+    fn roundtrip(
+        send_cache: &mut SendAtomCache,
+        recv_cache: &mut RecvAtomCache,
+        msg: Message,
+    ) -> (Message, usize) {
+        let mut buf = Vec::new();
+        msg.write_into(&mut buf).unwrap();
+        let (header, data) = send_cache.encode(&buf, true).unwrap();
+        let refs = recv_cache.read_refs(&mut ByteReader(&header)).unwrap();
+        let buf = resolve_refs(&data, &refs, usize::MAX).unwrap();
+        let msg = Message::read_from(&mut buf.as_slice()).unwrap();
+        (msg, header.len() + data.len())
+    }
+
+    #[test]
+    fn atom_cache_roundtrip_works() {
+        let mut send_cache = SendAtomCache::default();
+        let mut recv_cache = RecvAtomCache::default();
+
+        let pid = Pid::new("foo@localhost", 1, 2, 3);
+        let fun = InternalFun::New {
+            module: Atom::from("a_module"),
+            arity: 1,
+            pid: pid.clone(),
+            free_vars: vec![Atom::from("a_free_var").into()],
+            index: 0,
+            uniq: [0; 16],
+            old_index: 0,
+            old_uniq: 0,
+        };
+        let payload = Term::from(eetf::Tuple::from(vec![
+            Atom::from("hello").into(),
+            Term::from(fun),
+            FixInteger::from(10).into(),
+        ]));
+        let msg = Message::reg_send(pid, Atom::from("bar"), payload);
+
+        let (received, first_size) = roundtrip(&mut send_cache, &mut recv_cache, msg.clone());
+        assert_eq!(received, msg);
+
+        // Atoms are sent as cache references from now on.
+        let (received, second_size) = roundtrip(&mut send_cache, &mut recv_cache, msg.clone());
+        assert_eq!(received, msg);
+        assert!(second_size < first_size);
+    }
+
+    #[test]
+    fn long_atoms_are_cached() {
+        let mut send_cache = SendAtomCache::default();
+        let mut recv_cache = RecvAtomCache::default();
+
+        let pid = Pid::new("foo@localhost", 1, 2, 3);
+        let long_atom = Atom::from("ä".repeat(200));
+        let msg = Message::send(pid, long_atom.into());
+        for _ in 0..2 {
+            let (received, _) = roundtrip(&mut send_cache, &mut recv_cache, msg.clone());
+            assert_eq!(received, msg);
+        }
+    }
+
+    #[test]
+    fn missing_cache_entry_is_rejected() {
+        let mut recv_cache = RecvAtomCache::default();
+        // One reference to the entry 3 of the segment 0, which has not been cached yet.
+        let header = [1, 0b0000_0000, 3];
+        assert!(matches!(
+            recv_cache.read_refs(&mut ByteReader(&header)),
+            Err(EtfError::MissingAtomCacheEntry { index: 3 })
+        ));
+    }
+}
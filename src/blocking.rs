@@ -0,0 +1,479 @@
+//! Blocking versions of the EPMD client, handshakes and message channels.
+//!
+//! The types in this module work over [`std::io::Read`] and [`std::io::Write`] instead of async I/O,
+//! so they can be used without any async runtime (e.g., in simple command-line tools).
+//! They share the wire encoding with the async versions.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use erl_dist::blocking::EpmdClient;
+//! use erl_dist::epmd::DEFAULT_EPMD_PORT;
+//! use std::net::TcpStream;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let connection = TcpStream::connect(("localhost", DEFAULT_EPMD_PORT))?;
+//! let client = EpmdClient::new(connection);
+//! if let Some(entry) = client.get_node("foo")? {
+//!     println!("Found: {:?}", entry);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use crate::DistributionFlags;
+use crate::connection::ConnectError;
+use crate::epmd::{DEFAULT_EPMD_PORT, EpmdError, NodeEntry};
+use crate::handshake::{CookieProvider, HandshakeError, HandshakeStatus};
+use crate::message::{
+    LazyMessage, Message, RawFrame, RecvError, SendError, TrafficStats, TrafficStatsHandle,
+};
+use crate::node::{Creation, LocalNode, NodeName, PeerNode};
+use futures::executor::block_on;
+use futures::io::AllowStdIo;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+/// Blocking version of [`crate::epmd::EpmdClient`].
+#[derive(Debug)]
+pub struct EpmdClient<T> {
+    inner: crate::epmd::EpmdClient<AllowStdIo<T>>,
+}
+
+impl<T> EpmdClient<T>
+where
+    T: Read + Write + Unpin,
+{
+    /// Makes a new [`EpmdClient`] instance.
+    ///
+    /// `connection` is a connection to communicate with the target EPMD server.
+    pub fn new(connection: T) -> Self {
+        Self {
+            inner: crate::epmd::EpmdClient::new(AllowStdIo::new(connection)),
+        }
+    }
+
+    /// Registers a node in EPMD.
+    ///
+    /// See [`crate::epmd::EpmdClient::register()`] for the details.
+    pub fn register(self, node: NodeEntry) -> Result<(T, Creation), EpmdError> {
+        let (connection, creation) = block_on(self.inner.register(node))?;
+        Ok((connection.into_inner(), creation))
+    }
+
+    /// Gets all registered names from EPMD.
+    pub fn get_names(self) -> Result<Vec<(String, u16)>, EpmdError> {
+        block_on(self.inner.get_names())
+    }
+
+    /// Gets the distribution port (and other information) of the given node.
+    ///
+    /// If the node is not registered, this method returns `None`.
+    pub fn get_node(self, node_name: &str) -> Result<Option<NodeEntry>, EpmdError> {
+        block_on(self.inner.get_node(node_name))
+    }
+
+    /// Kills EPMD.
+    pub fn kill(self) -> Result<String, EpmdError> {
+        block_on(self.inner.kill())
+    }
+
+    /// Dumps all registered nodes.
+    pub fn dump(self) -> Result<String, EpmdError> {
+        block_on(self.inner.dump())
+    }
+}
+
+/// Blocking version of [`crate::handshake::ClientSideHandshake`].
+#[derive(Debug)]
+pub struct ClientSideHandshake<T> {
+    inner: crate::handshake::ClientSideHandshake<AllowStdIo<T>>,
+}
+
+impl<T> ClientSideHandshake<T>
+where
+    T: Read + Write + Unpin,
+{
+    /// Makes a new [`ClientSideHandshake`] instance.
+    pub fn new(connection: T, local_node: LocalNode, cookie: &str) -> Self {
+        Self {
+            inner: crate::handshake::ClientSideHandshake::new(
+                AllowStdIo::new(connection),
+                local_node,
+                cookie,
+            ),
+        }
+    }
+
+    /// Makes a new [`ClientSideHandshake`] instance that asks `cookies` for the cookie of the peer node.
+    ///
+    /// See [`crate::handshake::ClientSideHandshake::with_cookie_provider()`] for the details.
+    pub fn with_cookie_provider<C>(connection: T, local_node: LocalNode, cookies: C) -> Self
+    where
+        C: CookieProvider + 'static,
+    {
+        Self {
+            inner: crate::handshake::ClientSideHandshake::with_cookie_provider(
+                AllowStdIo::new(connection),
+                local_node,
+                cookies,
+            ),
+        }
+    }
+
+    /// Sets the maximum number of bytes of a handshake message sent by the peer.
+    ///
+    /// See [`crate::handshake::ClientSideHandshake::set_max_message_size()`] for the details.
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.inner.set_max_message_size(size);
+    }
+
+    /// Executes the first part of the handshake protocol.
+    ///
+    /// See [`crate::handshake::ClientSideHandshake::execute_send_name()`] for the details.
+    pub fn execute_send_name(
+        &mut self,
+        protocol_version: u16,
+    ) -> Result<HandshakeStatus, HandshakeError> {
+        block_on(self.inner.execute_send_name(protocol_version))
+    }
+
+    /// Executes the rest part of the handshake protocol.
+    ///
+    /// See [`crate::handshake::ClientSideHandshake::execute_rest()`] for the details.
+    pub fn execute_rest(self, do_continue: bool) -> Result<(T, PeerNode), HandshakeError> {
+        let (connection, peer_node) = block_on(self.inner.execute_rest(do_continue))?;
+        Ok((connection.into_inner(), peer_node))
+    }
+}
+
+/// Blocking version of [`crate::handshake::ServerSideHandshake`].
+#[derive(Debug)]
+pub struct ServerSideHandshake<T> {
+    inner: crate::handshake::ServerSideHandshake<AllowStdIo<T>>,
+}
+
+impl<T> ServerSideHandshake<T>
+where
+    T: Read + Write + Unpin,
+{
+    /// Makes a new [`ServerSideHandshake`] instance.
+    pub fn new(connection: T, local_node: LocalNode, cookie: &str) -> Self {
+        Self {
+            inner: crate::handshake::ServerSideHandshake::new(
+                AllowStdIo::new(connection),
+                local_node,
+                cookie,
+            ),
+        }
+    }
+
+    /// Makes a new [`ServerSideHandshake`] instance that asks `cookies` for the cookie of the peer node.
+    ///
+    /// See [`crate::handshake::ServerSideHandshake::with_cookie_provider()`] for the details.
+    pub fn with_cookie_provider<C>(connection: T, local_node: LocalNode, cookies: C) -> Self
+    where
+        C: CookieProvider + 'static,
+    {
+        Self {
+            inner: crate::handshake::ServerSideHandshake::with_cookie_provider(
+                AllowStdIo::new(connection),
+                local_node,
+                cookies,
+            ),
+        }
+    }
+
+    /// Sets the maximum number of bytes of a handshake message sent by the peer.
+    ///
+    /// See [`crate::handshake::ServerSideHandshake::set_max_message_size()`] for the details.
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.inner.set_max_message_size(size);
+    }
+
+    /// Executes the first part of the handshake protocol.
+    ///
+    /// See [`crate::handshake::ServerSideHandshake::execute_recv_name()`] for the details.
+    pub fn execute_recv_name(&mut self) -> Result<Option<NodeName>, HandshakeError> {
+        block_on(self.inner.execute_recv_name())
+    }
+
+    /// Executes the rest part of the handshake protocol.
+    ///
+    /// See [`crate::handshake::ServerSideHandshake::execute_rest()`] for the details.
+    pub fn execute_rest(self, status: HandshakeStatus) -> Result<(T, PeerNode), HandshakeError> {
+        let (connection, peer_node) = block_on(self.inner.execute_rest(status))?;
+        Ok((connection.into_inner(), peer_node))
+    }
+}
+
+/// Blocking version of [`crate::connection::connect()`] that uses [`std::net::TcpStream`].
+pub fn connect(
+    local_node: LocalNode,
+    peer_name: &NodeName,
+    cookie: &str,
+) -> Result<(Sender<TcpStream>, Receiver<TcpStream>, PeerNode), ConnectError> {
+    let host = peer_name.host();
+    let connection =
+        TcpStream::connect((host, DEFAULT_EPMD_PORT)).map_err(ConnectError::EpmdConnect)?;
+    let entry = EpmdClient::new(connection)
+        .get_node(peer_name.name())?
+        .ok_or_else(|| ConnectError::NodeNotFound {
+            peer_name: peer_name.clone(),
+        })?;
+    let version = crate::connection::select_version(&entry)?;
+
+    let connection = TcpStream::connect((host, entry.port)).map_err(ConnectError::Connect)?;
+    let flags = local_node.flags;
+    let mut handshake = ClientSideHandshake::new(connection, local_node, cookie);
+    handshake.execute_send_name(version)?;
+    let (connection, peer_node) = handshake.execute_rest(true)?;
+    let reader = connection.try_clone().map_err(ConnectError::Connect)?;
+    let (tx, rx) = channel_from_halves(reader, connection, flags & peer_node.flags);
+    Ok((tx, rx, peer_node))
+}
+
+/// Makes a blocking channel to send/receive messages to/from a connected node.
+///
+/// As [`std::net::TcpStream`] can't be cloned, `&TcpStream` can be used as `connection` instead.
+/// See [`crate::message::channel()`] for the details of the arguments.
+pub fn channel<T>(connection: T, flags: DistributionFlags) -> (Sender<T>, Receiver<T>)
+where
+    T: Read + Write + Unpin + Clone,
+{
+    channel_from_halves(connection.clone(), connection, flags)
+}
+
+/// Makes a blocking channel from the read and write halves of a connection.
+///
+/// See [`crate::message::channel()`] for the details of the arguments.
+pub fn channel_from_halves<R, W>(
+    reader: R,
+    writer: W,
+    flags: DistributionFlags,
+) -> (Sender<W>, Receiver<R>)
+where
+    R: Read + Unpin,
+    W: Write + Unpin,
+{
+    let (tx, rx) = crate::message::channel_from_halves(
+        AllowStdIo::new(reader),
+        AllowStdIo::new(writer),
+        flags,
+    );
+    (Sender { inner: tx }, Receiver { inner: rx })
+}
+
+/// Blocking version of [`crate::message::Sender`].
+#[derive(Debug)]
+pub struct Sender<T> {
+    inner: crate::message::Sender<AllowStdIo<T>>,
+}
+
+impl<T> Sender<T>
+where
+    T: Write + Unpin,
+{
+    /// Sets the maximum number of message bytes carried by a single fragment.
+    ///
+    /// See [`crate::message::Sender::set_fragment_size()`] for the details.
+    pub fn set_fragment_size(&mut self, size: usize) {
+        self.inner.set_fragment_size(size);
+    }
+
+    /// Sets whether atoms are sent as references to the atom cache of the connection.
+    ///
+    /// See [`crate::message::Sender::set_atom_cache_enabled()`] for the details.
+    pub fn set_atom_cache_enabled(&mut self, enabled: bool) {
+        self.inner.set_atom_cache_enabled(enabled);
+    }
+
+    /// Sends a message.
+    ///
+    /// See [`crate::message::Sender::send()`] for the details.
+    pub fn send(&mut self, message: Message) -> Result<(), SendError> {
+        block_on(self.inner.send(message))
+    }
+
+    /// Sends messages at once.
+    ///
+    /// See [`crate::message::Sender::send_all()`] for the details.
+    pub fn send_all<I>(&mut self, messages: I) -> Result<(), SendError>
+    where
+        I: IntoIterator<Item = Message>,
+    {
+        block_on(self.inner.send_all(messages))
+    }
+
+    /// Sends a message without encoding it.
+    ///
+    /// See [`crate::message::Sender::send_raw()`] for the details.
+    pub fn send_raw(&mut self, frame: RawFrame) -> Result<(), SendError> {
+        block_on(self.inner.send_raw(frame))
+    }
+
+    /// Encodes a message into the internal buffer without sending it.
+    ///
+    /// The buffered messages are sent when [`Sender::flush()`] is called.
+    pub fn feed(&mut self, message: Message) -> Result<(), SendError> {
+        self.inner.feed(message)
+    }
+
+    /// Writes a message into the internal buffer without encoding or sending it.
+    ///
+    /// The buffered messages are sent when [`Sender::flush()`] is called.
+    pub fn feed_raw(&mut self, frame: RawFrame) -> Result<(), SendError> {
+        self.inner.feed_raw(frame)
+    }
+
+    /// Writes the buffered messages to the connection and flushes it.
+    pub fn flush(&mut self) -> Result<(), SendError> {
+        block_on(self.inner.flush())
+    }
+
+    /// Returns the traffic statistics of this sender.
+    ///
+    /// See [`crate::message::Sender::stats()`] for the details.
+    pub fn stats(&self) -> TrafficStats {
+        self.inner.stats()
+    }
+
+    /// Returns a shared handle to the traffic statistics of this sender.
+    pub fn stats_handle(&self) -> TrafficStatsHandle {
+        self.inner.stats_handle()
+    }
+}
+
+/// Blocking version of [`crate::message::Receiver`].
+///
+/// [`Receiver`] also implements [`Iterator`] that yields the results of [`Receiver::recv()`].
+/// The iteration ends when the connection is closed by the peer.
+#[derive(Debug)]
+pub struct Receiver<T> {
+    inner: crate::message::Receiver<AllowStdIo<T>>,
+}
+
+impl<T> Receiver<T>
+where
+    T: Read + Unpin,
+{
+    /// Sets the maximum number of bytes of a frame.
+    ///
+    /// See [`crate::message::Receiver::set_max_frame_size()`] for the details.
+    pub fn set_max_frame_size(&mut self, size: usize) {
+        self.inner.set_max_frame_size(size);
+    }
+
+    /// Sets the maximum nesting depth of terms in a message.
+    ///
+    /// See [`crate::message::Receiver::set_max_term_depth()`] for the details.
+    pub fn set_max_term_depth(&mut self, depth: usize) {
+        self.inner.set_max_term_depth(depth);
+    }
+
+    /// Sets the maximum number of fragmented messages that are reassembled at the same time.
+    ///
+    /// See [`crate::message::Receiver::set_max_fragmented_messages()`] for the details.
+    pub fn set_max_fragmented_messages(&mut self, n: usize) {
+        self.inner.set_max_fragmented_messages(n);
+    }
+
+    /// Sets the maximum total number of bytes of the fragments buffered for reassembling messages.
+    ///
+    /// See [`crate::message::Receiver::set_max_fragment_buffer_size()`] for the details.
+    pub fn set_max_fragment_buffer_size(&mut self, size: usize) {
+        self.inner.set_max_fragment_buffer_size(size);
+    }
+
+    /// Receives a message.
+    ///
+    /// See [`crate::message::Receiver::recv()`] for the details.
+    pub fn recv(&mut self) -> Result<Message, RecvError> {
+        block_on(self.inner.recv())
+    }
+
+    /// Receives a message without decoding it.
+    ///
+    /// See [`crate::message::Receiver::recv_raw()`] for the details.
+    pub fn recv_raw(&mut self) -> Result<RawFrame, RecvError> {
+        block_on(self.inner.recv_raw())
+    }
+
+    /// Receives a message decoding only its control message.
+    ///
+    /// See [`crate::message::Receiver::recv_lazy()`] for the details.
+    pub fn recv_lazy(&mut self) -> Result<LazyMessage, RecvError> {
+        block_on(self.inner.recv_lazy())
+    }
+
+    /// Returns the traffic statistics of this receiver.
+    ///
+    /// See [`crate::message::Receiver::stats()`] for the details.
+    pub fn stats(&self) -> TrafficStats {
+        self.inner.stats()
+    }
+
+    /// Returns a shared handle to the traffic statistics of this receiver.
+    pub fn stats_handle(&self) -> TrafficStatsHandle {
+        self.inner.stats_handle()
+    }
+}
+
+impl<T> Iterator for Receiver<T>
+where
+    T: Read + Unpin,
+{
+    type Item = Result<Message, RecvError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.recv() {
+            Err(RecvError::Closed) => None,
+            result => Some(result),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::term::{Atom, Pid};
+    use std::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn blocking_connections_work() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let pid = Pid::new("foo@localhost", 0, 0, 0);
+        let msg = Message::send(pid, Atom::from("hello").into());
+
+        let client = std::thread::spawn({
+            let msg = msg.clone();
+            move || {
+                let connection = TcpStream::connect(addr).unwrap();
+                let local_node =
+                    LocalNode::new("foo@localhost".parse().unwrap(), Creation::random());
+                let flags = local_node.flags;
+                let mut handshake =
+                    ClientSideHandshake::new(connection, local_node, crate::tests::COOKIE);
+                handshake
+                    .execute_send_name(crate::LOWEST_DISTRIBUTION_PROTOCOL_VERSION)
+                    .unwrap();
+                let (connection, peer_node) = handshake.execute_rest(true).unwrap();
+                let (mut tx, _) = channel(&connection, flags & peer_node.flags);
+                tx.send(msg).unwrap();
+                tx.send(Message::Tick).unwrap();
+            }
+        });
+
+        let (connection, _) = listener.accept().unwrap();
+        let local_node = LocalNode::new("bar@localhost".parse().unwrap(), Creation::random());
+        let flags = local_node.flags;
+        let mut handshake = ServerSideHandshake::new(connection, local_node, crate::tests::COOKIE);
+        assert!(handshake.execute_recv_name().unwrap().is_some());
+        let (connection, peer_node) = handshake.execute_rest(HandshakeStatus::Ok).unwrap();
+        client.join().unwrap();
+
+        let (_, rx) = channel(&connection, flags & peer_node.flags);
+        let received = rx.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(received, [msg, Message::Tick]);
+    }
+}
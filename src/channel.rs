@@ -1,119 +1,977 @@
 use crate::DistributionFlags;
+use crate::atom_cache::{self, RecvAtomCache, SendAtomCache};
+use crate::etf;
 #[cfg(doc)]
 use crate::handshake;
-use crate::io::Connection;
+use crate::io::{ByteReader, Connection};
+#[cfg(doc)]
+use crate::message::Keepalive;
+use crate::message::LazyMessage;
 use crate::message::Message;
-use futures::io::{AsyncRead, AsyncWrite};
+use crate::raw_frame::{self, RawFrame};
+use crate::stats::{TrafficStats, TrafficStatsHandle};
+use futures::io::{AsyncRead, AsyncReadExt as _, AsyncWrite, ReadHalf, WriteHalf};
+use std::collections::{HashMap, VecDeque};
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+use std::time::Instant;
 
 /// Makes a channel to send/received messages to/from a connected node.
 ///
 /// Please ensure that the [`handshake`] has been completed using the `connection` before creating a channel.
+/// If `connection` can't be cloned, please use [`split_channel()`] or [`channel_from_halves()`] instead.
 ///
 /// `flags` should be an intersection of distribution flags of both nodes.
-/// Note that the current implementation doesn't consider the distribution flags.
+/// [`Sender::send()`] converts messages that the peer doesn't support into their fallback messages
+/// (e.g., [`Message::PayloadExit`] into [`Message::Exit`] if [`DistributionFlags::EXIT_PAYLOAD`] is missing),
+/// or returns [`SendError::UnsupportedByPeer`] if there is no such fallback.
 ///
 /// Note that, to keep the connection established, you need to send `Message::Tick` periodically.
+/// [`Keepalive`] can be used to do that automatically.
 /// Please see [the official `net_ticktime` doc](https://www.erlang.org/doc/man/kernel_app.html#net_ticktime) for more details.
 pub fn channel<T>(connection: T, flags: DistributionFlags) -> (Sender<T>, Receiver<T>)
 where
     T: AsyncRead + AsyncWrite + Unpin + Clone,
 {
-    let _ = flags;
-    (Sender::new(connection.clone()), Receiver::new(connection))
+    (
+        Sender::new(connection.clone(), flags),
+        Receiver::new(connection),
+    )
+}
+
+/// Makes a channel by splitting a connection that can't be cloned.
+///
+/// This is useful for connections such as TLS streams.
+/// See [`channel()`] for the details of the arguments.
+pub fn split_channel<T>(
+    connection: T,
+    flags: DistributionFlags,
+) -> (Sender<WriteHalf<T>>, Receiver<ReadHalf<T>>)
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let (reader, writer) = connection.split();
+    channel_from_halves(reader, writer, flags)
+}
+
+/// Makes a channel from the read and write halves of a connection.
+///
+/// See [`channel()`] for the details of the arguments.
+pub fn channel_from_halves<R, W>(
+    reader: R,
+    writer: W,
+    flags: DistributionFlags,
+) -> (Sender<W>, Receiver<R>)
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    (Sender::new(writer, flags), Receiver::new(reader))
 }
 
 const TYPE_TAG: u8 = 112;
+const DIST_HEADER: u8 = 68;
+const DIST_FRAG_HEADER: u8 = 69;
+const DIST_FRAG_CONT: u8 = 70;
+
+/// Default value of [`Sender::set_fragment_size()`].
+pub const DEFAULT_FRAGMENT_SIZE: usize = 64 * 1024;
+
+/// Default value of [`Receiver::set_max_frame_size()`].
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 128 * 1024 * 1024;
+
+/// Default value of [`Receiver::set_max_term_depth()`].
+pub const DEFAULT_MAX_TERM_DEPTH: usize = 1024;
+
+/// Default value of [`Receiver::set_max_fragmented_messages()`].
+pub const DEFAULT_MAX_FRAGMENTED_MESSAGES: usize = 64;
+
+/// Default value of [`Receiver::set_max_fragment_buffer_size()`].
+pub const DEFAULT_MAX_FRAGMENT_BUFFER_SIZE: usize = DEFAULT_MAX_FRAME_SIZE;
+
+// If this many bytes are buffered, `Sink::poll_ready()` flushes them before accepting more messages.
+const SINK_BACKPRESSURE_SIZE: usize = 64 * 1024;
+
+// Frames are read in chunks of this size so that a bogus frame size can't cause a huge allocation.
+const READ_CHUNK_SIZE: usize = 64 * 1024;
 
 /// Sender of a message channel.
+///
+/// [`Sender`] also implements [`futures::Sink<Message>`] whose [`Sink::start_send()`](futures::Sink::start_send)
+/// behaves like [`Sender::feed()`].
 #[derive(Debug)]
 pub struct Sender<T> {
     connection: Connection<T>,
+    encoder: FrameEncoder,
+    last_sent: Instant,
 }
 
 impl<T> Sender<T>
 where
-    T: AsyncRead + AsyncWrite + Unpin,
+    T: AsyncWrite + Unpin,
 {
-    fn new(connection: T) -> Self {
+    fn new(connection: T, flags: DistributionFlags) -> Self {
         Self {
             connection: Connection::new(connection),
+            encoder: FrameEncoder::new(flags),
+            last_sent: Instant::now(),
         }
     }
 
+    /// Sets the maximum number of message bytes carried by a single fragment.
+    ///
+    /// If [`DistributionFlags::FRAGMENTS`] has been negotiated, messages larger than this size
+    /// are split into multiple fragments. Otherwise, this value is ignored.
+    ///
+    /// The first fragment is encoded immediately, and the rest are encoded one at a time
+    /// as the preceding bytes are written.
+    /// Ticks fed in the meantime are written between the fragments, so a large message doesn't
+    /// make the peer time out, while the other messages are written in the order they were fed.
+    ///
+    /// The default value is [`DEFAULT_FRAGMENT_SIZE`] and values smaller than `1` are treated as `1`.
+    pub fn set_fragment_size(&mut self, size: usize) {
+        self.encoder.set_fragment_size(size);
+    }
+
+    /// Sets whether atoms are sent as references to the atom cache of the connection.
+    ///
+    /// If [`DistributionFlags::DIST_HDR_ATOM_CACHE`] has been negotiated, atoms that have been sent once
+    /// are replaced with short cache references in the subsequent messages.
+    /// Otherwise, this value is ignored.
+    ///
+    /// The default value is `true`.
+    pub fn set_atom_cache_enabled(&mut self, enabled: bool) {
+        self.encoder.set_atom_cache_enabled(enabled);
+    }
+
     /// Sends a message.
+    ///
+    /// If the peer doesn't support `message`, its fallback message is sent instead if exists.
+    /// Otherwise, [`SendError::UnsupportedByPeer`] is returned.
+    ///
+    /// This is equivalent to calling [`Sender::feed()`] and then [`Sender::flush()`].
     pub async fn send(&mut self, message: Message) -> Result<(), SendError> {
+        self.feed(message)?;
+        self.flush().await
+    }
+
+    /// Sends messages at once.
+    ///
+    /// The messages are encoded into the internal buffer and then written with a single flush.
+    /// If an error occurs while encoding a message, the preceding messages are kept in the buffer.
+    pub async fn send_all<I>(&mut self, messages: I) -> Result<(), SendError>
+    where
+        I: IntoIterator<Item = Message>,
+    {
+        for message in messages {
+            self.feed(message)?;
+        }
+        self.flush().await
+    }
+
+    /// Encodes a message into the internal buffer without sending it.
+    ///
+    /// The buffered messages are sent when [`Sender::flush()`] is called.
+    pub fn feed(&mut self, message: Message) -> Result<(), SendError> {
+        self.encoder.encode(message)
+    }
+
+    /// Sends a message without encoding it.
+    ///
+    /// Note that, unlike [`Sender::send()`], the message is sent as is even if the peer doesn't support it.
+    pub async fn send_raw(&mut self, frame: RawFrame) -> Result<(), SendError> {
+        self.feed_raw(frame)?;
+        self.flush().await
+    }
+
+    /// Writes a message into the internal buffer without encoding or sending it.
+    ///
+    /// The buffered messages are sent when [`Sender::flush()`] is called.
+    pub fn feed_raw(&mut self, frame: RawFrame) -> Result<(), SendError> {
+        self.encoder.encode_raw(frame)
+    }
+
+    /// Writes the buffered messages to the connection and flushes it.
+    ///
+    /// If this future is dropped before completion, the remaining bytes are written by the next call.
+    pub async fn flush(&mut self) -> Result<(), SendError> {
+        futures::future::poll_fn(|cx| self.poll_flush_buf(cx)).await
+    }
+
+    pub(crate) fn poll_flush_buf(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), SendError>> {
+        while !self.encoder.output().is_empty() {
+            let n = ready!(self.connection.poll_write(cx, self.encoder.output()))?;
+            if n == 0 {
+                return Poll::Ready(Err(
+                    std::io::Error::from(std::io::ErrorKind::WriteZero).into()
+                ));
+            }
+            self.encoder.consume_output(n);
+            self.last_sent = Instant::now();
+        }
+        ready!(self.connection.poll_flush(cx))?;
+        Poll::Ready(Ok(()))
+    }
+
+    /// Returns the traffic statistics of this sender.
+    pub fn stats(&self) -> TrafficStats {
+        self.encoder.stats()
+    }
+
+    /// Returns a shared handle to the traffic statistics of this sender.
+    pub fn stats_handle(&self) -> TrafficStatsHandle {
+        self.encoder.stats_handle()
+    }
+
+    pub(crate) fn last_sent(&self) -> Instant {
+        self.last_sent
+    }
+
+    pub(crate) fn flags(&self) -> DistributionFlags {
+        self.encoder.flags
+    }
+
+    pub(crate) fn buffered_len(&self) -> usize {
+        self.encoder.buffered_len()
+    }
+}
+
+impl<T> futures::Sink<Message> for Sender<T>
+where
+    T: AsyncWrite + Unpin,
+{
+    type Error = SendError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        if this.buffered_len() >= SINK_BACKPRESSURE_SIZE {
+            ready!(this.poll_flush_buf(cx))?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Message) -> Result<(), Self::Error> {
+        self.get_mut().feed(item)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_flush_buf(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_flush_buf(cx))?;
+        ready!(this.connection.poll_close(cx))?;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Sans-IO encoder of distribution frames.
+///
+/// This encodes messages into the bytes to be written to a connection in the same way as [`Sender`]
+/// (which is a thin driver that writes the output of this encoder to an async connection).
+/// It can be used to embed the distribution protocol in your own event loop.
+#[derive(Debug)]
+pub struct FrameEncoder {
+    flags: DistributionFlags,
+    fragment_size: usize,
+    next_sequence_id: u64,
+    atom_cache: SendAtomCache,
+    atom_cache_enabled: bool,
+    buf: Vec<u8>,
+    consumed: usize,
+    encode_buf: Vec<u8>,
+    queue: VecDeque<QueuedMessage>,
+    stats: TrafficStatsHandle,
+}
+
+// Message waiting for the remaining fragments of a preceding message to be written.
+#[derive(Debug)]
+enum QueuedMessage {
+    Frame(Vec<u8>),
+    Fragments(FragmentingMessage),
+}
+
+impl QueuedMessage {
+    fn len(&self) -> usize {
+        match self {
+            Self::Frame(frame) => frame.len(),
+            Self::Fragments(message) => message.data.len() - message.offset,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct FragmentingMessage {
+    sequence_id: u64,
+    next_fragment_id: u64,
+    refs: Vec<u8>,
+    data: Vec<u8>,
+    offset: usize,
+}
+
+impl FrameEncoder {
+    /// Makes a new [`FrameEncoder`] instance.
+    ///
+    /// `flags` should be an intersection of distribution flags of both nodes.
+    pub fn new(flags: DistributionFlags) -> Self {
+        Self {
+            flags,
+            fragment_size: DEFAULT_FRAGMENT_SIZE,
+            next_sequence_id: 0,
+            atom_cache: SendAtomCache::default(),
+            atom_cache_enabled: true,
+            buf: Vec::new(),
+            consumed: 0,
+            encode_buf: Vec::new(),
+            queue: VecDeque::new(),
+            stats: TrafficStatsHandle::default(),
+        }
+    }
+
+    /// Sets the maximum number of message bytes carried by a single fragment.
+    ///
+    /// See [`Sender::set_fragment_size()`] for the details.
+    pub fn set_fragment_size(&mut self, size: usize) {
+        self.fragment_size = size.max(1);
+    }
+
+    /// Sets whether atoms are sent as references to the atom cache of the connection.
+    ///
+    /// See [`Sender::set_atom_cache_enabled()`] for the details.
+    pub fn set_atom_cache_enabled(&mut self, enabled: bool) {
+        self.atom_cache_enabled = enabled;
+    }
+
+    /// Encodes a message and appends it to the output.
+    ///
+    /// See [`Sender::send()`] for how messages that the peer doesn't support are handled.
+    pub fn encode(&mut self, message: Message) -> Result<(), SendError> {
         if matches!(message, Message::Tick) {
-            self.connection.write_u32(0).await?;
-        } else {
-            let mut buf = Vec::new();
-            message.write_into(&mut buf)?;
+            self.encode_tick();
+            return Ok(());
+        }
 
-            self.connection.write_u32(1 + buf.len() as u32).await?;
-            self.connection.write_u8(TYPE_TAG).await?;
-            self.connection.write_all(&buf).await?;
-            self.connection.flush().await?;
+        let message = message.fit_to_flags(self.flags)?;
+        let op = message.op();
+        let mut encode_buf = std::mem::take(&mut self.encode_buf);
+        encode_buf.clear();
+        let result = message
+            .write_into(&mut encode_buf)
+            .and_then(|()| self.encode_bytes(&encode_buf));
+        self.encode_buf = encode_buf;
+        result?;
+        self.stats.add_message(op);
+        Ok(())
+    }
+
+    /// Appends a message to the output without encoding it.
+    pub fn encode_raw(&mut self, frame: RawFrame) -> Result<(), SendError> {
+        if frame.is_tick() {
+            self.encode_tick();
+            return Ok(());
         }
+        self.encode_bytes(frame.as_bytes())?;
+        self.stats.add_message(frame.peek_op());
         Ok(())
     }
+
+    /// Returns the traffic statistics of this encoder.
+    ///
+    /// The bytes are counted when they are consumed by [`FrameEncoder::consume_output()`].
+    pub fn stats(&self) -> TrafficStats {
+        self.stats.snapshot()
+    }
+
+    /// Returns a shared handle to the traffic statistics of this encoder.
+    pub fn stats_handle(&self) -> TrafficStatsHandle {
+        self.stats.clone()
+    }
+
+    /// Returns the bytes to be written to the connection.
+    ///
+    /// Only the next fragment of a fragmented message is included, and the subsequent fragments
+    /// and the messages following them are appended when the output has been consumed.
+    /// So the output is empty only if all the messages have been written.
+    pub fn output(&self) -> &[u8] {
+        &self.buf[self.consumed..]
+    }
+
+    /// Removes the first `n` bytes of the output (e.g., after they have been written to the connection).
+    pub fn consume_output(&mut self, n: usize) {
+        let n = n.min(self.buf.len() - self.consumed);
+        self.consumed += n;
+        self.stats.add_bytes(n);
+        if self.consumed == self.buf.len() {
+            self.buf.clear();
+            self.consumed = 0;
+            self.encode_queued();
+        }
+    }
+
+    pub(crate) fn buffered_len(&self) -> usize {
+        let queued = self.queue.iter().map(QueuedMessage::len).sum::<usize>();
+        self.output().len() + queued
+    }
+
+    fn encode_tick(&mut self) {
+        self.buf.extend_from_slice(&0u32.to_be_bytes());
+        self.stats.add_frame(true);
+    }
+
+    fn encode_bytes(&mut self, bytes: &[u8]) -> Result<(), SendError> {
+        // Distribution headers (and thus fragments) are only used if the atom cache is negotiated.
+        if !self.flags.contains(DistributionFlags::DIST_HDR_ATOM_CACHE) {
+            let mut frame = Vec::with_capacity(4 + 1 + bytes.len());
+            frame.extend_from_slice(&(1 + bytes.len() as u32).to_be_bytes());
+            frame.push(TYPE_TAG);
+            frame.extend_from_slice(bytes);
+            self.push_frame(frame);
+            return Ok(());
+        }
+
+        // Terms following a distribution header don't have the version magic.
+        let (refs, data) = self
+            .atom_cache
+            .encode(bytes, self.atom_cache_enabled)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        if self.flags.contains(DistributionFlags::FRAGMENTS) && data.len() > self.fragment_size {
+            let sequence_id = self.next_sequence_id;
+            self.next_sequence_id = self.next_sequence_id.wrapping_add(1);
+            let mut message = FragmentingMessage {
+                sequence_id,
+                next_fragment_id: data.len().div_ceil(self.fragment_size) as u64,
+                refs,
+                data,
+                offset: 0,
+            };
+            // As with the other messages, the first fragment is queued only if preceding messages are.
+            if self.queue.is_empty() {
+                self.encode_next_fragment(&mut message);
+            }
+            self.queue.push_back(QueuedMessage::Fragments(message));
+            return Ok(());
+        }
+
+        let mut frame = Vec::with_capacity(4 + 2 + refs.len() + data.len());
+        frame.extend_from_slice(&(2 + refs.len() as u32 + data.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&[etf::VERSION_MAGIC, DIST_HEADER]);
+        frame.extend_from_slice(&refs);
+        frame.extend_from_slice(&data);
+        self.push_frame(frame);
+        Ok(())
+    }
+
+    // Messages following a fragmented message are queued to keep the order of messages.
+    fn push_frame(&mut self, frame: Vec<u8>) {
+        if self.queue.is_empty() {
+            self.buf.extend_from_slice(&frame);
+            self.stats.add_frame(false);
+        } else {
+            self.queue.push_back(QueuedMessage::Frame(frame));
+        }
+    }
+
+    // Appends the queued messages to the output until a fragment is appended.
+    fn encode_queued(&mut self) {
+        while let Some(queued) = self.queue.pop_front() {
+            match queued {
+                QueuedMessage::Frame(frame) => {
+                    self.buf.extend_from_slice(&frame);
+                    self.stats.add_frame(false);
+                }
+                QueuedMessage::Fragments(mut message) => {
+                    self.encode_next_fragment(&mut message);
+                    if message.next_fragment_id > 0 {
+                        self.queue.push_front(QueuedMessage::Fragments(message));
+                    }
+                    return;
+                }
+            }
+        }
+    }
+
+    fn encode_next_fragment(&mut self, message: &mut FragmentingMessage) {
+        let end = (message.offset + self.fragment_size).min(message.data.len());
+        let chunk = &message.data[message.offset..end];
+        if message.offset == 0 {
+            // VERSION_MAGIC, DIST_FRAG_HEADER, SequenceId, FragmentId, AtomCacheRefs
+            let size = 2 + 8 + 8 + message.refs.len() as u32 + chunk.len() as u32;
+            self.buf.extend_from_slice(&size.to_be_bytes());
+            self.buf
+                .extend_from_slice(&[etf::VERSION_MAGIC, DIST_FRAG_HEADER]);
+            self.buf
+                .extend_from_slice(&message.sequence_id.to_be_bytes());
+            self.buf
+                .extend_from_slice(&message.next_fragment_id.to_be_bytes());
+            self.buf.extend_from_slice(&message.refs);
+        } else {
+            // VERSION_MAGIC, DIST_FRAG_CONT, SequenceId, FragmentId
+            let size = 2 + 8 + 8 + chunk.len() as u32;
+            self.buf.extend_from_slice(&size.to_be_bytes());
+            self.buf
+                .extend_from_slice(&[etf::VERSION_MAGIC, DIST_FRAG_CONT]);
+            self.buf
+                .extend_from_slice(&message.sequence_id.to_be_bytes());
+            self.buf
+                .extend_from_slice(&message.next_fragment_id.to_be_bytes());
+        }
+        self.buf.extend_from_slice(chunk);
+        self.stats.add_frame(false);
+
+        message.offset = end;
+        message.next_fragment_id -= 1;
+    }
 }
 
 /// Receiver of a message channel.
+///
+/// [`Receiver`] also implements [`futures::Stream`] that yields the results of [`Receiver::recv()`].
+/// The stream ends when the connection is closed by the peer.
 #[derive(Debug)]
 pub struct Receiver<T> {
     connection: Connection<T>,
+    decoder: FrameDecoder,
+    read_buf: Vec<u8>,
+    last_received: Instant,
 }
 
 impl<T> Receiver<T>
 where
-    T: AsyncRead + AsyncWrite + Unpin,
+    T: AsyncRead + Unpin,
 {
     fn new(connection: T) -> Self {
         Self {
             connection: Connection::new(connection),
+            decoder: FrameDecoder::new(),
+            read_buf: Vec::new(),
+            last_received: Instant::now(),
         }
     }
 
+    /// Sets the maximum number of bytes of a frame.
+    ///
+    /// Frames larger than this size are rejected with [`RecvError::FrameTooLarge`] without being read.
+    /// This limit also applies to the total size of a fragmented message.
+    ///
+    /// The default value is [`DEFAULT_MAX_FRAME_SIZE`].
+    pub fn set_max_frame_size(&mut self, size: usize) {
+        self.decoder.set_max_frame_size(size);
+    }
+
+    /// Sets the maximum nesting depth of terms in a message.
+    ///
+    /// Messages containing deeper terms are rejected with [`RecvError::TermTooDeep`].
+    ///
+    /// The default value is [`DEFAULT_MAX_TERM_DEPTH`].
+    pub fn set_max_term_depth(&mut self, depth: usize) {
+        self.decoder.set_max_term_depth(depth);
+    }
+
+    /// Sets the maximum number of fragmented messages that are reassembled at the same time.
+    ///
+    /// If the peer starts more fragmented messages than this, [`RecvError::TooManyFragments`] is returned.
+    ///
+    /// The default value is [`DEFAULT_MAX_FRAGMENTED_MESSAGES`].
+    pub fn set_max_fragmented_messages(&mut self, n: usize) {
+        self.decoder.set_max_fragmented_messages(n);
+    }
+
+    /// Sets the maximum total number of bytes of the fragments buffered for reassembling messages.
+    ///
+    /// If the buffered fragments exceed this size, [`RecvError::TooManyFragments`] is returned.
+    ///
+    /// The default value is [`DEFAULT_MAX_FRAGMENT_BUFFER_SIZE`].
+    pub fn set_max_fragment_buffer_size(&mut self, size: usize) {
+        self.decoder.set_max_fragment_buffer_size(size);
+    }
+
     /// Receives a message.
+    ///
+    /// Fragmented messages are reassembled transparently.
+    /// Ticks are returned as soon as they arrive, even if they are interleaved with fragments.
+    ///
+    /// This method is cancel-safe.
+    /// If the returned future is dropped before completion (e.g., it loses a `select!` race against a timer),
+    /// the partially read frame is kept in the receiver and the next call resumes reading it.
     pub async fn recv(&mut self) -> Result<Message, RecvError> {
-        let size = match self.connection.read_u32().await {
-            Ok(size) => size as usize,
-            Err(e) => {
-                if e.kind() == std::io::ErrorKind::UnexpectedEof {
-                    return Err(RecvError::Closed);
-                } else {
-                    return Err(e.into());
+        self.recv_raw().await?.decode()
+    }
+
+    /// Receives a message without decoding it.
+    ///
+    /// As with [`Receiver::recv()`], fragmented messages are reassembled transparently.
+    /// Atom cache references are also resolved so that the returned frame can be forwarded to other connections.
+    ///
+    /// This method is cancel-safe in the same way as [`Receiver::recv()`].
+    pub async fn recv_raw(&mut self) -> Result<RawFrame, RecvError> {
+        futures::future::poll_fn(|cx| self.poll_recv_raw(cx)).await
+    }
+
+    /// Receives a message decoding only its control message.
+    ///
+    /// The payload of the returned message is decoded only when [`Payload::decode()`] is called.
+    ///
+    /// This method is cancel-safe in the same way as [`Receiver::recv()`].
+    ///
+    /// [`Payload::decode()`]: crate::message::Payload::decode
+    pub async fn recv_lazy(&mut self) -> Result<LazyMessage, RecvError> {
+        self.recv_raw().await?.decode_lazy()
+    }
+
+    pub(crate) fn poll_recv_raw(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<RawFrame, RecvError>> {
+        loop {
+            if let Some(frame) = self.decoder.decode()? {
+                return Poll::Ready(Ok(frame));
+            }
+
+            if self.read_buf.is_empty() {
+                self.read_buf.resize(READ_CHUNK_SIZE, 0);
+            }
+            let n = ready!(self.connection.poll_read(cx, &mut self.read_buf))?;
+            if n == 0 {
+                if self.decoder.buffered_len() == 0 {
+                    return Poll::Ready(Err(RecvError::Closed));
                 }
+                return Poll::Ready(Err(
+                    std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()
+                ));
             }
-        };
-        if size == 0 {
-            return Ok(Message::Tick);
+            self.decoder.feed(&self.read_buf[..n]);
+            self.last_received = Instant::now();
         }
+    }
 
-        let tag = self.connection.read_u8().await?;
-        if tag != TYPE_TAG {
-            return Err(RecvError::UnexpectedTypeTag { tag });
-        }
+    /// Returns the traffic statistics of this receiver.
+    ///
+    /// [`TrafficStats::last_activity`] tells a silent peer (no ticks are arriving) from
+    /// an idle one (only ticks are arriving).
+    pub fn stats(&self) -> TrafficStats {
+        self.decoder.stats()
+    }
 
-        let mut buf = vec![0; size - 1];
-        self.connection.read_exact(&mut buf).await?;
-        Message::read_from(&mut buf.as_slice())
+    /// Returns a shared handle to the traffic statistics of this receiver.
+    pub fn stats_handle(&self) -> TrafficStatsHandle {
+        self.decoder.stats_handle()
+    }
+
+    pub(crate) fn last_received(&self) -> Instant {
+        self.last_received
     }
 
     /// Receives a message (owned version).
+    #[deprecated(
+        note = "`Receiver::recv()` is cancel-safe, so there is no need to move the receiver into the future"
+    )]
     pub async fn recv_owned(mut self) -> Result<(Message, Self), RecvError> {
         let msg = self.recv().await?;
         Ok((msg, self))
     }
 }
 
+impl<T> futures::Stream for Receiver<T>
+where
+    T: AsyncRead + Unpin,
+{
+    type Item = Result<Message, RecvError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match ready!(self.get_mut().poll_recv_raw(cx)) {
+            Ok(frame) => Poll::Ready(Some(frame.decode())),
+            Err(RecvError::Closed) => Poll::Ready(None),
+            Err(e) => Poll::Ready(Some(Err(e))),
+        }
+    }
+}
+
+/// Sans-IO decoder of distribution frames.
+///
+/// This decodes the bytes read from a connection into messages in the same way as [`Receiver`]
+/// (which is a thin driver that feeds the bytes read from an async connection to this decoder).
+/// It can be used to embed the distribution protocol in your own event loop.
+#[derive(Debug)]
+pub struct FrameDecoder {
+    input: Vec<u8>,
+    consumed: usize,
+    fragments: HashMap<u64, PartialMessage>,
+    fragments_size: usize,
+    atom_cache: RecvAtomCache,
+    max_frame_size: usize,
+    max_term_depth: usize,
+    max_fragmented_messages: usize,
+    max_fragment_buffer_size: usize,
+    stats: TrafficStatsHandle,
+}
+
+#[derive(Debug)]
+struct PartialMessage {
+    next_fragment_id: u64,
+    atoms: Vec<eetf::Atom>,
+    data: Vec<u8>,
+}
+
+impl FrameDecoder {
+    /// Makes a new [`FrameDecoder`] instance.
+    pub fn new() -> Self {
+        Self {
+            input: Vec::new(),
+            consumed: 0,
+            fragments: HashMap::new(),
+            fragments_size: 0,
+            atom_cache: RecvAtomCache::default(),
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            max_term_depth: DEFAULT_MAX_TERM_DEPTH,
+            max_fragmented_messages: DEFAULT_MAX_FRAGMENTED_MESSAGES,
+            max_fragment_buffer_size: DEFAULT_MAX_FRAGMENT_BUFFER_SIZE,
+            stats: TrafficStatsHandle::default(),
+        }
+    }
+
+    /// Sets the maximum number of bytes of a frame.
+    ///
+    /// See [`Receiver::set_max_frame_size()`] for the details.
+    pub fn set_max_frame_size(&mut self, size: usize) {
+        self.max_frame_size = size;
+    }
+
+    /// Sets the maximum nesting depth of terms in a message.
+    ///
+    /// See [`Receiver::set_max_term_depth()`] for the details.
+    pub fn set_max_term_depth(&mut self, depth: usize) {
+        self.max_term_depth = depth;
+    }
+
+    /// Sets the maximum number of fragmented messages that are reassembled at the same time.
+    ///
+    /// See [`Receiver::set_max_fragmented_messages()`] for the details.
+    pub fn set_max_fragmented_messages(&mut self, n: usize) {
+        self.max_fragmented_messages = n;
+    }
+
+    /// Sets the maximum total number of bytes of the fragments buffered for reassembling messages.
+    ///
+    /// See [`Receiver::set_max_fragment_buffer_size()`] for the details.
+    pub fn set_max_fragment_buffer_size(&mut self, size: usize) {
+        self.max_fragment_buffer_size = size;
+    }
+
+    /// Appends bytes read from the connection to the input of this decoder.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        if self.consumed > 0 {
+            self.input.drain(..self.consumed);
+            self.consumed = 0;
+        }
+        self.input.extend_from_slice(bytes);
+        self.stats.add_bytes(bytes.len());
+    }
+
+    /// Returns the number of input bytes that have not been decoded yet.
+    pub fn buffered_len(&self) -> usize {
+        self.input.len() - self.consumed
+    }
+
+    /// Decodes the next message in the input.
+    ///
+    /// Returns `None` if more input is needed.
+    /// As with [`Receiver::recv_raw()`], fragmented messages are reassembled and atom cache references
+    /// are resolved.
+    pub fn decode(&mut self) -> Result<Option<RawFrame>, RecvError> {
+        loop {
+            let input = &self.input[self.consumed..];
+            let Some(size) = input.get(..4) else {
+                return Ok(None);
+            };
+            let size = u32::from_be_bytes(size.try_into().expect("unreachable")) as usize;
+            if size == 0 {
+                self.consumed += 4;
+                self.stats.add_frame(true);
+                return Ok(Some(RawFrame::tick()));
+            }
+            if size > self.max_frame_size {
+                return Err(RecvError::FrameTooLarge {
+                    size,
+                    max_size: self.max_frame_size,
+                });
+            }
+            let Some(frame) = input.get(4..4 + size) else {
+                return Ok(None);
+            };
+            let frame = frame.to_vec();
+            self.consumed += 4 + size;
+            self.stats.add_frame(false);
+            if let Some(frame) = self.handle_frame(frame)? {
+                self.stats.add_message(frame.peek_op());
+                return Ok(Some(frame));
+            }
+        }
+    }
+
+    /// Returns the traffic statistics of this decoder.
+    ///
+    /// The bytes are counted when they are given by [`FrameDecoder::feed()`].
+    pub fn stats(&self) -> TrafficStats {
+        self.stats.snapshot()
+    }
+
+    /// Returns a shared handle to the traffic statistics of this decoder.
+    pub fn stats_handle(&self) -> TrafficStatsHandle {
+        self.stats.clone()
+    }
+
+    fn handle_frame(&mut self, mut frame: Vec<u8>) -> Result<Option<RawFrame>, RecvError> {
+        match frame[0] {
+            TYPE_TAG => {
+                // At least the control message must follow the tag (only empty frames are ticks).
+                if frame.len() == 1 {
+                    return Err(raw_frame::missing_control_message().into());
+                }
+                frame.remove(0);
+                RawFrame::validate(frame, self.max_term_depth).map(Some)
+            }
+            etf::VERSION_MAGIC if frame.len() > 1 => {
+                let mut reader = ByteReader(&frame[2..]);
+                match frame[1] {
+                    DIST_HEADER => {
+                        let atoms = self.atom_cache.read_refs(&mut reader)?;
+                        resolve_header_frame_data(reader.0, &atoms, self.max_term_depth).map(Some)
+                    }
+                    DIST_FRAG_HEADER => {
+                        let sequence_id = reader.read_u64()?;
+                        let fragment_id = reader.read_u64()?;
+                        if fragment_id == 0 || self.fragments.contains_key(&sequence_id) {
+                            return Err(RecvError::UnexpectedFragment {
+                                sequence_id,
+                                fragment_id,
+                            });
+                        }
+                        // The atom cache must be updated in the order of the first fragments.
+                        let atoms = self.atom_cache.read_refs(&mut reader)?;
+                        if fragment_id == 1 {
+                            return resolve_header_frame_data(
+                                reader.0,
+                                &atoms,
+                                self.max_term_depth,
+                            )
+                            .map(Some);
+                        }
+                        if self.fragments.len() >= self.max_fragmented_messages
+                            || self.fragments_size + reader.0.len() > self.max_fragment_buffer_size
+                        {
+                            return Err(self.too_many_fragments());
+                        }
+                        let partial = PartialMessage {
+                            next_fragment_id: fragment_id - 1,
+                            atoms,
+                            data: reader.0.to_vec(),
+                        };
+                        self.fragments_size += partial.data.len();
+                        self.fragments.insert(sequence_id, partial);
+                        Ok(None)
+                    }
+                    DIST_FRAG_CONT => {
+                        let sequence_id = reader.read_u64()?;
+                        let fragment_id = reader.read_u64()?;
+                        let Some(partial) = self.fragments.get_mut(&sequence_id) else {
+                            return Err(RecvError::UnexpectedFragment {
+                                sequence_id,
+                                fragment_id,
+                            });
+                        };
+                        if partial.next_fragment_id != fragment_id {
+                            self.remove_fragments(sequence_id);
+                            return Err(RecvError::UnexpectedFragment {
+                                sequence_id,
+                                fragment_id,
+                            });
+                        }
+                        let size = partial.data.len() + reader.0.len();
+                        if size > self.max_frame_size {
+                            self.remove_fragments(sequence_id);
+                            return Err(RecvError::FrameTooLarge {
+                                size,
+                                max_size: self.max_frame_size,
+                            });
+                        }
+                        if self.fragments_size + reader.0.len() > self.max_fragment_buffer_size {
+                            self.remove_fragments(sequence_id);
+                            return Err(self.too_many_fragments());
+                        }
+                        partial.data.extend_from_slice(reader.0);
+                        partial.next_fragment_id -= 1;
+                        self.fragments_size += reader.0.len();
+                        if partial.next_fragment_id > 0 {
+                            return Ok(None);
+                        }
+                        let partial = self.remove_fragments(sequence_id);
+                        resolve_header_frame_data(
+                            &partial.data,
+                            &partial.atoms,
+                            self.max_term_depth,
+                        )
+                        .map(Some)
+                    }
+                    tag => Err(RecvError::UnexpectedTypeTag { tag }),
+                }
+            }
+            tag => Err(RecvError::UnexpectedTypeTag { tag }),
+        }
+    }
+
+    fn remove_fragments(&mut self, sequence_id: u64) -> PartialMessage {
+        let partial = self.fragments.remove(&sequence_id).expect("unreachable");
+        self.fragments_size -= partial.data.len();
+        partial
+    }
+
+    fn too_many_fragments(&self) -> RecvError {
+        RecvError::TooManyFragments {
+            max_messages: self.max_fragmented_messages,
+            max_buffer_size: self.max_fragment_buffer_size,
+        }
+    }
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn resolve_header_frame_data(
+    data: &[u8],
+    atoms: &[eetf::Atom],
+    max_term_depth: usize,
+) -> Result<RawFrame, RecvError> {
+    if data.is_empty() {
+        return Err(raw_frame::missing_control_message().into());
+    }
+    let buf = atom_cache::resolve_refs(data, atoms, max_term_depth)?;
+    Ok(RawFrame::from_validated_bytes(buf))
+}
+
 /// Possible errors during sending messages.
 #[derive(Debug)]
 #[non_exhaustive]
 #[allow(missing_docs)]
 pub enum SendError {
+    /// The message is not supported by the peer and there is no fallback message.
+    UnsupportedByPeer {
+        op: i32,
+        required_flags: DistributionFlags,
+    },
+
     /// Encode error.
     Encode(eetf::EncodeError),
 
+    /// The queue of a [`SenderHandle`](crate::message::SenderHandle) is full.
+    QueueFull,
+
+    /// The writer of a [`SenderHandle`](crate::message::SenderHandle) has been stopped.
+    Closed,
+
     /// I/O error.
     Io(std::io::Error),
 }
@@ -121,7 +979,13 @@ pub enum SendError {
 impl std::fmt::Display for SendError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Self::UnsupportedByPeer { op, required_flags } => write!(
+                f,
+                "distributed operation {op} requires the distribution flags {required_flags:?} which are not supported by the peer"
+            ),
             Self::Encode(error) => write!(f, "{error}"),
+            Self::QueueFull => write!(f, "send queue is full"),
+            Self::Closed => write!(f, "sender has been closed"),
             Self::Io(error) => write!(f, "{error}"),
         }
     }
@@ -130,6 +994,7 @@ impl std::fmt::Display for SendError {
 impl std::error::Error for SendError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
+            Self::UnsupportedByPeer { .. } | Self::QueueFull | Self::Closed => None,
             Self::Encode(error) => Some(error),
             Self::Io(error) => Some(error),
         }
@@ -157,11 +1022,33 @@ pub enum RecvError {
     Closed,
 
     /// Unsupported distributed operation.
+    #[deprecated(note = "unknown operations are received as `Message::Unknown`")]
     UnsupportedOp { op: i32 },
 
     /// Unexpected type tag.
     UnexpectedTypeTag { tag: u8 },
 
+    /// Fragment that doesn't belong to any fragmented message being received.
+    UnexpectedFragment { sequence_id: u64, fragment_id: u64 },
+
+    /// Frame (or fragmented message) larger than the limit.
+    FrameTooLarge { size: usize, max_size: usize },
+
+    /// Fragmented messages being reassembled exceed the limit of their number or total size.
+    TooManyFragments {
+        max_messages: usize,
+        max_buffer_size: usize,
+    },
+
+    /// Term nested deeper than the limit.
+    TermTooDeep { max_depth: usize },
+
+    /// Nothing was received from the peer within the `net_ticktime`.
+    TickTimeout,
+
+    /// Atom cache reference to an entry that has not been cached yet.
+    MissingAtomCacheEntry { index: usize },
+
     /// Decode error.
     Decode(eetf::DecodeError),
 
@@ -173,9 +1060,34 @@ impl std::fmt::Display for RecvError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Closed => write!(f, "connection was closed by the peer"),
+            #[allow(deprecated)]
             Self::UnsupportedOp { op } => write!(f, "unsupported distributed operation {op}"),
-            Self::UnexpectedTypeTag { tag } => {
-                write!(f, "expected type tag {TYPE_TAG} but got {tag}")
+            Self::UnexpectedTypeTag { tag } => write!(f, "unexpected type tag {tag}"),
+            Self::UnexpectedFragment {
+                sequence_id,
+                fragment_id,
+            } => write!(
+                f,
+                "unexpected fragment {fragment_id} of the sequence {sequence_id}"
+            ),
+            Self::FrameTooLarge { size, max_size } => {
+                write!(f, "frame size {size} exceeds the limit of {max_size} bytes")
+            }
+            Self::TooManyFragments {
+                max_messages,
+                max_buffer_size,
+            } => write!(
+                f,
+                "fragmented messages exceed the limit of {max_messages} messages or {max_buffer_size} bytes"
+            ),
+            Self::TermTooDeep { max_depth } => {
+                write!(f, "term nesting depth exceeds the limit of {max_depth}")
+            }
+            Self::TickTimeout => {
+                write!(f, "nothing was received from the peer within net_ticktime")
+            }
+            Self::MissingAtomCacheEntry { index } => {
+                write!(f, "atom cache entry {index} has not been cached")
             }
             Self::Decode(error) => write!(f, "{error}"),
             Self::Io(error) => write!(f, "{error}"),
@@ -204,3 +1116,606 @@ impl From<eetf::DecodeError> for RecvError {
         Self::Decode(value)
     }
 }
+
+impl From<etf::EtfError> for RecvError {
+    fn from(value: etf::EtfError) -> Self {
+        match value {
+            etf::EtfError::TermTooDeep { max_depth } => Self::TermTooDeep { max_depth },
+            etf::EtfError::MissingAtomCacheEntry { index } => Self::MissingAtomCacheEntry { index },
+            etf::EtfError::Decode(e) => Self::Decode(e),
+            etf::EtfError::Io(e) => Self::Io(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::term::{Atom, Binary, FixInteger, Pid, PidOrAtom, Reference, Term, Tuple};
+
+    #[test]
+    fn frame_encoder_and_decoder_work() {
+        let flags = DistributionFlags::mandatory()
+            | DistributionFlags::DIST_HDR_ATOM_CACHE
+            | DistributionFlags::FRAGMENTS;
+        let mut encoder = FrameEncoder::new(flags);
+        encoder.set_fragment_size(16);
+        let mut decoder = FrameDecoder::new();
+
+        let pid = Pid::new("foo@localhost", 0, 0, 0);
+        let messages = vec![
+            Message::Tick,
+            Message::send(pid.clone(), Atom::from("hello").into()),
+            Message::send(pid, Binary::from(vec![7; 100]).into()),
+        ];
+        for message in messages.clone() {
+            encoder.encode(message).unwrap();
+        }
+
+        // Feed the output byte by byte to check that partial frames are handled.
+        let mut received = Vec::new();
+        while !encoder.output().is_empty() {
+            decoder.feed(&encoder.output()[..1]);
+            encoder.consume_output(1);
+            while let Some(frame) = decoder.decode().unwrap() {
+                received.push(frame.decode().unwrap());
+            }
+        }
+        assert_eq!(received, messages);
+        assert_eq!(decoder.buffered_len(), 0);
+    }
+
+    #[test]
+    fn traffic_stats_work() {
+        let flags = DistributionFlags::mandatory()
+            | DistributionFlags::DIST_HDR_ATOM_CACHE
+            | DistributionFlags::FRAGMENTS;
+        let mut encoder = FrameEncoder::new(flags);
+        encoder.set_fragment_size(16);
+        let mut decoder = FrameDecoder::new();
+        assert_eq!(encoder.stats(), TrafficStats::default());
+
+        let pid = Pid::new("foo@localhost", 0, 0, 0);
+        let send = Message::send(pid.clone(), Binary::from(vec![7; 100]).into());
+        let link = Message::link(pid.clone(), pid);
+        encoder.encode(send.clone()).unwrap();
+        encoder.encode(Message::Tick).unwrap();
+        encoder.encode(link.clone()).unwrap();
+        let frame = RawFrame::from_message(link.clone()).unwrap();
+        encoder.encode_raw(frame).unwrap();
+
+        // Bytes are counted only when they are consumed.
+        let handle = encoder.stats_handle();
+        assert_eq!(handle.snapshot().bytes, 0);
+        assert!(handle.snapshot().last_activity.is_none());
+        let mut bytes = 0;
+        while !encoder.output().is_empty() {
+            let output = encoder.output().to_vec();
+            encoder.consume_output(output.len());
+            decoder.feed(&output);
+            bytes += output.len();
+        }
+        while decoder.decode().unwrap().is_some() {}
+
+        let tx = handle.snapshot();
+        let rx = decoder.stats();
+        assert_eq!(tx.bytes, bytes as u64);
+        assert!(tx.frames > 4);
+        assert_eq!(tx.ticks, 1);
+        let expected = [(send.op().unwrap(), 1), (link.op().unwrap(), 2)];
+        assert_eq!(tx.messages, expected.into_iter().collect());
+        assert!(tx.last_activity.is_some());
+        assert!(rx.last_activity.is_some());
+        assert_eq!(
+            (rx.bytes, rx.frames, rx.ticks, &rx.messages),
+            (tx.bytes, tx.frames, tx.ticks, &tx.messages)
+        );
+    }
+
+    #[test]
+    fn fragmented_message_works() {
+        smol::block_on(async {
+            let (client, server) = crate::tests::tcp_stream_pair().await;
+            let flags = DistributionFlags::mandatory()
+                | DistributionFlags::DIST_HDR_ATOM_CACHE
+                | DistributionFlags::FRAGMENTS;
+            let (mut tx, _) = channel(client, flags);
+            let (_, mut rx) = channel(server, flags);
+            tx.set_fragment_size(16);
+
+            let pid = Pid::new("foo@localhost", 0, 0, 0);
+            let payload = Binary::from(vec![7; 100]);
+            let msg = Message::reg_send(pid, Atom::from("bar"), payload.into());
+            tx.send(msg.clone()).await.unwrap();
+            tx.send(Message::Tick).await.unwrap();
+            assert_eq!(rx.recv().await.unwrap(), msg);
+            assert_eq!(rx.recv().await.unwrap(), Message::Tick);
+        });
+    }
+
+    #[test]
+    fn fragmented_messages_keep_their_order() {
+        let flags = DistributionFlags::mandatory()
+            | DistributionFlags::DIST_HDR_ATOM_CACHE
+            | DistributionFlags::FRAGMENTS;
+        let mut encoder = FrameEncoder::new(flags);
+        encoder.set_fragment_size(64);
+        let mut decoder = FrameDecoder::new();
+
+        let pid = Pid::new("foo@localhost", 0, 0, 0);
+        let large = |atom: &str| {
+            let term = Tuple::from(vec![
+                Atom::from(atom).into(),
+                Binary::from(vec![7; 200]).into(),
+            ]);
+            Message::send(pid.clone(), term.into())
+        };
+        let small = |atom: &str| Message::send(pid.clone(), Atom::from(atom).into());
+
+        // The small messages refer to the atom cache entries added by the large ones.
+        let messages = [large("one"), large("two"), small("two"), small("one")];
+        for message in messages.clone() {
+            encoder.encode(message).unwrap();
+        }
+        assert!(encoder.output().len() < 200);
+        assert!(encoder.buffered_len() > 400);
+
+        // Only ticks are written between the fragments.
+        let first_fragment_len = encoder.output().len();
+        decoder.feed(&encoder.output()[..first_fragment_len]);
+        encoder.consume_output(first_fragment_len);
+        encoder.encode(Message::Tick).unwrap();
+        let mut received = Vec::new();
+        while !encoder.output().is_empty() {
+            decoder.feed(encoder.output());
+            encoder.consume_output(encoder.output().len());
+            while let Some(frame) = decoder.decode().unwrap() {
+                received.push(frame.decode().unwrap());
+            }
+        }
+        assert_eq!(received[0], Message::Tick);
+        assert_eq!(received[1..], messages);
+        assert_eq!(encoder.buffered_len(), 0);
+    }
+
+    #[test]
+    fn interleaved_fragments_are_reassembled() {
+        smol::block_on(async {
+            let (client, server) = crate::tests::tcp_stream_pair().await;
+            let mut writer = Connection::new(client);
+            let (_, mut rx) = channel(server, DistributionFlags::FRAGMENTS);
+
+            let pid = Pid::new("foo@localhost", 0, 0, 0);
+            let msgs = [
+                Message::send(pid.clone(), Atom::from("hello").into()),
+                Message::send(pid, Atom::from("world").into()),
+            ];
+            let mut data = Vec::new();
+            for msg in msgs.clone() {
+                let mut buf = Vec::new();
+                msg.write_into(&mut buf).unwrap();
+                let (_, msg_data) = SendAtomCache::default().encode(&buf, false).unwrap();
+                data.push(msg_data);
+            }
+
+            for (i, fragment_id) in [(0, 2u64), (1, 2), (0, 1), (1, 1)] {
+                let (head, tail) = data[i].split_at(data[i].len() / 2);
+                let mut frame = vec![etf::VERSION_MAGIC];
+                if fragment_id == 2 {
+                    frame.push(DIST_FRAG_HEADER);
+                } else {
+                    frame.push(DIST_FRAG_CONT);
+                }
+                frame.extend_from_slice(&(i as u64).to_be_bytes());
+                frame.extend_from_slice(&fragment_id.to_be_bytes());
+                if fragment_id == 2 {
+                    frame.push(0);
+                    frame.extend_from_slice(head);
+                } else {
+                    frame.extend_from_slice(tail);
+                }
+                writer
+                    .write_all(&(frame.len() as u32).to_be_bytes())
+                    .await
+                    .unwrap();
+                writer.write_all(&frame).await.unwrap();
+            }
+            writer.flush().await.unwrap();
+
+            assert_eq!(rx.recv().await.unwrap(), msgs[0]);
+            assert_eq!(rx.recv().await.unwrap(), msgs[1]);
+        });
+    }
+
+    #[test]
+    fn too_large_frame_is_rejected() {
+        smol::block_on(async {
+            let (client, server) = crate::tests::tcp_stream_pair().await;
+            let mut writer = Connection::new(client);
+            let (_, mut rx) = channel(server, DistributionFlags::mandatory());
+            rx.set_max_frame_size(1024);
+
+            writer.write_all(&u32::MAX.to_be_bytes()).await.unwrap();
+            writer.flush().await.unwrap();
+            assert!(matches!(
+                rx.recv().await,
+                Err(RecvError::FrameTooLarge {
+                    size: 0xFFFF_FFFF,
+                    max_size: 1024
+                })
+            ));
+        });
+    }
+
+    #[test]
+    fn too_many_fragments_are_rejected() {
+        // The first fragment of a message consisting of two fragments.
+        let first_fragment = |sequence_id: u64, data: &[u8]| {
+            let mut frame = vec![etf::VERSION_MAGIC, DIST_FRAG_HEADER];
+            frame.extend_from_slice(&sequence_id.to_be_bytes());
+            frame.extend_from_slice(&2u64.to_be_bytes());
+            frame.push(0);
+            frame.extend_from_slice(data);
+            let mut bytes = (frame.len() as u32).to_be_bytes().to_vec();
+            bytes.extend_from_slice(&frame);
+            bytes
+        };
+
+        let mut decoder = FrameDecoder::new();
+        decoder.set_max_fragmented_messages(100);
+        for sequence_id in 0..100 {
+            decoder.feed(&first_fragment(sequence_id, &[0; 10]));
+            assert!(decoder.decode().unwrap().is_none());
+        }
+        decoder.feed(&first_fragment(100, &[0; 10]));
+        assert!(matches!(
+            decoder.decode(),
+            Err(RecvError::TooManyFragments {
+                max_messages: 100,
+                ..
+            })
+        ));
+
+        let mut decoder = FrameDecoder::new();
+        decoder.set_max_fragment_buffer_size(100);
+        decoder.feed(&first_fragment(0, &[0; 60]));
+        assert!(decoder.decode().unwrap().is_none());
+        decoder.feed(&first_fragment(1, &[0; 60]));
+        assert!(matches!(
+            decoder.decode(),
+            Err(RecvError::TooManyFragments {
+                max_buffer_size: 100,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn frames_without_control_message_are_rejected() {
+        let mut decoder = FrameDecoder::new();
+        decoder.feed(&[0, 0, 0, 1, TYPE_TAG]);
+        assert!(matches!(decoder.decode(), Err(RecvError::Io(_))));
+
+        let mut decoder = FrameDecoder::new();
+        decoder.feed(&[0, 0, 0, 3, etf::VERSION_MAGIC, DIST_HEADER, 0]);
+        assert!(matches!(decoder.decode(), Err(RecvError::Io(_))));
+    }
+
+    #[test]
+    fn too_deep_term_is_rejected() {
+        smol::block_on(async {
+            let (client, server) = crate::tests::tcp_stream_pair().await;
+            let flags = DistributionFlags::mandatory() | DistributionFlags::DIST_HDR_ATOM_CACHE;
+            let (mut tx, _) = channel(client.clone(), DistributionFlags::mandatory());
+            let (mut header_tx, _) = channel(client, flags);
+            let (_, mut rx) = channel(server, flags);
+            rx.set_max_term_depth(10);
+
+            let pid = Pid::new("foo@localhost", 0, 0, 0);
+            let mut shallow = Term::from(Atom::from("hello"));
+            for _ in 0..5 {
+                shallow = eetf::Tuple::from(vec![shallow]).into();
+            }
+            let mut deep = shallow.clone();
+            for _ in 0..5 {
+                deep = eetf::Tuple::from(vec![deep]).into();
+            }
+
+            for tx in [&mut tx, &mut header_tx] {
+                let msg = Message::send(pid.clone(), shallow.clone());
+                tx.send(msg.clone()).await.unwrap();
+                assert_eq!(rx.recv().await.unwrap(), msg);
+
+                tx.send(Message::send(pid.clone(), deep.clone()))
+                    .await
+                    .unwrap();
+                assert!(matches!(
+                    rx.recv().await,
+                    Err(RecvError::TermTooDeep { max_depth: 10 })
+                ));
+            }
+        });
+    }
+
+    #[test]
+    fn send_all_works() {
+        smol::block_on(async {
+            let (client, server) = crate::tests::tcp_stream_pair().await;
+            let flags = DistributionFlags::mandatory();
+            let (mut tx, _) = channel(client, flags);
+            let (_, mut rx) = channel(server, flags);
+
+            let pid = Pid::new("foo@localhost", 0, 0, 0);
+            let msgs = (0..100)
+                .map(|i| {
+                    Message::reg_send(pid.clone(), Atom::from("bar"), FixInteger::from(i).into())
+                })
+                .collect::<Vec<_>>();
+            tx.send_all(msgs.clone()).await.unwrap();
+            tx.feed(Message::Tick).unwrap();
+            tx.feed(msgs[0].clone()).unwrap();
+            tx.flush().await.unwrap();
+
+            for msg in msgs.iter().chain([&Message::Tick, &msgs[0]]) {
+                assert_eq!(rx.recv().await.unwrap(), *msg);
+            }
+        });
+    }
+
+    #[test]
+    fn raw_frames_can_be_forwarded() {
+        smol::block_on(async {
+            let header_flags =
+                DistributionFlags::mandatory() | DistributionFlags::DIST_HDR_ATOM_CACHE;
+            let (client0, server0) = crate::tests::tcp_stream_pair().await;
+            let (mut tx0, _) = channel(client0, header_flags);
+            let (_, mut rx0) = channel(server0, header_flags);
+            let (client1, server1) = crate::tests::tcp_stream_pair().await;
+            let (mut tx1, _) = channel(client1, DistributionFlags::mandatory());
+            let (_, mut rx1) = channel(server1, DistributionFlags::mandatory());
+
+            let pid = Pid::new("foo@localhost", 0, 0, 0);
+            let msg = Message::reg_send(pid, Atom::from("bar"), Atom::from("baz").into());
+            for _ in 0..2 {
+                tx0.send(msg.clone()).await.unwrap();
+                tx0.send(Message::Tick).await.unwrap();
+                for _ in 0..2 {
+                    let frame = rx0.recv_raw().await.unwrap();
+                    tx1.send_raw(frame).await.unwrap();
+                }
+                assert_eq!(rx1.recv().await.unwrap(), msg);
+                assert_eq!(rx1.recv().await.unwrap(), Message::Tick);
+            }
+        });
+    }
+
+    #[test]
+    fn unknown_messages_are_preserved() {
+        smol::block_on(async {
+            let flags = DistributionFlags::mandatory() | DistributionFlags::DIST_HDR_ATOM_CACHE;
+            let (client, server) = crate::tests::tcp_stream_pair().await;
+            let (mut tx, _) = channel(client, flags);
+            let (_, mut rx) = channel(server, flags);
+
+            let pid = Pid::new("foo@localhost", 0, 0, 0);
+            let control = Tuple::from(vec![
+                FixInteger::from(99).into(),
+                pid.into(),
+                Atom::from("bar").into(),
+            ]);
+            let mut bytes = Vec::new();
+            Term::from(control.clone()).encode(&mut bytes).unwrap();
+            Term::from(Atom::from("baz")).encode(&mut bytes).unwrap();
+            let frame = RawFrame::new(bytes).unwrap();
+
+            // Unknown operations don't stop the receiver.
+            tx.send_raw(frame.clone()).await.unwrap();
+            tx.send(Message::Tick).await.unwrap();
+            let msg = rx.recv().await.unwrap();
+            assert_eq!(
+                msg,
+                Message::Unknown {
+                    op: 99,
+                    control,
+                    payload: Some(Atom::from("baz").into()),
+                }
+            );
+            assert_eq!(msg.op(), Some(99));
+            assert_eq!(rx.recv().await.unwrap(), Message::Tick);
+
+            // Unknown messages can be re-encoded as is.
+            tx.send(msg).await.unwrap();
+            assert_eq!(rx.recv_raw().await.unwrap(), frame);
+
+            // Extra terms after the payload are rejected instead of being dropped.
+            let mut bytes = frame.into_bytes();
+            Term::from(Atom::from("qux")).encode(&mut bytes).unwrap();
+            assert!(matches!(
+                RawFrame::new(bytes).unwrap().decode(),
+                Err(RecvError::Io(_))
+            ));
+        });
+    }
+
+    #[test]
+    fn lazy_messages_work() {
+        smol::block_on(async {
+            let (client, server) = crate::tests::tcp_stream_pair().await;
+            let (mut tx, _) = channel(client, DistributionFlags::mandatory());
+            let (_, mut rx) = channel(server, DistributionFlags::mandatory());
+
+            let from = Pid::new("foo@localhost", 0, 0, 0);
+            let to = Pid::new("bar@localhost", 1, 0, 0);
+            let msg = Message::reg_send(from.clone(), Atom::from("baz"), Atom::from("qux").into());
+            tx.send(msg.clone()).await.unwrap();
+            let lazy = rx.recv_lazy().await.unwrap();
+            assert_eq!(lazy.op(), Some(6));
+            assert_eq!(lazy.from_pid(), Some(&from));
+            assert_eq!(lazy.to_pid(), None);
+            assert_eq!(lazy.to_name(), Some(&Atom::from("baz")));
+            let payload = lazy.payload().unwrap();
+            assert_eq!(payload.as_bytes(), [131, 119, 3, b'q', b'u', b'x']);
+            assert_eq!(payload.decode().unwrap(), Atom::from("qux").into());
+            assert_eq!(lazy.decode().unwrap(), msg);
+
+            let msg = Message::link(from.clone(), to.clone());
+            tx.send(msg.clone()).await.unwrap();
+            let lazy = rx.recv_lazy().await.unwrap();
+            assert_eq!(lazy.from_pid(), Some(&from));
+            assert_eq!(lazy.to_pid(), Some(&to));
+            assert!(lazy.payload().is_none());
+            assert_eq!(lazy.decode().unwrap(), msg);
+
+            tx.send(Message::Tick).await.unwrap();
+            let lazy = rx.recv_lazy().await.unwrap();
+            assert!(lazy.is_tick());
+            assert_eq!(lazy.decode().unwrap(), Message::Tick);
+        });
+    }
+
+    #[test]
+    fn cancelled_recv_can_be_resumed() {
+        use futures::AsyncWriteExt as _;
+
+        smol::block_on(async {
+            let (client, mut server) = crate::tests::tcp_stream_pair().await;
+            let (_, mut rx) = channel(client, DistributionFlags::mandatory());
+
+            let pid = Pid::new("foo@localhost", 0, 0, 0);
+            let msg = Message::send(pid, Atom::from("hello").into());
+            let bytes = RawFrame::from_message(msg.clone()).unwrap().into_bytes();
+            let mut frame = (1 + bytes.len() as u32).to_be_bytes().to_vec();
+            frame.push(TYPE_TAG);
+            frame.extend_from_slice(&bytes);
+
+            // Cancels `recv()` in the middle of both the size prefix and the body.
+            for chunk in [&frame[..2], &frame[2..8]] {
+                server.write_all(chunk).await.unwrap();
+                let recv = std::pin::pin!(rx.recv());
+                let timer = smol::Timer::after(std::time::Duration::from_millis(50));
+                let result = futures::future::select(recv, timer).await;
+                assert!(matches!(result, futures::future::Either::Right(_)));
+            }
+            server.write_all(&frame[8..]).await.unwrap();
+            assert_eq!(rx.recv().await.unwrap(), msg);
+        });
+    }
+
+    #[test]
+    fn sink_and_stream_work() {
+        use futures::{SinkExt, StreamExt as _};
+
+        smol::block_on(async {
+            let (client, server) = crate::tests::tcp_stream_pair().await;
+            let (mut tx, _) = split_channel(client, DistributionFlags::mandatory());
+            let (_, mut rx) = split_channel(server, DistributionFlags::mandatory());
+
+            let pid = Pid::new("foo@localhost", 0, 0, 0);
+            let msgs = (0..3)
+                .map(|i| Message::send(pid.clone(), FixInteger::from(i).into()))
+                .collect::<Vec<_>>();
+            let mut items = futures::stream::iter(msgs.clone()).map(Ok);
+            SinkExt::send_all(&mut tx, &mut items).await.unwrap();
+            SinkExt::send(&mut tx, Message::Tick).await.unwrap();
+            tx.close().await.unwrap();
+
+            let received = (&mut rx).map(|msg| msg.unwrap()).collect::<Vec<_>>().await;
+            assert_eq!(received[..3], msgs);
+            assert_eq!(received[3], Message::Tick);
+            assert_eq!(received.len(), 4);
+            assert!(rx.next().await.is_none());
+        });
+    }
+
+    #[test]
+    fn split_channel_works() {
+        smol::block_on(async {
+            let (client, server) = crate::tests::tcp_stream_pair().await;
+            let flags = DistributionFlags::mandatory();
+            let (mut tx, mut rx) = split_channel(client, flags);
+            let (mut peer_tx, mut peer_rx) = channel_from_halves(server.clone(), server, flags);
+
+            let pid = Pid::new("foo@localhost", 0, 0, 0);
+            let msg = Message::send(pid, Atom::from("hello").into());
+            tx.send(msg.clone()).await.unwrap();
+            assert_eq!(peer_rx.recv().await.unwrap(), msg);
+            peer_tx.send(msg.clone()).await.unwrap();
+            assert_eq!(rx.recv().await.unwrap(), msg);
+        });
+    }
+
+    #[test]
+    fn fallback_messages_are_sent() {
+        smol::block_on(async {
+            let (client, server) = crate::tests::tcp_stream_pair().await;
+            let flags = DistributionFlags::from_bits_truncate(
+                DistributionFlags::mandatory().bits() & !DistributionFlags::UNLINK_ID.bits(),
+            );
+            let (mut tx, _) = channel(client, flags);
+            let (_, mut rx) = channel(server, flags);
+
+            let from_pid = Pid::new("foo@localhost", 0, 0, 0);
+            let to_pid = Pid::new("bar@localhost", 1, 0, 0);
+            let reason = Term::from(Atom::from("normal"));
+            let id = Term::from(FixInteger::from(1));
+            let msgs = [
+                Message::payload_exit(from_pid.clone(), to_pid.clone(), reason.clone()),
+                Message::send_sender(from_pid.clone(), to_pid.clone(), reason.clone()),
+                Message::unlink_id(id, from_pid.clone(), to_pid.clone()),
+            ];
+            for msg in msgs {
+                tx.send(msg).await.unwrap();
+            }
+            assert_eq!(
+                rx.recv().await.unwrap(),
+                Message::exit(from_pid.clone(), to_pid.clone(), reason.clone())
+            );
+            assert_eq!(
+                rx.recv().await.unwrap(),
+                Message::send(to_pid.clone(), reason)
+            );
+            assert_eq!(rx.recv().await.unwrap(), Message::unlink(from_pid, to_pid));
+        });
+    }
+
+    #[test]
+    fn unsupported_messages_are_rejected() {
+        smol::block_on(async {
+            let (client, _server) = crate::tests::tcp_stream_pair().await;
+            let (mut tx, _) = channel(client, DistributionFlags::mandatory());
+
+            let from_pid = Pid::new("foo@localhost", 0, 0, 0);
+            let alias = Reference::from(("foo@localhost", vec![1, 2, 3]));
+            let msg = Message::alias_send(from_pid.clone(), alias.clone(), Atom::from("hi").into());
+            assert!(matches!(
+                tx.send(msg).await,
+                Err(SendError::UnsupportedByPeer { op: 33, required_flags })
+                    if required_flags == DistributionFlags::ALIAS
+            ));
+
+            let to_proc = PidOrAtom::Atom(Atom::from("bar"));
+            let msg = Message::monitor_p(from_pid, to_proc, alias);
+            assert!(matches!(
+                tx.send(msg).await,
+                Err(SendError::UnsupportedByPeer { op: 19, .. })
+            ));
+        });
+    }
+
+    #[test]
+    fn atom_cache_works() {
+        smol::block_on(async {
+            let (client, server) = crate::tests::tcp_stream_pair().await;
+            let flags = DistributionFlags::mandatory() | DistributionFlags::DIST_HDR_ATOM_CACHE;
+            let (mut tx, _) = channel(client, flags);
+            let (_, mut rx) = channel(server, flags);
+
+            let pid = Pid::new("foo@localhost", 0, 0, 0);
+            let msg = Message::reg_send(pid, Atom::from("bar"), Atom::from("baz").into());
+            for _ in 0..3 {
+                tx.send(msg.clone()).await.unwrap();
+                assert_eq!(rx.recv().await.unwrap(), msg);
+            }
+        });
+    }
+}
@@ -0,0 +1,1020 @@
+//! High-level functions to establish distribution connections.
+//!
+//! [`connect()`] does all the steps needed to talk to an Erlang node:
+//! looking up the node in EPMD on the peer host, selecting a distribution protocol version,
+//! executing the client-side handshake and making a channel.
+//! [`DistListener`] is the server-side counterpart: it registers the local node in EPMD and
+//! yields the connections that have completed the server-side handshake.
+//! [`ConnectionRegistry`] helps nodes that both connect and accept to resolve simultaneous connects.
+//!
+//! As this crate doesn't depend on any specific async runtime, [`connect()`] takes a function
+//! that opens TCP connections and [`DistListener`] takes a stream of accepted connections.
+//! [`connect_tokio()`] (`tokio` feature) and [`crate::blocking::connect()`] are the variants that
+//! don't need such a function.
+//!
+//! # Examples
+//!
+//! Connects to a node:
+//! ```no_run
+//! use erl_dist::connection;
+//! use erl_dist::message::Message;
+//! use erl_dist::node::{Creation, LocalNode};
+//! use erl_dist::term::{Atom, Pid};
+//! use smol::net::TcpStream;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! # smol::block_on(async {
+//! let local_node = LocalNode::new("foo@localhost".parse()?, Creation::random());
+//! let peer_name = "bar@localhost".parse()?;
+//! let tcp_connect =
+//!     |host: String, port| async move { TcpStream::connect((host.as_str(), port)).await };
+//! let (mut tx, _, _peer_node) =
+//!     connection::connect(local_node.clone(), &peer_name, "cookie", tcp_connect).await?;
+//!
+//! let from_pid = Pid::new(local_node.name.to_string(), 0, 0, local_node.creation.get());
+//! let msg = Message::reg_send(from_pid, Atom::from("baz"), Atom::from("hello").into());
+//! tx.send(msg).await?;
+//! # Ok(())
+//! # })
+//! # }
+//! ```
+//!
+//! Accepts connections from other nodes:
+//! ```no_run
+//! use erl_dist::connection::{self, DistListener};
+//! use erl_dist::epmd::{DEFAULT_EPMD_PORT, NodeEntry};
+//! use futures::StreamExt as _;
+//! use smol::net::{TcpListener, TcpStream};
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! # smol::block_on(async {
+//! // Listen on a port in the range (like `inet_dist_listen_min` and `inet_dist_listen_max`).
+//! let listener = connection::bind_tcp("0.0.0.0".parse()?, 9100..=9200)?;
+//! let port = listener.local_addr()?.port();
+//! let listener = TcpListener::try_from(listener)?;
+//!
+//! let epmd = TcpStream::connect(("localhost", DEFAULT_EPMD_PORT)).await?;
+//! let entry = NodeEntry::new("foo", port);
+//! let mut listener =
+//!     DistListener::register(listener.incoming(), epmd, entry, "foo@localhost".parse()?, "cookie")
+//!         .await?;
+//! while let Some(accepted) = listener.next().await {
+//!     let (_tx, _rx, peer_node) = accepted?;
+//!     println!("Connected: {}", peer_node.name);
+//! }
+//! # Ok(())
+//! # })
+//! # }
+//! ```
+use crate::DistributionFlags;
+use crate::epmd::{DEFAULT_EPMD_PORT, EpmdClient, EpmdError, NodeEntry, NodeType};
+use crate::handshake::{
+    ClientSideHandshake, CookieProvider, HandshakeError, HandshakeStatus, ServerSideHandshake,
+};
+use crate::message::{Receiver, Sender};
+use crate::node::{LocalNode, NodeName, PeerNode};
+use crate::{HIGHEST_DISTRIBUTION_PROTOCOL_VERSION, LOWEST_DISTRIBUTION_PROTOCOL_VERSION};
+use futures::future::BoxFuture;
+use futures::io::{AsyncRead, AsyncWrite, ReadHalf, WriteHalf};
+use futures::stream::{FuturesUnordered, Stream, StreamExt as _};
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr, TcpListener};
+use std::ops::RangeInclusive;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// Connects to a node and makes a channel to send/receive messages to/from it.
+///
+/// This executes the following steps:
+/// 1. Connects to EPMD on the host of `peer_name` and gets the node entry of the peer.
+/// 2. Selects the highest distribution protocol version supported by both nodes.
+/// 3. Connects to the distribution port of the peer and executes the client-side handshake
+///    (if the peer replies [`HandshakeStatus::Alive`],
+///    the handshake continues).
+/// 4. Makes a channel with `local_node.flags & peer_node.flags`.
+///
+/// `tcp_connect` is called with a host and a port to open a TCP connection
+/// (first to EPMD and then to the peer node).
+#[allow(clippy::type_complexity)]
+pub async fn connect<F, Fut, T>(
+    local_node: LocalNode,
+    peer_name: &NodeName,
+    cookie: &str,
+    mut tcp_connect: F,
+) -> Result<(Sender<WriteHalf<T>>, Receiver<ReadHalf<T>>, PeerNode), ConnectError>
+where
+    F: FnMut(String, u16) -> Fut,
+    Fut: Future<Output = std::io::Result<T>>,
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let host = peer_name.host().to_owned();
+    let connection = tcp_connect(host.clone(), DEFAULT_EPMD_PORT)
+        .await
+        .map_err(ConnectError::EpmdConnect)?;
+    let entry = EpmdClient::new(connection)
+        .get_node(peer_name.name())
+        .await?
+        .ok_or_else(|| ConnectError::NodeNotFound {
+            peer_name: peer_name.clone(),
+        })?;
+    let version = select_version(&entry)?;
+
+    let connection = tcp_connect(host, entry.port)
+        .await
+        .map_err(ConnectError::Connect)?;
+    let flags = local_node.flags;
+    let mut handshake = ClientSideHandshake::new(connection, local_node, cookie);
+    handshake.execute_send_name(version).await?;
+    let (connection, peer_node) = handshake.execute_rest(true).await?;
+    let (tx, rx) = crate::message::split_channel(connection, flags & peer_node.flags);
+    Ok((tx, rx, peer_node))
+}
+
+/// [`connect()`] that uses [`tokio::net::TcpStream`] to open TCP connections.
+///
+/// This is available only if the `tokio` feature is enabled.
+#[cfg(feature = "tokio")]
+#[allow(clippy::type_complexity)]
+pub async fn connect_tokio(
+    local_node: LocalNode,
+    peer_name: &NodeName,
+    cookie: &str,
+) -> Result<
+    (
+        Sender<WriteHalf<tokio_util::compat::Compat<tokio::net::TcpStream>>>,
+        Receiver<ReadHalf<tokio_util::compat::Compat<tokio::net::TcpStream>>>,
+        PeerNode,
+    ),
+    ConnectError,
+> {
+    use tokio_util::compat::TokioAsyncReadCompatExt as _;
+
+    let tcp_connect = |host: String, port| async move {
+        let connection = tokio::net::TcpStream::connect((host.as_str(), port)).await?;
+        Ok(connection.compat())
+    };
+    connect(local_node, peer_name, cookie, tcp_connect).await
+}
+
+/// Connection accepted by [`DistListener`].
+pub type Accepted<T> = (Sender<WriteHalf<T>>, Receiver<ReadHalf<T>>, PeerNode);
+
+/// Range of ports to listen on.
+///
+/// This is made from a port number (`0` means any port) or a port range
+/// (like the `inet_dist_listen_min` and `inet_dist_listen_max` parameters of Erlang).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PortRange(RangeInclusive<u16>);
+
+impl From<u16> for PortRange {
+    fn from(value: u16) -> Self {
+        Self(value..=value)
+    }
+}
+
+impl From<RangeInclusive<u16>> for PortRange {
+    fn from(value: RangeInclusive<u16>) -> Self {
+        Self(value)
+    }
+}
+
+/// Binds a TCP listener to the first available port in `ports`.
+///
+/// The returned listener is in blocking mode.
+/// Please convert it by your async runtime (e.g., `smol::net::TcpListener::try_from()`, or
+/// `tokio::net::TcpListener::from_std()` after calling `set_nonblocking(true)`) to pass it to [`DistListener`].
+pub fn bind_tcp<P: Into<PortRange>>(ip: IpAddr, ports: P) -> std::io::Result<TcpListener> {
+    let PortRange(ports) = ports.into();
+    let mut last_error = None;
+    for port in ports.clone() {
+        match TcpListener::bind(SocketAddr::new(ip, port)) {
+            Ok(listener) => return Ok(listener),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("empty port range {ports:?}"),
+        )
+    }))
+}
+
+/// Listener that yields the connections that have completed the server-side handshake.
+///
+/// The local node is registered in EPMD by [`DistListener::register()`] and is deregistered when
+/// this listener is dropped (EPMD removes the node when the registration connection is closed).
+///
+/// The handshakes of accepted connections are executed concurrently.
+/// Peers requesting a dynamic node name are rejected with [`HandshakeStatus::NotAllowed`].
+pub struct DistListener<S, E, T> {
+    incoming: Option<S>,
+    epmd_connection: E,
+    local_node: LocalNode,
+    cookies: Arc<dyn CookieProvider>,
+    handshake_timeout: Option<(Duration, Arc<HandshakeTimer>)>,
+    handshakes: FuturesUnordered<BoxFuture<'static, Result<Accepted<T>, AcceptError>>>,
+}
+
+type HandshakeTimer = dyn Fn(Duration) -> BoxFuture<'static, ()> + Send + Sync;
+
+impl<S, E, T> DistListener<S, E, T>
+where
+    S: Stream<Item = std::io::Result<T>> + Unpin,
+    E: AsyncRead + AsyncWrite + Unpin,
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    /// Registers the local node in EPMD and makes a new [`DistListener`] instance.
+    ///
+    /// `incoming` is a stream of connections accepted on the port of `entry`
+    /// (e.g., `smol::net::TcpListener::incoming()`),
+    /// and `epmd_connection` is a connection to EPMD on the local host.
+    /// If `entry` is a normal (not hidden) node, [`DistributionFlags::PUBLISHED`] is set to the local node.
+    pub async fn register(
+        incoming: S,
+        epmd_connection: E,
+        entry: NodeEntry,
+        local_name: NodeName,
+        cookie: &str,
+    ) -> Result<Self, EpmdError> {
+        let published = entry.node_type == NodeType::Normal;
+        let (epmd_connection, creation) = EpmdClient::new(epmd_connection).register(entry).await?;
+        let mut local_node = LocalNode::new(local_name, creation);
+        if published {
+            local_node.flags |= DistributionFlags::PUBLISHED;
+        }
+        Ok(Self {
+            incoming: Some(incoming),
+            epmd_connection,
+            local_node,
+            cookies: Arc::new(cookie.to_owned()),
+            handshake_timeout: None,
+            handshakes: FuturesUnordered::new(),
+        })
+    }
+}
+
+impl<S, E, T> DistListener<S, E, T> {
+    /// Returns the local node (including the creation assigned by EPMD).
+    pub fn local_node(&self) -> &LocalNode {
+        &self.local_node
+    }
+
+    /// Sets the distribution flags used in the handshakes of subsequent connections.
+    pub fn set_flags(&mut self, flags: DistributionFlags) {
+        self.local_node.flags = flags;
+    }
+
+    /// Sets the provider of the cookies used in the handshakes of subsequent connections.
+    ///
+    /// This replaces the single cookie given to [`DistListener::register()`].
+    pub fn set_cookie_provider<C>(&mut self, cookies: C)
+    where
+        C: CookieProvider + 'static,
+    {
+        self.cookies = Arc::new(cookies);
+    }
+
+    /// Sets the time limit of the handshakes of subsequent connections.
+    ///
+    /// See [`ServerSideHandshake::set_timeout()`] for the details
+    /// ([`DEFAULT_NET_SETUPTIME`](crate::handshake::DEFAULT_NET_SETUPTIME) is a reasonable value).
+    /// Without this, a peer that connects and then goes silent holds its connection forever.
+    pub fn set_handshake_timeout<F, Fut>(&mut self, timeout: Duration, sleep: F)
+    where
+        F: Fn(Duration) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+    {
+        let timer = move |timeout| {
+            let sleep = sleep(timeout);
+            Box::pin(async move {
+                sleep.await;
+            }) as BoxFuture<'static, ()>
+        };
+        self.handshake_timeout = Some((timeout, Arc::new(timer)));
+    }
+
+    /// Returns a reference to the connection registering the local node in EPMD.
+    pub fn epmd_connection(&self) -> &E {
+        &self.epmd_connection
+    }
+}
+
+impl<S, E, T> Stream for DistListener<S, E, T>
+where
+    S: Stream<Item = std::io::Result<T>> + Unpin,
+    E: Unpin,
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Item = Result<Accepted<T>, AcceptError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        while let Some(incoming) = &mut this.incoming {
+            match incoming.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(connection))) => {
+                    let local_node = this.local_node.clone();
+                    let cookies = Arc::clone(&this.cookies);
+                    let timeout = this.handshake_timeout.clone();
+                    this.handshakes
+                        .push(Box::pin(accept(connection, local_node, cookies, timeout)));
+                }
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(AcceptError::Io(e)))),
+                Poll::Ready(None) => this.incoming = None,
+                Poll::Pending => break,
+            }
+        }
+        match this.handshakes.poll_next_unpin(cx) {
+            Poll::Ready(None) if this.incoming.is_some() => Poll::Pending,
+            poll => poll,
+        }
+    }
+}
+
+impl<S, E, T> std::fmt::Debug for DistListener<S, E, T>
+where
+    E: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DistListener")
+            .field("epmd_connection", &self.epmd_connection)
+            .field("local_node", &self.local_node)
+            .field(
+                "handshake_timeout",
+                &self.handshake_timeout.as_ref().map(|(timeout, _)| timeout),
+            )
+            .field("handshakes", &self.handshakes.len())
+            .finish_non_exhaustive()
+    }
+}
+
+async fn accept<T>(
+    connection: T,
+    local_node: LocalNode,
+    cookies: Arc<dyn CookieProvider>,
+    timeout: Option<(Duration, Arc<HandshakeTimer>)>,
+) -> Result<Accepted<T>, AcceptError>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let flags = local_node.flags;
+    let mut handshake = ServerSideHandshake::with_cookie_provider(connection, local_node, cookies);
+    if let Some((timeout, timer)) = timeout {
+        handshake.set_timeout(timeout, |timeout| timer(timeout));
+    }
+    let status = match handshake.execute_recv_name().await? {
+        Some(_) => HandshakeStatus::Ok,
+        None => HandshakeStatus::NotAllowed,
+    };
+    let (connection, peer_node) = handshake.execute_rest(status).await?;
+    let (tx, rx) = crate::message::split_channel(connection, flags & peer_node.flags);
+    Ok((tx, rx, peer_node))
+}
+
+/// Registry of the connections of the local node, used to resolve simultaneous connects.
+///
+/// When two nodes connect to each other at the same time, Erlang keeps only one of the connections
+/// by the following rules, which this registry implements:
+/// - The server side replies [`HandshakeStatus::Nok`] if it has its own ongoing attempt to the
+///   client and its name is greater than the client's name (its own attempt wins).
+/// - Otherwise, the server side replies [`HandshakeStatus::OkSimultaneous`] and its own attempt
+///   loses (it will be rejected by the peer with [`HandshakeStatus::Nok`]).
+/// - If a connection to the client is already active, the server side replies
+///   [`HandshakeStatus::Alive`], and the client side continues the handshake only if it has no
+///   connection to the server (i.e., the server's connection is a stale one that will be replaced).
+///
+/// Node names are compared literally.
+///
+/// # Examples
+///
+/// ```no_run
+/// use erl_dist::connection::ConnectionRegistry;
+/// use erl_dist::handshake::{ClientSideHandshake, ServerSideHandshake};
+/// use erl_dist::node::{Creation, LocalNode};
+/// use smol::net::TcpStream;
+///
+/// # async fn run(incoming: TcpStream, outgoing: TcpStream) -> Result<(), Box<dyn std::error::Error>> {
+/// let local_node = LocalNode::new("foo@localhost".parse()?, Creation::random());
+/// let registry = ConnectionRegistry::new(local_node.name.clone());
+///
+/// // Server side.
+/// let mut handshake = ServerSideHandshake::new(incoming, local_node.clone(), "cookie");
+/// if let Some(peer_name) = handshake.execute_recv_name().await? {
+///     let (status, pending) = registry.accept(&peer_name);
+///     let (connection, peer_node) = handshake.execute_rest(status).await?;
+///     let _active = pending.expect("ok status").complete();
+/// }
+///
+/// // Client side.
+/// let peer_name = "bar@localhost".parse()?;
+/// if let Some(pending) = registry.connect(&peer_name) {
+///     let mut handshake = ClientSideHandshake::new(outgoing, local_node, "cookie");
+///     let version = erl_dist::LOWEST_DISTRIBUTION_PROTOCOL_VERSION;
+///     let status = handshake.execute_send_name(version).await?;
+///     let do_continue = pending.should_continue(&status);
+///     let (connection, peer_node) = handshake.execute_rest(do_continue).await?;
+///     let _active = pending.complete();
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ConnectionRegistry {
+    local_name: NodeName,
+    inner: Arc<Mutex<RegistryInner>>,
+}
+
+#[derive(Debug, Default)]
+struct RegistryInner {
+    next_id: u64,
+    entries: HashMap<NodeName, RegistryEntry>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct RegistryEntry {
+    id: u64,
+    state: RegistryEntryState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RegistryEntryState {
+    Outgoing,
+    Incoming,
+    Active,
+}
+
+impl ConnectionRegistry {
+    /// Makes a new [`ConnectionRegistry`] instance for the given local node.
+    pub fn new(local_name: NodeName) -> Self {
+        Self {
+            local_name,
+            inner: Arc::default(),
+        }
+    }
+
+    /// Returns the name of the local node.
+    pub fn local_name(&self) -> &NodeName {
+        &self.local_name
+    }
+
+    /// Starts an outgoing connection attempt to the given node.
+    ///
+    /// Returns `None` if there is already a connection or an ongoing attempt to the node.
+    pub fn connect(&self, peer_name: &NodeName) -> Option<PendingConnection> {
+        let mut inner = self.lock();
+        if inner.entries.contains_key(peer_name) {
+            return None;
+        }
+        Some(self.insert(&mut inner, peer_name, RegistryEntryState::Outgoing))
+    }
+
+    /// Decides the status to reply to an incoming connection from the given node.
+    ///
+    /// If the status allows the handshake to continue, a [`PendingConnection`] is also returned.
+    /// An ongoing incoming attempt from the same node makes the new one rejected with [`HandshakeStatus::Nok`].
+    pub fn accept(&self, peer_name: &NodeName) -> (HandshakeStatus, Option<PendingConnection>) {
+        let mut inner = self.lock();
+        let status = match inner.entries.get(peer_name).map(|entry| entry.state) {
+            None => HandshakeStatus::Ok,
+            Some(RegistryEntryState::Outgoing) => {
+                if self.local_name.to_string() > peer_name.to_string() {
+                    return (HandshakeStatus::Nok, None);
+                }
+                HandshakeStatus::OkSimultaneous
+            }
+            Some(RegistryEntryState::Incoming) => return (HandshakeStatus::Nok, None),
+            Some(RegistryEntryState::Active) => {
+                // The active connection is replaced when the new one completes.
+                let id = inner.next_id;
+                inner.next_id += 1;
+                let pending = PendingConnection {
+                    registry: self.clone(),
+                    peer_name: peer_name.clone(),
+                    id,
+                };
+                return (HandshakeStatus::Alive, Some(pending));
+            }
+        };
+        let pending = self.insert(&mut inner, peer_name, RegistryEntryState::Incoming);
+        (status, Some(pending))
+    }
+
+    /// Returns `true` if a connection to the given node is active.
+    pub fn is_active(&self, peer_name: &NodeName) -> bool {
+        self.lock()
+            .entries
+            .get(peer_name)
+            .is_some_and(|entry| entry.state == RegistryEntryState::Active)
+    }
+
+    fn insert(
+        &self,
+        inner: &mut RegistryInner,
+        peer_name: &NodeName,
+        state: RegistryEntryState,
+    ) -> PendingConnection {
+        let id = inner.next_id;
+        inner.next_id += 1;
+        inner
+            .entries
+            .insert(peer_name.clone(), RegistryEntry { id, state });
+        PendingConnection {
+            registry: self.clone(),
+            peer_name: peer_name.clone(),
+            id,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, RegistryInner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn remove(&self, peer_name: &NodeName, id: u64) {
+        let mut inner = self.lock();
+        if inner
+            .entries
+            .get(peer_name)
+            .is_some_and(|entry| entry.id == id)
+        {
+            inner.entries.remove(peer_name);
+        }
+    }
+}
+
+/// Connection attempt registered in a [`ConnectionRegistry`].
+///
+/// The attempt is removed from the registry when this is dropped without calling
+/// [`PendingConnection::complete()`] (e.g., the handshake failed).
+#[derive(Debug)]
+pub struct PendingConnection {
+    registry: ConnectionRegistry,
+    peer_name: NodeName,
+    id: u64,
+}
+
+impl PendingConnection {
+    /// Returns the name of the peer node.
+    pub fn peer_name(&self) -> &NodeName {
+        &self.peer_name
+    }
+
+    /// Returns the `do_continue` argument of [`ClientSideHandshake::execute_rest()`] for the status
+    /// replied by the peer.
+    ///
+    /// This returns `false` if this attempt has been superseded by an incoming connection from the peer.
+    pub fn should_continue(&self, status: &HandshakeStatus) -> bool {
+        let superseded = self
+            .registry
+            .lock()
+            .entries
+            .get(&self.peer_name)
+            .is_none_or(|entry| entry.id != self.id);
+        match status {
+            HandshakeStatus::Nok | HandshakeStatus::NotAllowed => false,
+            _ => !superseded,
+        }
+    }
+
+    /// Marks the connection as active after the handshake has completed.
+    ///
+    /// If there is another active connection to the peer (i.e., [`HandshakeStatus::Alive`] was replied),
+    /// it is replaced by this one.
+    pub fn complete(self) -> ActiveConnection {
+        // A new ID is assigned so that dropping `self` doesn't remove the active entry.
+        let mut inner = self.registry.lock();
+        let id = inner.next_id;
+        inner.next_id += 1;
+        let entry = RegistryEntry {
+            id,
+            state: RegistryEntryState::Active,
+        };
+        inner.entries.insert(self.peer_name.clone(), entry);
+        std::mem::drop(inner);
+        ActiveConnection {
+            registry: self.registry.clone(),
+            peer_name: self.peer_name.clone(),
+            id,
+        }
+    }
+}
+
+impl Drop for PendingConnection {
+    fn drop(&mut self) {
+        self.registry.remove(&self.peer_name, self.id);
+    }
+}
+
+/// Active connection registered in a [`ConnectionRegistry`].
+///
+/// The connection is removed from the registry when this is dropped.
+#[derive(Debug)]
+pub struct ActiveConnection {
+    registry: ConnectionRegistry,
+    peer_name: NodeName,
+    id: u64,
+}
+
+impl ActiveConnection {
+    /// Returns the name of the peer node.
+    pub fn peer_name(&self) -> &NodeName {
+        &self.peer_name
+    }
+
+    /// Returns `false` if this connection has been replaced by a newer one.
+    ///
+    /// A replaced connection is stale and should be closed.
+    pub fn is_current(&self) -> bool {
+        self.registry
+            .lock()
+            .entries
+            .get(&self.peer_name)
+            .is_some_and(|entry| entry.id == self.id)
+    }
+}
+
+impl Drop for ActiveConnection {
+    fn drop(&mut self) {
+        self.registry.remove(&self.peer_name, self.id);
+    }
+}
+
+/// Selects the highest distribution protocol version supported by both this crate and the given node.
+pub(crate) fn select_version(entry: &NodeEntry) -> Result<u16, ConnectError> {
+    let highest = entry
+        .highest_version
+        .min(HIGHEST_DISTRIBUTION_PROTOCOL_VERSION);
+    let lowest = entry
+        .lowest_version
+        .max(LOWEST_DISTRIBUTION_PROTOCOL_VERSION);
+    if highest < lowest {
+        return Err(ConnectError::IncompatibleVersion {
+            lowest_version: entry.lowest_version,
+            highest_version: entry.highest_version,
+        });
+    }
+    Ok(highest)
+}
+
+/// Possible errors of [`connect()`].
+#[derive(Debug)]
+#[non_exhaustive]
+#[allow(missing_docs)]
+pub enum ConnectError {
+    /// Failed to connect to EPMD on the peer host.
+    EpmdConnect(std::io::Error),
+
+    /// EPMD request failure.
+    Epmd(EpmdError),
+
+    /// The peer node is not registered in EPMD.
+    NodeNotFound { peer_name: NodeName },
+
+    /// The peer node doesn't support any distribution protocol version that this crate can handle.
+    IncompatibleVersion {
+        lowest_version: u16,
+        highest_version: u16,
+    },
+
+    /// Failed to connect to the distribution port of the peer node.
+    Connect(std::io::Error),
+
+    /// Handshake failure.
+    Handshake(HandshakeError),
+}
+
+impl std::fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::EpmdConnect(error) => write!(f, "failed to connect to EPMD: {error}"),
+            Self::Epmd(error) => write!(f, "EPMD request failed: {error}"),
+            Self::NodeNotFound { peer_name } => {
+                write!(f, "node {peer_name} is not registered in EPMD")
+            }
+            Self::IncompatibleVersion {
+                lowest_version,
+                highest_version,
+            } => write!(
+                f,
+                "peer supports distribution protocol versions {lowest_version}..={highest_version}, but this crate supports {LOWEST_DISTRIBUTION_PROTOCOL_VERSION}..={HIGHEST_DISTRIBUTION_PROTOCOL_VERSION}"
+            ),
+            Self::Connect(error) => write!(f, "failed to connect to the peer node: {error}"),
+            Self::Handshake(error) => write!(f, "handshake failed: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for ConnectError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::EpmdConnect(error) | Self::Connect(error) => Some(error),
+            Self::Epmd(error) => Some(error),
+            Self::Handshake(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<EpmdError> for ConnectError {
+    fn from(value: EpmdError) -> Self {
+        Self::Epmd(value)
+    }
+}
+
+impl From<HandshakeError> for ConnectError {
+    fn from(value: HandshakeError) -> Self {
+        Self::Handshake(value)
+    }
+}
+
+/// Possible errors of [`DistListener`].
+#[derive(Debug)]
+#[non_exhaustive]
+#[allow(missing_docs)]
+pub enum AcceptError {
+    /// Failed to accept a connection.
+    Io(std::io::Error),
+
+    /// Handshake failure.
+    Handshake(HandshakeError),
+}
+
+impl std::fmt::Display for AcceptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "failed to accept a connection: {error}"),
+            Self::Handshake(error) => write!(f, "handshake failed: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for AcceptError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            Self::Handshake(error) => Some(error),
+        }
+    }
+}
+
+impl From<HandshakeError> for AcceptError {
+    fn from(value: HandshakeError) -> Self {
+        Self::Handshake(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handshake::{HandshakeStatus, ServerSideHandshake};
+    use crate::message::Message;
+    use crate::node::Creation;
+    use crate::term::{Atom, Pid};
+    use futures::{AsyncReadExt as _, AsyncWriteExt as _};
+    use smol::net::{TcpListener, TcpStream};
+
+    // Serves a single `PORT_PLEASE2_REQ` request like EPMD.
+    async fn serve_epmd(listener: &TcpListener, entry: Option<&NodeEntry>) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut buf = [0; 2];
+        stream.read_exact(&mut buf).await.unwrap();
+        let mut request = vec![0; usize::from(u16::from_be_bytes(buf))];
+        stream.read_exact(&mut request).await.unwrap();
+
+        let mut response = vec![119];
+        if let Some(entry) = entry {
+            response.push(0);
+            response.extend_from_slice(&entry.port.to_be_bytes());
+            response.push(77);
+            response.push(0);
+            response.extend_from_slice(&entry.highest_version.to_be_bytes());
+            response.extend_from_slice(&entry.lowest_version.to_be_bytes());
+            response.extend_from_slice(&(entry.name.len() as u16).to_be_bytes());
+            response.extend_from_slice(entry.name.as_bytes());
+            response.extend_from_slice(&0u16.to_be_bytes());
+        } else {
+            response.push(1);
+        }
+        stream.write_all(&response).await.unwrap();
+    }
+
+    #[test]
+    fn connect_works() {
+        smol::block_on(async {
+            let epmd = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let epmd_port = epmd.local_addr().unwrap().port();
+            let dist = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let entry = NodeEntry::new("bar", dist.local_addr().unwrap().port());
+            let tcp_connect = |host: String, port| async move {
+                let port = if port == DEFAULT_EPMD_PORT {
+                    epmd_port
+                } else {
+                    port
+                };
+                TcpStream::connect((host.as_str(), port)).await
+            };
+
+            let local_node = LocalNode::new("foo@127.0.0.1".parse().unwrap(), Creation::random());
+            let peer_name: NodeName = "bar@127.0.0.1".parse().unwrap();
+            let pid = Pid::new("foo@127.0.0.1", 0, 0, 0);
+            let msg = Message::send(pid, Atom::from("hello").into());
+            let client = async {
+                let (mut tx, _, peer_node) =
+                    connect(local_node, &peer_name, crate::tests::COOKIE, tcp_connect)
+                        .await
+                        .unwrap();
+                assert_eq!(peer_node.name, peer_name);
+                tx.send(msg.clone()).await.unwrap();
+            };
+            let server = async {
+                serve_epmd(&epmd, Some(&entry)).await;
+                let (stream, _) = dist.accept().await.unwrap();
+                let local_node =
+                    LocalNode::new("bar@127.0.0.1".parse().unwrap(), Creation::random());
+                let flags = local_node.flags;
+                let mut handshake =
+                    ServerSideHandshake::new(stream, local_node, crate::tests::COOKIE);
+                handshake.execute_recv_name().await.unwrap();
+                let (stream, peer_node) =
+                    handshake.execute_rest(HandshakeStatus::Ok).await.unwrap();
+                let (_, mut rx) = crate::message::channel(stream, flags & peer_node.flags);
+                rx.recv().await.unwrap()
+            };
+            let ((), received) = futures::future::join(client, server).await;
+            assert_eq!(received, msg);
+
+            // Unregistered node.
+            let local_node = LocalNode::new("foo@127.0.0.1".parse().unwrap(), Creation::random());
+            let (result, ()) = futures::future::join(
+                connect(local_node, &peer_name, crate::tests::COOKIE, tcp_connect),
+                serve_epmd(&epmd, None),
+            )
+            .await;
+            assert!(matches!(result, Err(ConnectError::NodeNotFound { .. })));
+        });
+    }
+
+    #[test]
+    fn dist_listener_works() {
+        smol::block_on(async {
+            let epmd = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let epmd_addr = epmd.local_addr().unwrap();
+            let listener = bind_tcp("127.0.0.1".parse().unwrap(), 0).unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let listener = TcpListener::try_from(listener).unwrap();
+
+            // An occupied port is skipped.
+            assert!(bind_tcp("127.0.0.1".parse().unwrap(), port).is_err());
+            if let Some(max) = port.checked_add(10) {
+                let other = bind_tcp("127.0.0.1".parse().unwrap(), port..=max).unwrap();
+                assert_ne!(other.local_addr().unwrap().port(), port);
+            }
+
+            let register = async {
+                let epmd_connection = TcpStream::connect(epmd_addr).await.unwrap();
+                DistListener::register(
+                    listener.incoming(),
+                    epmd_connection,
+                    NodeEntry::new("bar", port),
+                    "bar@127.0.0.1".parse().unwrap(),
+                    crate::tests::COOKIE,
+                )
+                .await
+                .unwrap()
+            };
+            let serve_register = async {
+                let (mut stream, _) = epmd.accept().await.unwrap();
+                let mut buf = [0; 2];
+                stream.read_exact(&mut buf).await.unwrap();
+                let mut request = vec![0; usize::from(u16::from_be_bytes(buf))];
+                stream.read_exact(&mut request).await.unwrap();
+                assert_eq!(request[0], 120);
+                assert_eq!(&request[1..3], &port.to_be_bytes());
+                stream.write_all(&[121, 0, 0, 3]).await.unwrap();
+                stream
+            };
+            let (mut dist_listener, mut registration) =
+                futures::future::join(register, serve_register).await;
+            assert_eq!(dist_listener.local_node().creation.get(), 3);
+            assert!(
+                dist_listener
+                    .local_node()
+                    .flags
+                    .contains(DistributionFlags::PUBLISHED)
+            );
+
+            // A failed handshake doesn't stop the listener.
+            let pid = Pid::new("foo@127.0.0.1", 0, 0, 0);
+            let msg = Message::send(pid, Atom::from("hello").into());
+            let client = async {
+                for cookie in ["wrong-cookie", crate::tests::COOKIE] {
+                    let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+                    let local_node =
+                        LocalNode::new("foo@127.0.0.1".parse().unwrap(), Creation::random());
+                    let flags = local_node.flags;
+                    let mut handshake = ClientSideHandshake::new(stream, local_node, cookie);
+                    handshake
+                        .execute_send_name(crate::LOWEST_DISTRIBUTION_PROTOCOL_VERSION)
+                        .await
+                        .unwrap();
+                    if let Ok((stream, peer_node)) = handshake.execute_rest(true).await {
+                        let (mut tx, _) = crate::message::channel(stream, flags & peer_node.flags);
+                        tx.send(msg.clone()).await.unwrap();
+                    }
+                }
+            };
+            let server = async {
+                assert!(matches!(
+                    dist_listener.next().await,
+                    Some(Err(AcceptError::Handshake(HandshakeError::CookieMismatch)))
+                ));
+                let (_, mut rx, peer_node) = dist_listener.next().await.unwrap().unwrap();
+                assert_eq!(peer_node.name.to_string(), "foo@127.0.0.1");
+                rx.recv().await.unwrap()
+            };
+            let ((), received) = futures::future::join(client, server).await;
+            assert_eq!(received, msg);
+
+            // Silent peers are disconnected after the handshake timeout.
+            dist_listener.set_handshake_timeout(Duration::from_millis(50), smol::Timer::after);
+            let _silent = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+            assert!(matches!(
+                dist_listener.next().await,
+                Some(Err(AcceptError::Handshake(HandshakeError::Timeout {
+                    phase: "recv_name"
+                })))
+            ));
+
+            // Dropping the listener closes the registration.
+            std::mem::drop(dist_listener);
+            let mut buf = Vec::new();
+            registration.read_to_end(&mut buf).await.unwrap();
+            assert!(buf.is_empty());
+        });
+    }
+
+    #[test]
+    fn simultaneous_connects_are_resolved() {
+        let a_name: NodeName = "a@localhost".parse().unwrap();
+        let b_name: NodeName = "b@localhost".parse().unwrap();
+        let a = ConnectionRegistry::new(a_name.clone());
+        let b = ConnectionRegistry::new(b_name.clone());
+
+        // Both nodes connect to each other at the same time.
+        let a_to_b = a.connect(&b_name).unwrap();
+        let b_to_a = b.connect(&a_name).unwrap();
+        assert!(a.connect(&b_name).is_none());
+
+        // `b` has the greater name, so its attempt wins.
+        let (status, pending) = b.accept(&a_name);
+        assert_eq!(status, HandshakeStatus::Nok);
+        assert!(pending.is_none());
+        assert!(!a_to_b.should_continue(&status));
+
+        let (status, a_from_b) = a.accept(&b_name);
+        assert_eq!(status, HandshakeStatus::OkSimultaneous);
+        assert!(b_to_a.should_continue(&status));
+        std::mem::drop(a_to_b);
+
+        let a_active = a_from_b.unwrap().complete();
+        let b_active = b_to_a.complete();
+        assert!(a.is_active(&b_name));
+        assert!(b.is_active(&a_name));
+
+        // `a` restarts and connects to `b` that still has the stale connection.
+        std::mem::drop(a_active);
+        let a = ConnectionRegistry::new(a_name.clone());
+        let a_to_b = a.connect(&b_name).unwrap();
+        let (status, b_from_a) = b.accept(&a_name);
+        assert_eq!(status, HandshakeStatus::Alive);
+        assert!(a_to_b.should_continue(&status));
+        assert!(b_active.is_current());
+        let b_new_active = b_from_a.unwrap().complete();
+        assert!(!b_active.is_current());
+        assert!(b_new_active.is_current());
+
+        // Dropping the stale connection doesn't affect the new one.
+        std::mem::drop(b_active);
+        assert!(b.is_active(&a_name));
+        std::mem::drop(b_new_active);
+        assert!(!b.is_active(&a_name));
+    }
+
+    #[test]
+    fn select_version_works() {
+        let mut entry = NodeEntry::new("foo", 0);
+        assert_eq!(
+            select_version(&entry).unwrap(),
+            HIGHEST_DISTRIBUTION_PROTOCOL_VERSION
+        );
+
+        entry.lowest_version = HIGHEST_DISTRIBUTION_PROTOCOL_VERSION + 1;
+        entry.highest_version = HIGHEST_DISTRIBUTION_PROTOCOL_VERSION + 2;
+        assert!(matches!(
+            select_version(&entry),
+            Err(ConnectError::IncompatibleVersion { .. })
+        ));
+    }
+}
@@ -5,7 +5,7 @@
 //!
 //! See [EPMD Protocol (Erlang Official Doc)](https://www.erlang.org/doc/apps/erts/erl_dist_protocol.html#epmd-protocol)
 //! for more details.
-use crate::io::Connection;
+use crate::io::{ByteReader, Connection};
 use crate::node::Creation;
 #[cfg(doc)]
 use crate::node::NodeName;
@@ -110,6 +110,9 @@ pub enum EpmdError {
     /// Malformed `NAMES_RESP` line.
     MalformedNamesResponse { line: String },
 
+    /// Transport protocol that EPMD doesn't define.
+    UnsupportedProtocol { protocol: TransportProtocol },
+
     /// I/O error.
     Io(std::io::Error),
 }
@@ -147,6 +150,12 @@ impl std::fmt::Display for EpmdError {
                     "found a malformed NAMES_RESP line: expected_format=\"name {{NAME}} at port {{PORT}}\", actual_line={line:?}"
                 )
             }
+            Self::UnsupportedProtocol { protocol } => {
+                write!(
+                    f,
+                    "EPMD doesn't support the transport protocol {protocol:?}"
+                )
+            }
             Self::Io(error) => write!(f, "{error}"),
         }
     }
@@ -169,6 +178,8 @@ impl From<std::io::Error> for EpmdError {
 }
 
 /// EPMD client.
+///
+/// This is a thin async driver of [`EpmdExchange`].
 #[derive(Debug)]
 pub struct EpmdClient<T> {
     connection: Connection<T>,
@@ -192,107 +203,30 @@ where
     /// The connection created to the EPMD must be kept as long as the node is a distributed node.
     /// When the connection is closed, the node is automatically unregistered from the EPMD.
     pub async fn register(mut self, node: NodeEntry) -> Result<(T, Creation), EpmdError> {
-        // Request.
-        let size = 1 + node.bytes_len();
-        let size = u16::try_from(size).map_err(|_| EpmdError::TooLongRequest { size })?;
-        self.connection.write_u16(size).await?;
-        self.connection.write_u8(TAG_ALIVE2_REQ).await?;
-        self.connection.write_u16(node.port).await?;
-        self.connection.write_u8(node.node_type.into()).await?;
-        self.connection.write_u8(node.protocol.into()).await?;
-        self.connection.write_u16(node.highest_version).await?;
-        self.connection.write_u16(node.lowest_version).await?;
-        self.connection.write_u16(node.name.len() as u16).await?;
-        self.connection.write_all(node.name.as_bytes()).await?;
-        self.connection.write_u16(node.extra.len() as u16).await?;
-        self.connection.write_all(&node.extra).await?;
-        self.connection.flush().await?;
-
-        // Response.
-        match self.connection.read_u8().await? {
-            TAG_ALIVE2_RESP => {
-                match self.connection.read_u8().await? {
-                    0 => {}
-                    code => return Err(EpmdError::RegisterNodeError { code }),
-                }
-
-                let creation = Creation::new(u32::from(self.connection.read_u16().await?));
-                Ok((self.connection.into_inner(), creation))
-            }
-            TAG_ALIVE2_X_RESP => {
-                match self.connection.read_u8().await? {
-                    0 => {}
-                    code => return Err(EpmdError::RegisterNodeError { code }),
-                }
-
-                let creation = Creation::new(self.connection.read_u32().await?);
-                Ok((self.connection.into_inner(), creation))
-            }
-            tag => Err(EpmdError::UnknownResponseTag {
-                request: "ALIVE2_REQ",
-                tag,
-            }),
-        }
+        let exchange = EpmdExchange::register(&node)?;
+        let EpmdResponse::Registered(creation) = self.execute(exchange).await? else {
+            unreachable!();
+        };
+        Ok((self.connection.into_inner(), creation))
     }
 
     /// Gets all registered nodes (name and port pairs) from EPMD.
     pub async fn get_names(mut self) -> Result<Vec<(String, u16)>, EpmdError> {
-        // Request.
-        self.connection.write_u16(1).await?; // Length
-        self.connection.write_u8(TAG_NAMES_REQ).await?;
-        self.connection.flush().await?;
-
-        // Response.
-        let _epmd_port = self.connection.read_u32().await?;
-        let node_info_text = self.connection.read_string().await?;
-
-        node_info_text
-            .split('\n')
-            .filter(|s| !s.is_empty())
-            .map(|line| NodeNameAndPort::from_str(line).map(|x| (x.name, x.port)))
-            .collect()
+        let EpmdResponse::Names(names) = self.execute(EpmdExchange::get_names()).await? else {
+            unreachable!();
+        };
+        Ok(names)
     }
 
     /// Queries the node which has the given name to EPMD.
     ///
     /// If the node has not been registered in the connected EPMD, this method will return `None`.
     pub async fn get_node(mut self, node_name: &str) -> Result<Option<NodeEntry>, EpmdError> {
-        // Request.
-        let size = 1 + node_name.len();
-        let size = u16::try_from(size).map_err(|_| EpmdError::TooLongRequest { size })?;
-        self.connection.write_u16(size).await?;
-        self.connection.write_u8(TAG_PORT_PLEASE2_REQ).await?;
-        self.connection.write_all(node_name.as_bytes()).await?;
-        self.connection.flush().await?;
-
-        // Response.
-        let tag = self.connection.read_u8().await?;
-        if tag != TAG_PORT2_RESP {
-            return Err(EpmdError::UnknownResponseTag {
-                request: "NAMES_REQ",
-                tag,
-            });
-        }
-
-        match self.connection.read_u8().await? {
-            0 => {}
-            1 => {
-                return Ok(None);
-            }
-            code => {
-                return Err(EpmdError::GetNodeEntryError { code });
-            }
-        }
-
-        Ok(Some(NodeEntry {
-            port: self.connection.read_u16().await?,
-            node_type: self.connection.read_u8().await?.into(),
-            protocol: self.connection.read_u8().await?.into(),
-            highest_version: self.connection.read_u16().await?,
-            lowest_version: self.connection.read_u16().await?,
-            name: self.connection.read_u16_string().await?,
-            extra: self.connection.read_u16_bytes().await?,
-        }))
+        let exchange = EpmdExchange::get_node(node_name)?;
+        let EpmdResponse::NodeEntry(node) = self.execute(exchange).await? else {
+            unreachable!();
+        };
+        Ok(node)
     }
 
     /// Kills EPMD.
@@ -302,13 +236,9 @@ where
     ///
     /// If EPMD is killed, this method returns `"OK"`.
     pub async fn kill(mut self) -> Result<String, EpmdError> {
-        // Request.
-        self.connection.write_u16(1).await?;
-        self.connection.write_u8(TAG_KILL_REQ).await?;
-        self.connection.flush().await?;
-
-        // Response.
-        let result = self.connection.read_string().await?;
+        let EpmdResponse::Killed(result) = self.execute(EpmdExchange::kill()).await? else {
+            unreachable!();
+        };
         Ok(result)
     }
 
@@ -330,16 +260,237 @@ where
     /// "old/unused name ${NODE_NAME} at port ${PORT}, fd = ${FD}\n"
     /// ```
     pub async fn dump(mut self) -> Result<String, EpmdError> {
-        // Request.
-        self.connection.write_u16(1).await?;
-        self.connection.write_u8(TAG_DUMP_REQ).await?;
+        let EpmdResponse::Dump(info) = self.execute(EpmdExchange::dump()).await? else {
+            unreachable!();
+        };
+        Ok(info)
+    }
+
+    async fn execute(&mut self, mut exchange: EpmdExchange) -> Result<EpmdResponse, EpmdError> {
+        self.connection.write_all(&exchange.take_output()).await?;
         self.connection.flush().await?;
 
-        // Response.
-        let _epmd_port = self.connection.read_u32().await?;
-        let info = self.connection.read_string().await?;
-        Ok(info)
+        let mut buf = [0; 1024];
+        loop {
+            let n = self.connection.read(&mut buf).await?;
+            if n == 0 {
+                return exchange.feed_eof();
+            }
+            if let Some(response) = exchange.feed(&buf[..n])? {
+                return Ok(response);
+            }
+        }
+    }
+}
+
+/// Response of an EPMD request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EpmdResponse {
+    /// Response of [`EpmdExchange::register()`].
+    Registered(Creation),
+
+    /// Response of [`EpmdExchange::get_names()`].
+    Names(Vec<(String, u16)>),
+
+    /// Response of [`EpmdExchange::get_node()`].
+    NodeEntry(Option<NodeEntry>),
+
+    /// Response of [`EpmdExchange::kill()`].
+    Killed(String),
+
+    /// Response of [`EpmdExchange::dump()`].
+    Dump(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EpmdRequest {
+    Register,
+    Names,
+    GetNode,
+    Kill,
+    Dump,
+}
+
+/// Sans-IO state machine of a request/response exchange with EPMD.
+///
+/// This produces the request bytes via [`EpmdExchange::take_output()`] and parses the response
+/// bytes given via [`EpmdExchange::feed()`].
+/// Note that the responses of some requests (e.g., [`EpmdExchange::get_names()`]) end when EPMD
+/// closes the connection, so [`EpmdExchange::feed_eof()`] needs to be called in that case.
+///
+/// See [`EpmdClient`] for the details of each request.
+#[derive(Debug)]
+pub struct EpmdExchange {
+    request: EpmdRequest,
+    input: Vec<u8>,
+    output: Vec<u8>,
+}
+
+impl EpmdExchange {
+    /// Makes an `ALIVE2_REQ` exchange to register a node.
+    pub fn register(node: &NodeEntry) -> Result<Self, EpmdError> {
+        let size = 1 + node.bytes_len();
+        let size = u16::try_from(size).map_err(|_| EpmdError::TooLongRequest { size })?;
+        let mut output = Vec::with_capacity(2 + usize::from(size));
+        output.extend_from_slice(&size.to_be_bytes());
+        output.push(TAG_ALIVE2_REQ);
+        output.extend_from_slice(&node.port.to_be_bytes());
+        output.push(node.node_type.into());
+        output.push(u8::try_from(node.protocol)?);
+        output.extend_from_slice(&node.highest_version.to_be_bytes());
+        output.extend_from_slice(&node.lowest_version.to_be_bytes());
+        output.extend_from_slice(&(node.name.len() as u16).to_be_bytes());
+        output.extend_from_slice(node.name.as_bytes());
+        output.extend_from_slice(&(node.extra.len() as u16).to_be_bytes());
+        output.extend_from_slice(&node.extra);
+        Ok(Self::new(EpmdRequest::Register, output))
+    }
+
+    /// Makes a `NAMES_REQ` exchange to get all registered nodes.
+    pub fn get_names() -> Self {
+        Self::new(EpmdRequest::Names, vec![0, 1, TAG_NAMES_REQ])
+    }
+
+    /// Makes a `PORT_PLEASE2_REQ` exchange to query a node.
+    pub fn get_node(node_name: &str) -> Result<Self, EpmdError> {
+        let size = 1 + node_name.len();
+        let size = u16::try_from(size).map_err(|_| EpmdError::TooLongRequest { size })?;
+        let mut output = Vec::with_capacity(2 + usize::from(size));
+        output.extend_from_slice(&size.to_be_bytes());
+        output.push(TAG_PORT_PLEASE2_REQ);
+        output.extend_from_slice(node_name.as_bytes());
+        Ok(Self::new(EpmdRequest::GetNode, output))
+    }
+
+    /// Makes a `KILL_REQ` exchange to kill EPMD.
+    pub fn kill() -> Self {
+        Self::new(EpmdRequest::Kill, vec![0, 1, TAG_KILL_REQ])
+    }
+
+    /// Makes a `DUMP_REQ` exchange to dump all data from EPMD.
+    pub fn dump() -> Self {
+        Self::new(EpmdRequest::Dump, vec![0, 1, TAG_DUMP_REQ])
+    }
+
+    fn new(request: EpmdRequest, output: Vec<u8>) -> Self {
+        Self {
+            request,
+            input: Vec::new(),
+            output,
+        }
+    }
+
+    /// Takes the request bytes to be sent to EPMD.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    /// Appends response bytes received from EPMD and returns the response if it is complete.
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<Option<EpmdResponse>, EpmdError> {
+        self.input.extend_from_slice(bytes);
+        let result = match self.request {
+            EpmdRequest::Register => parse_register_response(&self.input),
+            EpmdRequest::GetNode => parse_get_node_response(&self.input),
+            EpmdRequest::Names | EpmdRequest::Kill | EpmdRequest::Dump => return Ok(None),
+        };
+        match result {
+            Err(EpmdError::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+            result => result.map(Some),
+        }
+    }
+
+    /// Notifies that EPMD has closed the connection and returns the response.
+    pub fn feed_eof(self) -> Result<EpmdResponse, EpmdError> {
+        match self.request {
+            EpmdRequest::Register => parse_register_response(&self.input),
+            EpmdRequest::GetNode => parse_get_node_response(&self.input),
+            EpmdRequest::Names => {
+                let mut reader = ByteReader(&self.input);
+                let _epmd_port = reader.read_u32()?;
+                utf8_string(reader.0)?
+                    .split('\n')
+                    .filter(|s| !s.is_empty())
+                    .map(|line| NodeNameAndPort::from_str(line).map(|x| (x.name, x.port)))
+                    .collect::<Result<_, _>>()
+                    .map(EpmdResponse::Names)
+            }
+            EpmdRequest::Kill => Ok(EpmdResponse::Killed(utf8_string(&self.input)?)),
+            EpmdRequest::Dump => {
+                let mut reader = ByteReader(&self.input);
+                let _epmd_port = reader.read_u32()?;
+                Ok(EpmdResponse::Dump(utf8_string(reader.0)?))
+            }
+        }
+    }
+}
+
+fn parse_register_response(input: &[u8]) -> Result<EpmdResponse, EpmdError> {
+    let mut reader = ByteReader(input);
+    let tag = reader.read_u8()?;
+    if !matches!(tag, TAG_ALIVE2_RESP | TAG_ALIVE2_X_RESP) {
+        return Err(EpmdError::UnknownResponseTag {
+            request: "ALIVE2_REQ",
+            tag,
+        });
+    }
+    match reader.read_u8()? {
+        0 => {}
+        code => return Err(EpmdError::RegisterNodeError { code }),
+    }
+    let creation = if tag == TAG_ALIVE2_RESP {
+        Creation::new(u32::from(reader.read_u16()?))
+    } else {
+        Creation::new(reader.read_u32()?)
+    };
+    Ok(EpmdResponse::Registered(creation))
+}
+
+fn parse_get_node_response(input: &[u8]) -> Result<EpmdResponse, EpmdError> {
+    let mut reader = ByteReader(input);
+    let tag = reader.read_u8()?;
+    if tag != TAG_PORT2_RESP {
+        return Err(EpmdError::UnknownResponseTag {
+            request: "PORT_PLEASE2_REQ",
+            tag,
+        });
     }
+    match reader.read_u8()? {
+        0 => {}
+        1 => {
+            return Ok(EpmdResponse::NodeEntry(None));
+        }
+        code => {
+            return Err(EpmdError::GetNodeEntryError { code });
+        }
+    }
+
+    let port = reader.read_u16()?;
+    let node_type = reader.read_u8()?.into();
+    let protocol = reader.read_u8()?.into();
+    let highest_version = reader.read_u16()?;
+    let lowest_version = reader.read_u16()?;
+    let n = usize::from(reader.read_u16()?);
+    let name = utf8_string(reader.read_bytes(n)?)?;
+    let n = usize::from(reader.read_u16()?);
+    let extra = reader.read_bytes(n)?.to_vec();
+    Ok(EpmdResponse::NodeEntry(Some(NodeEntry {
+        name,
+        port,
+        node_type,
+        protocol,
+        highest_version,
+        lowest_version,
+        extra,
+    })))
+}
+
+fn utf8_string(bytes: &[u8]) -> std::io::Result<String> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "stream did not contain valid UTF-8",
+        )
+    })
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
@@ -373,6 +524,13 @@ pub enum TransportProtocol {
     /// TCP/IPv4.
     TcpIpV4,
 
+    /// Unix domain socket (`local` address family).
+    ///
+    /// As EPMD doesn't define a value for this protocol (nodes in this family don't register in EPMD),
+    /// it can't be converted into a number.
+    /// See [`crate::local`] for the details.
+    Local,
+
     /// Other protocol.
     Other(u8),
 }
@@ -386,11 +544,14 @@ impl From<u8> for TransportProtocol {
     }
 }
 
-impl From<TransportProtocol> for u8 {
-    fn from(v: TransportProtocol) -> Self {
+impl TryFrom<TransportProtocol> for u8 {
+    type Error = EpmdError;
+
+    fn try_from(v: TransportProtocol) -> Result<Self, Self::Error> {
         match v {
-            TransportProtocol::TcpIpV4 => 0,
-            TransportProtocol::Other(v) => v,
+            TransportProtocol::TcpIpV4 => Ok(0),
+            TransportProtocol::Local => Err(EpmdError::UnsupportedProtocol { protocol: v }),
+            TransportProtocol::Other(v) => Ok(v),
         }
     }
 }
@@ -433,6 +594,63 @@ impl From<NodeType> for u8 {
 mod tests {
     use super::*;
 
+    #[test]
+    fn epmd_exchange_works() {
+        // ALIVE2_REQ
+        let mut exchange = EpmdExchange::register(&NodeEntry::new("foo", 3000)).unwrap();
+        let output = exchange.take_output();
+        assert_eq!(&output[..3], &[0, 16, TAG_ALIVE2_REQ]);
+        assert_eq!(exchange.feed(&[TAG_ALIVE2_X_RESP, 0, 0]).unwrap(), None);
+        assert_eq!(
+            exchange.feed(&[0, 0, 5]).unwrap(),
+            Some(EpmdResponse::Registered(Creation::new(5)))
+        );
+
+        // PORT_PLEASE2_REQ
+        let mut exchange = EpmdExchange::get_node("foo").unwrap();
+        assert_eq!(exchange.take_output(), b"\x00\x04zfoo");
+        let mut response = vec![TAG_PORT2_RESP, 0, 11, 184, 77, 0, 0, 6, 0, 6, 0, 3];
+        response.extend_from_slice(b"foo\x00\x00");
+        let (first, second) = response.split_at(7);
+        assert_eq!(exchange.feed(first).unwrap(), None);
+        let Some(EpmdResponse::NodeEntry(Some(node))) = exchange.feed(second).unwrap() else {
+            panic!()
+        };
+        assert_eq!(node, NodeEntry::new("foo", 3000));
+
+        let mut exchange = EpmdExchange::get_node("foo").unwrap();
+        assert_eq!(
+            exchange.feed(&[TAG_PORT2_RESP, 1]).unwrap(),
+            Some(EpmdResponse::NodeEntry(None))
+        );
+
+        // NAMES_REQ
+        let mut exchange = EpmdExchange::get_names();
+        assert_eq!(exchange.take_output(), [0, 1, TAG_NAMES_REQ]);
+        assert_eq!(exchange.feed(&[0, 0, 17, 17]).unwrap(), None);
+        assert_eq!(exchange.feed(b"name foo at port 3000\n").unwrap(), None);
+        assert_eq!(
+            exchange.feed_eof().unwrap(),
+            EpmdResponse::Names(vec![("foo".to_owned(), 3000)])
+        );
+
+        // Truncated response.
+        let mut exchange = EpmdExchange::get_node("foo").unwrap();
+        assert_eq!(exchange.feed(&[TAG_PORT2_RESP]).unwrap(), None);
+        assert!(matches!(exchange.feed_eof(), Err(EpmdError::Io(_))));
+
+        // Local nodes can't be registered.
+        let mut node = NodeEntry::new("foo", 3000);
+        node.protocol = TransportProtocol::Local;
+        assert!(matches!(
+            EpmdExchange::register(&node),
+            Err(EpmdError::UnsupportedProtocol {
+                protocol: TransportProtocol::Local
+            })
+        ));
+        assert_eq!(TransportProtocol::from(1), TransportProtocol::Other(1));
+    }
+
     #[test]
     fn epmd_client_works() {
         let node_name = "epmd_client_works";
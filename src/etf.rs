@@ -0,0 +1,449 @@
+use eetf::DecodeError;
+
+pub const VERSION_MAGIC: u8 = 131;
+
+const NEW_FLOAT_EXT: u8 = 70;
+const BIT_BINARY_EXT: u8 = 77;
+const ATOM_CACHE_REF: u8 = 82;
+const NEW_PID_EXT: u8 = 88;
+const NEW_PORT_EXT: u8 = 89;
+const NEWER_REFERENCE_EXT: u8 = 90;
+pub const SMALL_INTEGER_EXT: u8 = 97;
+const INTEGER_EXT: u8 = 98;
+const FLOAT_EXT: u8 = 99;
+const ATOM_EXT: u8 = 100;
+const REFERENCE_EXT: u8 = 101;
+const PORT_EXT: u8 = 102;
+const PID_EXT: u8 = 103;
+pub const SMALL_TUPLE_EXT: u8 = 104;
+const LARGE_TUPLE_EXT: u8 = 105;
+const NIL_EXT: u8 = 106;
+const STRING_EXT: u8 = 107;
+const LIST_EXT: u8 = 108;
+const BINARY_EXT: u8 = 109;
+const SMALL_BIG_EXT: u8 = 110;
+const LARGE_BIG_EXT: u8 = 111;
+const NEW_FUN_EXT: u8 = 112;
+const EXPORT_EXT: u8 = 113;
+const NEW_REFERENCE_EXT: u8 = 114;
+const SMALL_ATOM_EXT: u8 = 115;
+const MAP_EXT: u8 = 116;
+const FUN_EXT: u8 = 117;
+const ATOM_UTF8_EXT: u8 = 118;
+const SMALL_ATOM_UTF8_EXT: u8 = 119;
+const V4_PORT_EXT: u8 = 120;
+
+/// Possible errors while processing encoded terms.
+#[derive(Debug)]
+pub enum EtfError {
+    /// Term nested deeper than the limit.
+    TermTooDeep { max_depth: usize },
+
+    /// Atom cache reference to an entry that has not been cached yet.
+    MissingAtomCacheEntry { index: usize },
+
+    /// Malformed term.
+    Decode(DecodeError),
+
+    /// Truncated or invalid input.
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for EtfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TermTooDeep { max_depth } => {
+                write!(f, "term nesting depth exceeds the limit of {max_depth}")
+            }
+            Self::MissingAtomCacheEntry { index } => {
+                write!(f, "atom cache entry {index} has not been cached")
+            }
+            Self::Decode(error) => write!(f, "{error}"),
+            Self::Io(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for EtfError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Decode(e) => Some(e),
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<DecodeError> for EtfError {
+    fn from(value: DecodeError) -> Self {
+        Self::Decode(value)
+    }
+}
+
+impl From<std::io::Error> for EtfError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+/// Atom found while copying terms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AtomBytes<'a> {
+    /// UTF-8 encoded atom text (`ATOM_UTF8_EXT` or `SMALL_ATOM_UTF8_EXT`).
+    Utf8(&'a [u8]),
+
+    /// Atom encoded by a deprecated Latin-1 tag, including the tag and the length field.
+    Latin1(&'a [u8]),
+
+    /// `ATOM_CACHE_REF` index.
+    CacheRef(u8),
+}
+
+/// Checks that the term at the head of `buf` starts with [`VERSION_MAGIC`] and is nested at most `max_depth` levels.
+///
+/// Returns the byte length of the term including the version magic.
+/// Atom cache references are rejected as they are only allowed after a distribution header.
+pub fn check_term(buf: &[u8], max_depth: usize) -> Result<usize, EtfError> {
+    let Some((&version, rest)) = buf.split_first() else {
+        return Err(
+            std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "unexpected eof").into(),
+        );
+    };
+    if version != VERSION_MAGIC {
+        return Err(DecodeError::UnsupportedVersion { version }.into());
+    }
+    let n = copy_term(rest, &mut Discard, max_depth, |atom, _| {
+        if matches!(atom, AtomBytes::CacheRef(_)) {
+            return Err(DecodeError::UnknownTag {
+                tag: ATOM_CACHE_REF,
+            });
+        }
+        Ok(())
+    })?;
+    Ok(1 + n)
+}
+
+/// Copies the term at the head of `input` into `out` while letting `on_atom` write every atom in it.
+///
+/// Returns the number of bytes consumed from `input`.
+/// If the term is nested more than `max_depth` levels, [`EtfError::TermTooDeep`] is returned.
+/// Note that `input` must not start with [`VERSION_MAGIC`].
+pub fn copy_term<O, F>(
+    input: &[u8],
+    out: &mut O,
+    max_depth: usize,
+    mut on_atom: F,
+) -> Result<usize, EtfError>
+where
+    O: Output,
+    F: FnMut(AtomBytes, &mut O) -> Result<(), DecodeError>,
+{
+    let mut copier = Copier {
+        input,
+        pos: 0,
+        out,
+        max_depth,
+        on_atom: &mut on_atom,
+    };
+    copier.copy_term()?;
+    Ok(copier.pos)
+}
+
+/// Writes `atom` in the normal (non-cached) form.
+///
+/// [`AtomBytes::CacheRef`] is written as is.
+pub fn write_atom_bytes<O: Output>(atom: AtomBytes, out: &mut O) {
+    match atom {
+        AtomBytes::Utf8(text) => {
+            if let Ok(n) = u8::try_from(text.len()) {
+                out.extend_from_slice(&[SMALL_ATOM_UTF8_EXT, n]);
+            } else {
+                out.push(ATOM_UTF8_EXT);
+                out.extend_from_slice(&(text.len() as u16).to_be_bytes());
+            }
+            out.extend_from_slice(text);
+        }
+        AtomBytes::Latin1(bytes) => out.extend_from_slice(bytes),
+        AtomBytes::CacheRef(index) => out.extend_from_slice(&[ATOM_CACHE_REF, index]),
+    }
+}
+
+/// Destination of [`copy_term()`].
+pub trait Output {
+    fn push(&mut self, b: u8);
+    fn extend_from_slice(&mut self, bytes: &[u8]);
+    fn len(&self) -> usize;
+    fn patch_u32(&mut self, pos: usize, v: u32);
+}
+
+impl Output for Vec<u8> {
+    fn push(&mut self, b: u8) {
+        Vec::push(self, b);
+    }
+
+    fn extend_from_slice(&mut self, bytes: &[u8]) {
+        Vec::extend_from_slice(self, bytes);
+    }
+
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn patch_u32(&mut self, pos: usize, v: u32) {
+        self[pos..][..4].copy_from_slice(&v.to_be_bytes());
+    }
+}
+
+/// [`Output`] that discards the written bytes.
+#[derive(Debug)]
+pub struct Discard;
+
+impl Output for Discard {
+    fn push(&mut self, _b: u8) {}
+
+    fn extend_from_slice(&mut self, _bytes: &[u8]) {}
+
+    fn len(&self) -> usize {
+        0
+    }
+
+    fn patch_u32(&mut self, _pos: usize, _v: u32) {}
+}
+
+#[derive(Debug)]
+struct Level {
+    remaining: u64,
+    trailer: usize,
+    size_field: Option<usize>,
+}
+
+impl Level {
+    fn new(remaining: u64) -> Self {
+        Self {
+            remaining,
+            trailer: 0,
+            size_field: None,
+        }
+    }
+
+    fn with_trailer(trailer: usize) -> Self {
+        Self {
+            remaining: 1,
+            trailer,
+            size_field: None,
+        }
+    }
+}
+
+struct Copier<'a, O, F> {
+    input: &'a [u8],
+    pos: usize,
+    out: &'a mut O,
+    max_depth: usize,
+    on_atom: &'a mut F,
+}
+
+impl<'a, O, F> Copier<'a, O, F>
+where
+    O: Output,
+    F: FnMut(AtomBytes, &mut O) -> Result<(), DecodeError>,
+{
+    fn copy_term(&mut self) -> Result<(), EtfError> {
+        // Nested terms are tracked by an explicit stack instead of recursion so that
+        // deeply nested input can't overflow the stack.
+        let mut stack = vec![Level::new(1)];
+        while !stack.is_empty() {
+            if stack.len() > self.max_depth {
+                return Err(EtfError::TermTooDeep {
+                    max_depth: self.max_depth,
+                });
+            }
+            let level = stack.last_mut().expect("unreachable");
+            if level.remaining == 0 {
+                let level = stack.pop().expect("unreachable");
+                self.copy(level.trailer)?;
+                if let Some(pos) = level.size_field {
+                    let size = self.out.len() - pos;
+                    self.out.patch_u32(pos, size as u32);
+                }
+                continue;
+            }
+            level.remaining -= 1;
+
+            let tag = self.read_u8()?;
+            match tag {
+                ATOM_CACHE_REF => {
+                    let index = self.read_u8()?;
+                    (self.on_atom)(AtomBytes::CacheRef(index), self.out)?;
+                    continue;
+                }
+                ATOM_EXT => {
+                    let n = self.read_u16()? as usize;
+                    let start = self.pos - 3;
+                    self.take(n)?;
+                    let bytes = &self.input[start..self.pos];
+                    (self.on_atom)(AtomBytes::Latin1(bytes), self.out)?;
+                    continue;
+                }
+                SMALL_ATOM_EXT => {
+                    let n = self.read_u8()? as usize;
+                    let start = self.pos - 2;
+                    self.take(n)?;
+                    let bytes = &self.input[start..self.pos];
+                    (self.on_atom)(AtomBytes::Latin1(bytes), self.out)?;
+                    continue;
+                }
+                ATOM_UTF8_EXT => {
+                    let n = self.read_u16()? as usize;
+                    let text = self.take(n)?;
+                    (self.on_atom)(AtomBytes::Utf8(text), self.out)?;
+                    continue;
+                }
+                SMALL_ATOM_UTF8_EXT => {
+                    let n = self.read_u8()? as usize;
+                    let text = self.take(n)?;
+                    (self.on_atom)(AtomBytes::Utf8(text), self.out)?;
+                    continue;
+                }
+                _ => {}
+            }
+
+            self.out.push(tag);
+            match tag {
+                NEW_FLOAT_EXT => self.copy(8)?,
+                BIT_BINARY_EXT => {
+                    let n = self.copy_u32()?;
+                    self.copy(1 + n as usize)?;
+                }
+                NEW_PID_EXT => stack.push(Level::with_trailer(12)),
+                NEW_PORT_EXT => stack.push(Level::with_trailer(8)),
+                NEWER_REFERENCE_EXT => {
+                    let n = self.copy_u16()?;
+                    stack.push(Level::with_trailer(4 + 4 * n as usize));
+                }
+                SMALL_INTEGER_EXT => self.copy(1)?,
+                INTEGER_EXT => self.copy(4)?,
+                FLOAT_EXT => self.copy(31)?,
+                REFERENCE_EXT | PORT_EXT => stack.push(Level::with_trailer(5)),
+                PID_EXT => stack.push(Level::with_trailer(9)),
+                SMALL_TUPLE_EXT => {
+                    let n = self.copy_u8()?;
+                    stack.push(Level::new(u64::from(n)));
+                }
+                LARGE_TUPLE_EXT => {
+                    let n = self.copy_u32()?;
+                    stack.push(Level::new(u64::from(n)));
+                }
+                NIL_EXT => {}
+                STRING_EXT => {
+                    let n = self.copy_u16()?;
+                    self.copy(n as usize)?;
+                }
+                LIST_EXT => {
+                    let n = self.copy_u32()?;
+                    stack.push(Level::new(u64::from(n) + 1));
+                }
+                BINARY_EXT => {
+                    let n = self.copy_u32()?;
+                    self.copy(n as usize)?;
+                }
+                SMALL_BIG_EXT => {
+                    let n = self.copy_u8()?;
+                    self.copy(1 + n as usize)?;
+                }
+                LARGE_BIG_EXT => {
+                    let n = self.copy_u32()?;
+                    self.copy(1 + n as usize)?;
+                }
+                NEW_FUN_EXT => {
+                    // Size (including the size field itself), Arity, Uniq, Index, NumFree,
+                    // and then Module, OldIndex, OldUniq, Pid and free variables.
+                    //
+                    // As atoms in the fun may change their sizes, the size field is recalculated.
+                    let size_field = self.out.len();
+                    self.read_u32()?;
+                    self.out.extend_from_slice(&[0; 4]);
+                    self.copy(1 + 16 + 4)?;
+                    let num_free = self.copy_u32()?;
+                    stack.push(Level {
+                        remaining: 4 + u64::from(num_free),
+                        trailer: 0,
+                        size_field: Some(size_field),
+                    });
+                }
+                EXPORT_EXT => stack.push(Level::new(3)),
+                NEW_REFERENCE_EXT => {
+                    let n = self.copy_u16()?;
+                    stack.push(Level::with_trailer(1 + 4 * n as usize));
+                }
+                MAP_EXT => {
+                    let n = self.copy_u32()?;
+                    stack.push(Level::new(u64::from(n) * 2));
+                }
+                FUN_EXT => {
+                    let n = self.copy_u32()?;
+                    stack.push(Level::new(u64::from(n) + 4));
+                }
+                V4_PORT_EXT => stack.push(Level::with_trailer(12)),
+                _ => return Err(DecodeError::UnknownTag { tag }.into()),
+            }
+        }
+        Ok(())
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+        if self.input.len() - self.pos < n {
+            return Err(DecodeError::Io(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "unexpected eof",
+            )));
+        }
+        let bytes = &self.input[self.pos..][..n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn copy(&mut self, n: usize) -> Result<(), DecodeError> {
+        if self.input.len() - self.pos < n {
+            return Err(DecodeError::Io(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "unexpected eof",
+            )));
+        }
+        self.out.extend_from_slice(&self.input[self.pos..][..n]);
+        self.pos += n;
+        Ok(())
+    }
+
+    fn read_u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, DecodeError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, DecodeError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn copy_u8(&mut self) -> Result<u8, DecodeError> {
+        let v = self.read_u8()?;
+        self.out.push(v);
+        Ok(v)
+    }
+
+    fn copy_u16(&mut self) -> Result<u16, DecodeError> {
+        let v = self.read_u16()?;
+        self.out.extend_from_slice(&v.to_be_bytes());
+        Ok(v)
+    }
+
+    fn copy_u32(&mut self) -> Result<u32, DecodeError> {
+        let v = self.read_u32()?;
+        self.out.extend_from_slice(&v.to_be_bytes());
+        Ok(v)
+    }
+}
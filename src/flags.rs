@@ -57,8 +57,6 @@ impl DistributionFlags {
     pub const UNICODE_IO: Self = Self(0x1000);
 
     /// The node implements atom cache in distribution header.
-    ///
-    /// Note that currently `erl_dist` can not handle distribution headers.
     pub const DIST_HDR_ATOM_CACHE: Self = Self(0x2000);
 
     /// The node understands the `SMALL_ATOM_EXT` tag.
@@ -330,6 +328,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::clone_on_copy)]
     fn clone_and_copy() {
         let a = DistributionFlags::mandatory();
         let b = a;
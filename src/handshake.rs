@@ -5,23 +5,82 @@
 //! See
 //! [Distribution Handshake (Erlang Official Doc)](https://www.erlang.org/doc/apps/erts/erl_dist_protocol.html#distribution-handshake)
 //! for more details.
-use crate::io::Connection;
+use crate::io::{ByteReader, Connection};
 use crate::node::{Creation, LocalNode, NodeName, NodeNameError, PeerNode};
 use crate::{DistributionFlags, LOWEST_DISTRIBUTION_PROTOCOL_VERSION};
 use futures::io::{AsyncRead, AsyncWrite};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Duration;
 
 const PROTOCOL_VERSION: u16 = LOWEST_DISTRIBUTION_PROTOCOL_VERSION;
 const NODE_NAME_VERSION: u16 = 5;
 
+/// Default value of the `net_setuptime` of Erlang nodes.
+///
+/// This can be used as the timeout of [`ClientSideHandshake::set_timeout()`] and [`ServerSideHandshake::set_timeout()`].
+pub const DEFAULT_NET_SETUPTIME: Duration = Duration::from_secs(7);
+
+/// Default value of [`ClientSideHandshake::set_max_message_size()`] and [`ServerSideHandshake::set_max_message_size()`].
+pub const DEFAULT_MAX_HANDSHAKE_MESSAGE_SIZE: usize = 4096;
+
+/// Provider of the cookies used to authenticate peer nodes.
+///
+/// The handshakes ask the cookie shared with the peer node by its name,
+/// which corresponds to the per-node cookies of Erlang (`erlang:set_cookie/2`).
+/// This makes it possible to rotate cookies, to look them up from a secret store,
+/// or to bridge clusters that use different cookies.
+///
+/// [`String`] implements this trait to use the same cookie for all the peers,
+/// and so do functions taking a [`NodeName`] and returning a cookie.
+pub trait CookieProvider: Send + Sync {
+    /// Returns the cookie shared with the given peer node.
+    fn cookie(&self, peer_name: &NodeName) -> String;
+}
+
+impl CookieProvider for String {
+    fn cookie(&self, _peer_name: &NodeName) -> String {
+        self.clone()
+    }
+}
+
+impl<F> CookieProvider for F
+where
+    F: Fn(&NodeName) -> String + Send + Sync,
+{
+    fn cookie(&self, peer_name: &NodeName) -> String {
+        self(peer_name)
+    }
+}
+
+impl<T> CookieProvider for Arc<T>
+where
+    T: CookieProvider + ?Sized,
+{
+    fn cookie(&self, peer_name: &NodeName) -> String {
+        (**self).cookie(peer_name)
+    }
+}
+
+// Wrapper to keep cookies out of `Debug` outputs.
+#[derive(Clone)]
+struct Cookies(Arc<dyn CookieProvider>);
+
+impl std::fmt::Debug for Cookies {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Cookies(..)")
+    }
+}
+
 /// Client-side handshake.
+///
+/// This is a thin async driver of [`ClientSideStateMachine`].
 #[derive(Debug)]
 pub struct ClientSideHandshake<T> {
-    local_node: LocalNode,
-    local_challenge: Challenge,
-    cookie: String,
-    connection: Connection<T>,
-    send_name_status: Option<HandshakeStatus>,
-    may_need_complement: bool,
+    connection: HandshakeConnection<T>,
+    state_machine: ClientSideStateMachine,
 }
 
 impl<T> ClientSideHandshake<T>
@@ -30,16 +89,44 @@ where
 {
     /// Makes a new [`ClientSideHandshake`] instance.
     pub fn new(connection: T, local_node: LocalNode, cookie: &str) -> Self {
+        Self::with_cookie_provider(connection, local_node, cookie.to_owned())
+    }
+
+    /// Makes a new [`ClientSideHandshake`] instance that asks `cookies` for the cookie of the peer node.
+    pub fn with_cookie_provider<C>(connection: T, local_node: LocalNode, cookies: C) -> Self
+    where
+        C: CookieProvider + 'static,
+    {
         Self {
-            local_node,
-            local_challenge: Challenge::new(),
-            cookie: cookie.to_owned(),
-            connection: Connection::new(connection),
-            send_name_status: None,
-            may_need_complement: false,
+            connection: HandshakeConnection::new(connection),
+            state_machine: ClientSideStateMachine::with_cookie_provider(local_node, cookies),
         }
     }
 
+    /// Sets the maximum number of bytes of a handshake message sent by the peer.
+    ///
+    /// Larger messages are rejected with [`HandshakeError::MessageTooLarge`].
+    ///
+    /// The default value is [`DEFAULT_MAX_HANDSHAKE_MESSAGE_SIZE`].
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.state_machine.set_max_message_size(size);
+    }
+
+    /// Sets the time limit of the whole handshake (like the `net_setuptime` of Erlang nodes).
+    ///
+    /// The deadline is counted from this call and covers all the subsequent phases.
+    /// If it expires, the handshake fails with [`HandshakeError::Timeout`].
+    ///
+    /// As `erl_dist` doesn't depend on any specific async runtime, a function to make a timer future
+    /// (e.g., `smol::Timer::after` or `tokio::time::sleep`) needs to be given.
+    pub fn set_timeout<F, Fut>(&mut self, timeout: Duration, sleep: F)
+    where
+        F: FnOnce(Duration) -> Fut,
+        Fut: Future + Send + 'static,
+    {
+        self.connection.set_deadline(sleep(timeout));
+    }
+
     /// Executes the first part of the handshake protocol.
     ///
     /// To complete the handshake, you then need to call [`ClientSideHandshake::execute_rest()`] method
@@ -48,10 +135,23 @@ where
         &mut self,
         protocol_version: u16,
     ) -> Result<HandshakeStatus, HandshakeError> {
-        self.send_name(protocol_version).await?;
-        let status = self.recv_status().await?;
-        self.send_name_status = Some(status.clone());
-        Ok(status)
+        self.state_machine.send_name(protocol_version)?;
+        let output = self.state_machine.take_output_messages();
+        self.connection.write_output(output).await?;
+        loop {
+            match self.state_machine.poll_event()? {
+                Some(ClientSideEvent::Status(status)) => return Ok(status),
+                Some(ClientSideEvent::Completed(_)) => unreachable!(),
+                None => {
+                    let hint = self.state_machine.read_hint();
+                    let bytes = self
+                        .connection
+                        .read_input(self.state_machine.phase(), hint)
+                        .await?;
+                    self.state_machine.feed(&bytes);
+                }
+            }
+        }
     }
 
     /// Executes the rest part of the handshake protocol.
@@ -66,216 +166,363 @@ where
         mut self,
         do_continue: bool,
     ) -> Result<(T, PeerNode), HandshakeError> {
-        match self.send_name_status {
-            None => {
-                return Err(HandshakeError::PhaseError {
-                    current: "ClientSideHandshake::execute_rest()",
-                    depends_on: "ClientSideHandshake::execute_send_name()",
-                });
-            }
-            Some(HandshakeStatus::Nok) => return Err(HandshakeError::OngoingHandshake),
-            Some(HandshakeStatus::NotAllowed) => return Err(HandshakeError::NotAllowed),
-            Some(HandshakeStatus::Alive) => {
-                self.send_status(if do_continue { "true" } else { "false" })
-                    .await?;
-                if !do_continue {
-                    return Err(HandshakeError::AlreadyActive);
+        let result = self.state_machine.proceed(do_continue);
+        let output = self.state_machine.take_output_messages();
+        self.connection.write_output(output).await?;
+        result?;
+        loop {
+            match self.state_machine.poll_event()? {
+                Some(ClientSideEvent::Completed(peer_node)) => {
+                    let output = self.state_machine.take_output_messages();
+                    self.connection.write_output(output).await?;
+                    return Ok((self.connection.into_inner(), peer_node));
+                }
+                Some(ClientSideEvent::Status(_)) => unreachable!(),
+                None => {
+                    let output = self.state_machine.take_output_messages();
+                    self.connection.write_output(output).await?;
+                    let hint = self.state_machine.read_hint();
+                    let bytes = self
+                        .connection
+                        .read_input(self.state_machine.phase(), hint)
+                        .await?;
+                    self.state_machine.feed(&bytes);
                 }
             }
-            _ => {}
         }
+    }
+}
+
+/// Event emitted by [`ClientSideStateMachine::poll_event()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientSideEvent {
+    /// The peer replied the status of the handshake.
+    ///
+    /// [`ClientSideStateMachine::proceed()`] needs to be called to continue the handshake.
+    Status(HandshakeStatus),
+
+    /// The handshake has been completed.
+    Completed(PeerNode),
+}
+
+#[derive(Debug)]
+enum ClientSideState {
+    Initial,
+    RecvStatus,
+    StatusReceived(HandshakeStatus),
+    RecvChallenge,
+    RecvChallengeAck(PeerNode, String),
+    Completed,
+}
+
+/// Sans-IO state machine of the client-side handshake.
+///
+/// This consumes the bytes received from the peer via [`ClientSideStateMachine::feed()`] and
+/// produces the bytes to be sent to the peer via [`ClientSideStateMachine::take_output()`] and
+/// events via [`ClientSideStateMachine::poll_event()`].
+/// It can be used to embed the handshake in your own event loop.
+///
+/// The typical flow is as follows:
+/// 1. Call [`ClientSideStateMachine::send_name()`].
+/// 2. Feed input until [`ClientSideEvent::Status`] is emitted.
+/// 3. Call [`ClientSideStateMachine::proceed()`].
+/// 4. Feed input until [`ClientSideEvent::Completed`] is emitted.
+///
+/// Don't forget to send the output after each step.
+#[derive(Debug)]
+pub struct ClientSideStateMachine {
+    local_node: LocalNode,
+    local_challenge: Challenge,
+    cookies: Cookies,
+    max_message_size: usize,
+    state: ClientSideState,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    output_phases: Vec<(&'static str, usize)>,
+}
+
+impl ClientSideStateMachine {
+    /// Makes a new [`ClientSideStateMachine`] instance.
+    pub fn new(local_node: LocalNode, cookie: &str) -> Self {
+        Self::with_cookie_provider(local_node, cookie.to_owned())
+    }
 
-        let (peer_node, peer_challenge) = self.recv_challenge().await?;
-        if self.may_need_complement && peer_node.creation.is_some() {
-            self.send_complement().await?;
+    /// Makes a new [`ClientSideStateMachine`] instance that asks `cookies` for the cookie of the peer node.
+    pub fn with_cookie_provider<C>(local_node: LocalNode, cookies: C) -> Self
+    where
+        C: CookieProvider + 'static,
+    {
+        Self {
+            local_node,
+            local_challenge: Challenge::new(),
+            cookies: Cookies(Arc::new(cookies)),
+            max_message_size: DEFAULT_MAX_HANDSHAKE_MESSAGE_SIZE,
+            state: ClientSideState::Initial,
+            input: Vec::new(),
+            output: Vec::new(),
+            output_phases: Vec::new(),
         }
-        self.send_challenge_reply(peer_challenge).await?;
-        self.recv_challenge_ack().await?;
+    }
 
-        let connection = self.connection.into_inner();
-        Ok((connection, peer_node))
+    /// Sets the maximum number of bytes of a handshake message sent by the peer.
+    ///
+    /// See [`ClientSideHandshake::set_max_message_size()`] for the details.
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = size;
     }
 
-    async fn send_name(&mut self, protocol_version: u16) -> Result<(), HandshakeError> {
-        let mut writer = self.connection.handshake_message_writer();
+    /// Appends bytes received from the peer to the input of this state machine.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.input.extend_from_slice(bytes);
+    }
+
+    /// Returns the number of bytes needed to make progress.
+    ///
+    /// Reading no more than this number of bytes from the connection ensures that
+    /// the bytes following the handshake (i.e., distribution messages) are not consumed by this state machine.
+    /// `0` means that no input is needed in the current state.
+    pub fn read_hint(&self) -> usize {
+        match self.state {
+            ClientSideState::RecvStatus
+            | ClientSideState::RecvChallenge
+            | ClientSideState::RecvChallengeAck(..) => message_read_hint(&self.input),
+            _ => 0,
+        }
+    }
+
+    /// Takes the bytes to be sent to the peer.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        self.output_phases.clear();
+        std::mem::take(&mut self.output)
+    }
+
+    fn take_output_messages(&mut self) -> Vec<(&'static str, Vec<u8>)> {
+        let phases = std::mem::take(&mut self.output_phases);
+        split_output(self.take_output(), phases)
+    }
+
+    /// Queues the first message (`send_name`) of the handshake.
+    pub fn send_name(&mut self, protocol_version: u16) -> Result<(), HandshakeError> {
+        if !matches!(self.state, ClientSideState::Initial) {
+            return Err(HandshakeError::PhaseError {
+                current: "ClientSideStateMachine::send_name()",
+                depends_on: "ClientSideStateMachine::new()",
+            });
+        }
+
+        let mut message = vec![];
         match protocol_version {
             PROTOCOL_VERSION => {
-                writer.write_u8(b'N')?;
-                writer.write_u64(self.local_node.flags.bits())?;
-                writer.write_u32(self.local_node.creation.get())?;
+                message.push(b'N');
+                message.extend_from_slice(&self.local_node.flags.bits().to_be_bytes());
+                message.extend_from_slice(&self.local_node.creation.get().to_be_bytes());
                 if self.local_node.flags.contains(DistributionFlags::NAME_ME) {
-                    writer.write_u16(self.local_node.name.host().len() as u16)?;
-                    writer.write_all(self.local_node.name.host().as_bytes())?;
+                    let host = self.local_node.name.host();
+                    message.extend_from_slice(&(host.len() as u16).to_be_bytes());
+                    message.extend_from_slice(host.as_bytes());
                 } else {
-                    writer.write_u16(self.local_node.name.len() as u16)?;
-                    writer.write_all(self.local_node.name.to_string().as_bytes())?;
+                    let name = self.local_node.name.to_string();
+                    message.extend_from_slice(&(name.len() as u16).to_be_bytes());
+                    message.extend_from_slice(name.as_bytes());
                 }
             }
             value => {
                 return Err(HandshakeError::UnknownProtocolVersion { value });
             }
         }
-        writer.finish().await?;
+        self.write_output("send_name", &message)?;
+        self.state = ClientSideState::RecvStatus;
         Ok(())
     }
 
-    async fn recv_status(&mut self) -> Result<HandshakeStatus, HandshakeError> {
-        let mut reader = self.connection.handshake_message_reader().await?;
-        let tag = reader.read_u8().await?;
-        if tag != b's' {
-            return Err(HandshakeError::UnexpectedTag {
-                message: "STATUS",
-                tag,
+    /// Continues the handshake after [`ClientSideEvent::Status`] has been emitted.
+    ///
+    /// See [`ClientSideHandshake::execute_rest()`] for the meaning of `do_continue`.
+    ///
+    /// Note that even if this method fails, there may be output (e.g., the reply to [`HandshakeStatus::Alive`])
+    /// that should be sent to the peer.
+    pub fn proceed(&mut self, do_continue: bool) -> Result<(), HandshakeError> {
+        let ClientSideState::StatusReceived(status) = &self.state else {
+            return Err(HandshakeError::PhaseError {
+                current: "ClientSideStateMachine::proceed()",
+                depends_on: "ClientSideEvent::Status",
             });
-        }
-        let status = reader.read_bytes().await?;
-        let status = match status.as_slice() {
-            b"ok" => HandshakeStatus::Ok,
-            b"ok_simultaneous" => HandshakeStatus::OkSimultaneous,
-            b"nok" => HandshakeStatus::Nok,
-            b"not_allowed" => HandshakeStatus::NotAllowed,
-            b"alive" => HandshakeStatus::Alive,
-            _ => {
-                if status.starts_with(b"named:") {
-                    let bytes = &status["named:".len()..];
-                    if bytes.len() < 2 {
-                        return Err(HandshakeError::Io(std::io::Error::new(
-                            std::io::ErrorKind::UnexpectedEof,
-                            "unexpected eof",
-                        )));
-                    }
-                    let n = u16::from_be_bytes([bytes[0], bytes[1]]) as usize;
-                    let bytes = &bytes[2..];
-                    if bytes.len() < n + 4 {
-                        return Err(HandshakeError::Io(std::io::Error::new(
-                            std::io::ErrorKind::UnexpectedEof,
-                            "unexpected eof",
-                        )));
-                    }
-                    let name = std::str::from_utf8(&bytes[..n])
-                        .map_err(|_| {
-                            std::io::Error::new(
-                                std::io::ErrorKind::InvalidData,
-                                "invalid UTF-8 in node name",
-                            )
-                        })?
-                        .to_owned();
-                    let bytes = &bytes[n..];
-                    let node_name: NodeName = name.parse()?;
-                    let name = node_name.name().to_owned();
-
-                    let creation =
-                        Creation::new(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
-                    HandshakeStatus::Named { name, creation }
-                } else {
-                    let status = String::from_utf8_lossy(&status).to_string();
-                    return Err(HandshakeError::UnknownStatus { status });
+        };
+        match status {
+            HandshakeStatus::Nok => return Err(HandshakeError::OngoingHandshake),
+            HandshakeStatus::NotAllowed => return Err(HandshakeError::NotAllowed),
+            HandshakeStatus::Alive => {
+                let status: &[u8] = if do_continue { b"strue" } else { b"sfalse" };
+                self.write_output("send_status", status)?;
+                if !do_continue {
+                    self.state = ClientSideState::Completed;
+                    return Err(HandshakeError::AlreadyActive);
                 }
             }
-        };
-        reader.finish().await?;
-        Ok(status)
-    }
-
-    async fn send_status(&mut self, status: &str) -> Result<(), HandshakeError> {
-        let mut writer = self.connection.handshake_message_writer();
-        writer.write_u8(b's')?;
-        writer.write_all(status.as_bytes())?;
+            _ => {}
+        }
+        self.state = ClientSideState::RecvChallenge;
         Ok(())
     }
 
-    async fn recv_challenge(&mut self) -> Result<(PeerNode, Challenge), HandshakeError> {
-        let mut reader = self.connection.handshake_message_reader().await?;
-        let (node, challenge) = match reader.read_u8().await? {
-            b'n' => {
-                let version = reader.read_u16().await?;
-                if version != NODE_NAME_VERSION {
-                    return Err(HandshakeError::InvalidVersionValue { value: version });
+    /// Processes the input and returns the next event if any.
+    ///
+    /// `None` means that more input is needed (see [`ClientSideStateMachine::read_hint()`])
+    /// or that a method call is needed to continue the handshake.
+    pub fn poll_event(&mut self) -> Result<Option<ClientSideEvent>, HandshakeError> {
+        loop {
+            match &self.state {
+                ClientSideState::RecvStatus => {
+                    let Some(message) = take_message(&mut self.input, self.max_message_size)?
+                    else {
+                        return Ok(None);
+                    };
+                    let status = parse_status(&message)?;
+                    self.state = ClientSideState::StatusReceived(status.clone());
+                    return Ok(Some(ClientSideEvent::Status(status)));
                 }
-                let flags =
-                    DistributionFlags::from_bits_truncate(u64::from(reader.read_u32().await?));
-                let challenge = Challenge(reader.read_u32().await?);
-                let name = reader.read_string().await?.parse()?;
-                let node = PeerNode {
-                    name,
-                    flags,
-                    creation: None,
-                };
-                (node, challenge)
-            }
-            b'N' => {
-                let flags = DistributionFlags::from_bits_truncate(reader.read_u64().await?);
-                let challenge = Challenge(reader.read_u32().await?);
-                let creation = Creation::new(reader.read_u32().await?);
-                let name = reader.read_u16_string().await?.parse()?;
-                let node = PeerNode {
-                    name,
-                    flags,
-                    creation: Some(creation),
-                };
-                (node, challenge)
-            }
-            tag => {
-                return Err(HandshakeError::UnexpectedTag {
-                    message: "CHALLENGE",
-                    tag,
-                });
+                ClientSideState::RecvChallenge => {
+                    let Some(message) = take_message(&mut self.input, self.max_message_size)?
+                    else {
+                        return Ok(None);
+                    };
+                    let (peer_node, peer_challenge) = parse_challenge(&message)?;
+                    let cookie = self.cookies.0.cookie(&peer_node.name);
+
+                    let mut reply = vec![b'r'];
+                    reply.extend_from_slice(&self.local_challenge.0.to_be_bytes());
+                    reply.extend_from_slice(&peer_challenge.digest(&cookie).0);
+                    self.write_output("send_challenge_reply", &reply)?;
+                    self.state = ClientSideState::RecvChallengeAck(peer_node, cookie);
+                }
+                ClientSideState::RecvChallengeAck(..) => {
+                    let Some(message) = take_message(&mut self.input, self.max_message_size)?
+                    else {
+                        return Ok(None);
+                    };
+                    let mut reader = ByteReader(&message);
+                    let tag = reader.read_u8()?;
+                    if tag != b'a' {
+                        return Err(HandshakeError::UnexpectedTag {
+                            message: "CHALLENGE_ACK",
+                            tag,
+                        });
+                    }
+                    let digest = reader.read_bytes(16)?;
+                    let ClientSideState::RecvChallengeAck(peer_node, cookie) =
+                        std::mem::replace(&mut self.state, ClientSideState::Completed)
+                    else {
+                        unreachable!()
+                    };
+                    if digest != self.local_challenge.digest(&cookie).0 {
+                        return Err(HandshakeError::CookieMismatch);
+                    }
+                    return Ok(Some(ClientSideEvent::Completed(peer_node)));
+                }
+                ClientSideState::Initial
+                | ClientSideState::StatusReceived(_)
+                | ClientSideState::Completed => return Ok(None),
             }
-        };
-        reader.finish().await?;
-        Ok((node, challenge))
+        }
     }
 
-    async fn send_complement(&mut self) -> Result<(), HandshakeError> {
-        let mut writer = self.connection.handshake_message_writer();
-        writer.write_u8(b'c')?;
-        writer.write_u32((self.local_node.flags.bits() >> 32) as u32)?;
-        writer.write_u32(self.local_node.creation.get())?;
-        writer.finish().await?;
+    // Records the phase of each message so that write errors (e.g., timeouts) are labelled correctly.
+    fn write_output(&mut self, phase: &'static str, message: &[u8]) -> std::io::Result<()> {
+        write_message(&mut self.output, message)?;
+        self.output_phases.push((phase, 2 + message.len()));
         Ok(())
     }
 
-    async fn send_challenge_reply(
-        &mut self,
-        peer_challenge: Challenge,
-    ) -> Result<(), HandshakeError> {
-        let mut writer = self.connection.handshake_message_writer();
-        writer.write_u8(b'r')?;
-        writer.write_u32(self.local_challenge.0)?;
-        writer.write_all(&peer_challenge.digest(&self.cookie).0)?;
-        writer.finish().await?;
-        Ok(())
+    fn phase(&self) -> &'static str {
+        match self.state {
+            ClientSideState::Initial => "send_name",
+            ClientSideState::RecvStatus => "recv_status",
+            ClientSideState::StatusReceived(_) => "send_status",
+            ClientSideState::RecvChallenge => "recv_challenge",
+            ClientSideState::RecvChallengeAck(..) => "recv_challenge_ack",
+            ClientSideState::Completed => "completed",
+        }
     }
+}
 
-    async fn recv_challenge_ack(&mut self) -> Result<(), HandshakeError> {
-        let mut reader = self.connection.handshake_message_reader().await?;
-        let tag = reader.read_u8().await?;
-        if tag != b'a' {
-            return Err(HandshakeError::UnexpectedTag {
-                message: "CHALLENGE_ACK",
-                tag,
-            });
+fn parse_status(message: &[u8]) -> Result<HandshakeStatus, HandshakeError> {
+    let mut reader = ByteReader(message);
+    let tag = reader.read_u8()?;
+    if tag != b's' {
+        return Err(HandshakeError::UnexpectedTag {
+            message: "STATUS",
+            tag,
+        });
+    }
+    let status = reader.0;
+    let status = match status {
+        b"ok" => HandshakeStatus::Ok,
+        b"ok_simultaneous" => HandshakeStatus::OkSimultaneous,
+        b"nok" => HandshakeStatus::Nok,
+        b"not_allowed" => HandshakeStatus::NotAllowed,
+        b"alive" => HandshakeStatus::Alive,
+        _ => {
+            if let Some(bytes) = status.strip_prefix(b"named:") {
+                let mut reader = ByteReader(bytes);
+                let n = usize::from(reader.read_u16()?);
+                let node_name: NodeName = utf8_string(reader.read_bytes(n)?)?.parse()?;
+                let name = node_name.name().to_owned();
+                let creation = Creation::new(reader.read_u32()?);
+                HandshakeStatus::Named { name, creation }
+            } else {
+                let status = String::from_utf8_lossy(status).to_string();
+                return Err(HandshakeError::UnknownStatus { status });
+            }
         }
+    };
+    Ok(status)
+}
 
-        let mut digest = [0; 16];
-        reader.read_exact(&mut digest).await?;
-        if digest != self.local_challenge.digest(&self.cookie).0 {
-            return Err(HandshakeError::CookieMismatch);
+fn parse_challenge(message: &[u8]) -> Result<(PeerNode, Challenge), HandshakeError> {
+    let mut reader = ByteReader(message);
+    match reader.read_u8()? {
+        b'n' => {
+            let version = reader.read_u16()?;
+            if version != NODE_NAME_VERSION {
+                return Err(HandshakeError::InvalidVersionValue { value: version });
+            }
+            let flags = DistributionFlags::from_bits_truncate(u64::from(reader.read_u32()?));
+            let challenge = Challenge(reader.read_u32()?);
+            let name = utf8_string(reader.0)?.parse()?;
+            let node = PeerNode {
+                name,
+                flags,
+                creation: None,
+            };
+            Ok((node, challenge))
         }
-        reader.finish().await?;
-
-        Ok(())
+        b'N' => {
+            let flags = DistributionFlags::from_bits_truncate(reader.read_u64()?);
+            let challenge = Challenge(reader.read_u32()?);
+            let creation = Creation::new(reader.read_u32()?);
+            let n = usize::from(reader.read_u16()?);
+            let name = utf8_string(reader.read_bytes(n)?)?.parse()?;
+            let node = PeerNode {
+                name,
+                flags,
+                creation: Some(creation),
+            };
+            Ok((node, challenge))
+        }
+        tag => Err(HandshakeError::UnexpectedTag {
+            message: "CHALLENGE",
+            tag,
+        }),
     }
 }
 
 /// Server-side handshake.
+///
+/// This is a thin async driver of [`ServerSideStateMachine`].
 #[derive(Debug)]
 pub struct ServerSideHandshake<T> {
-    local_node: LocalNode,
-    local_challenge: Challenge,
-    cookie: String,
-    connection: Connection<T>,
-    peer_node: Option<PeerNode>,
+    connection: HandshakeConnection<T>,
+    state_machine: ServerSideStateMachine,
 }
 
 impl<T> ServerSideHandshake<T>
@@ -284,15 +531,44 @@ where
 {
     /// Makes a new [`ServerSideHandshake`] instance.
     pub fn new(connection: T, local_node: LocalNode, cookie: &str) -> Self {
+        Self::with_cookie_provider(connection, local_node, cookie.to_owned())
+    }
+
+    /// Makes a new [`ServerSideHandshake`] instance that asks `cookies` for the cookie of the peer node.
+    pub fn with_cookie_provider<C>(connection: T, local_node: LocalNode, cookies: C) -> Self
+    where
+        C: CookieProvider + 'static,
+    {
         Self {
-            local_node,
-            local_challenge: Challenge::new(),
-            cookie: cookie.to_owned(),
-            connection: Connection::new(connection),
-            peer_node: None,
+            connection: HandshakeConnection::new(connection),
+            state_machine: ServerSideStateMachine::with_cookie_provider(local_node, cookies),
         }
     }
 
+    /// Sets the maximum number of bytes of a handshake message sent by the peer.
+    ///
+    /// Larger messages are rejected with [`HandshakeError::MessageTooLarge`].
+    ///
+    /// The default value is [`DEFAULT_MAX_HANDSHAKE_MESSAGE_SIZE`].
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.state_machine.set_max_message_size(size);
+    }
+
+    /// Sets the time limit of the whole handshake (like the `net_setuptime` of Erlang nodes).
+    ///
+    /// The deadline is counted from this call and covers all the subsequent phases.
+    /// If it expires, the handshake fails with [`HandshakeError::Timeout`].
+    ///
+    /// As `erl_dist` doesn't depend on any specific async runtime, a function to make a timer future
+    /// (e.g., `smol::Timer::after` or `tokio::time::sleep`) needs to be given.
+    pub fn set_timeout<F, Fut>(&mut self, timeout: Duration, sleep: F)
+    where
+        F: FnOnce(Duration) -> Fut,
+        Fut: Future + Send + 'static,
+    {
+        self.connection.set_deadline(sleep(timeout));
+    }
+
     /// Executes the first part of the handshake protocol.
     ///
     /// To complete the handshake, you then need to call [`ServerSideHandshake::execute_rest()`] method
@@ -301,51 +577,20 @@ where
     /// Note that the return value becomes `None` if the peer requested a dynamic node name.
     /// In the case, if you want to continue the handshake, you need to use [`HandshakeStatus::Named`] for the reply.
     pub async fn execute_recv_name(&mut self) -> Result<Option<NodeName>, HandshakeError> {
-        let mut reader = self.connection.handshake_message_reader().await?;
-        let tag = reader.read_u8().await?;
-        let node = match tag {
-            b'n' => {
-                let version = reader.read_u16().await?;
-                if version != NODE_NAME_VERSION {
-                    return Err(HandshakeError::InvalidVersionValue { value: version });
-                }
-                let flags =
-                    DistributionFlags::from_bits_truncate(u64::from(reader.read_u32().await?));
-                let name = reader.read_string().await?.parse()?;
-                PeerNode {
-                    name,
-                    flags,
-                    creation: None,
+        loop {
+            match self.state_machine.poll_event()? {
+                Some(ServerSideEvent::Name(name)) => return Ok(name),
+                Some(ServerSideEvent::Completed(_)) => unreachable!(),
+                None => {
+                    let hint = self.state_machine.read_hint();
+                    let bytes = self
+                        .connection
+                        .read_input(self.state_machine.phase(), hint)
+                        .await?;
+                    self.state_machine.feed(&bytes);
                 }
             }
-            b'N' => {
-                let flags = DistributionFlags::from_bits_truncate(reader.read_u64().await?);
-                let creation = Creation::new(reader.read_u32().await?);
-                let name = if flags.contains(DistributionFlags::NAME_ME) {
-                    let host = reader.read_u16_string().await?;
-                    NodeName::new("nonode", &host)?
-                } else {
-                    reader.read_u16_string().await?.parse()?
-                };
-                PeerNode {
-                    name,
-                    flags,
-                    creation: Some(creation),
-                }
-            }
-            _ => {
-                return Err(HandshakeError::UnexpectedTag {
-                    message: "NAME",
-                    tag,
-                });
-            }
-        };
-        reader.finish().await?;
-
-        let name = node.name.clone();
-        let is_dynamic = node.flags.contains(DistributionFlags::NAME_ME);
-        self.peer_node = Some(node);
-        if is_dynamic { Ok(None) } else { Ok(Some(name)) }
+        }
     }
 
     /// Executes the rest part of the handshake protocol.
@@ -354,141 +599,546 @@ where
     ///
     /// Note that if the [`HandshakeStatus`] is a non-ok status, this method call fails just
     /// after sending the status to the peer node.
+    /// In a case where the status is [`HandshakeStatus::Alive`], this method fails with
+    /// [`HandshakeError::AlreadyActive`] if the peer node decides not to continue the handshake.
     pub async fn execute_rest(
         mut self,
         status: HandshakeStatus,
     ) -> Result<(T, PeerNode), HandshakeError> {
-        let (peer_flags, peer_creation) = if let Some(peer) = &self.peer_node {
-            (peer.flags, peer.creation)
-        } else {
-            return Err(HandshakeError::PhaseError {
-                current: "ServerSideHandshake::execute_rest()",
-                depends_on: "ServerSideHandshake::execute_recv_name()",
-            });
-        };
+        let result = self.state_machine.send_status(status);
+        let output = self.state_machine.take_output_messages();
+        self.connection.write_output(output).await?;
+        result?;
+        loop {
+            match self.state_machine.poll_event()? {
+                Some(ServerSideEvent::Completed(peer_node)) => {
+                    let output = self.state_machine.take_output_messages();
+                    self.connection.write_output(output).await?;
+                    return Ok((self.connection.into_inner(), peer_node));
+                }
+                Some(ServerSideEvent::Name(_)) => unreachable!(),
+                None => {
+                    let output = self.state_machine.take_output_messages();
+                    self.connection.write_output(output).await?;
+                    let hint = self.state_machine.read_hint();
+                    let bytes = self
+                        .connection
+                        .read_input(self.state_machine.phase(), hint)
+                        .await?;
+                    self.state_machine.feed(&bytes);
+                }
+            }
+        }
+    }
+}
+
+/// Event emitted by [`ServerSideStateMachine::poll_event()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerSideEvent {
+    /// The peer sent its name.
+    ///
+    /// `None` means that the peer requested a dynamic node name
+    /// (see [`ServerSideHandshake::execute_recv_name()`] for the details).
+    /// [`ServerSideStateMachine::send_status()`] needs to be called to continue the handshake.
+    Name(Option<NodeName>),
+
+    /// The handshake has been completed.
+    Completed(PeerNode),
+}
+
+#[derive(Debug)]
+enum ServerSideState {
+    RecvName,
+    NameReceived(PeerNode),
+    RecvAliveReply(PeerNode),
+    RecvComplement(PeerNode),
+    RecvChallengeReply(PeerNode),
+    Completed,
+}
+
+/// Sans-IO state machine of the server-side handshake.
+///
+/// This consumes the bytes received from the peer via [`ServerSideStateMachine::feed()`] and
+/// produces the bytes to be sent to the peer via [`ServerSideStateMachine::take_output()`] and
+/// events via [`ServerSideStateMachine::poll_event()`].
+/// It can be used to embed the handshake in your own event loop.
+///
+/// The typical flow is as follows:
+/// 1. Feed input until [`ServerSideEvent::Name`] is emitted.
+/// 2. Call [`ServerSideStateMachine::send_status()`].
+/// 3. Feed input until [`ServerSideEvent::Completed`] is emitted.
+///
+/// Don't forget to send the output after each step.
+#[derive(Debug)]
+pub struct ServerSideStateMachine {
+    local_node: LocalNode,
+    local_challenge: Challenge,
+    cookies: Cookies,
+    max_message_size: usize,
+    state: ServerSideState,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    output_phases: Vec<(&'static str, usize)>,
+}
+
+impl ServerSideStateMachine {
+    /// Makes a new [`ServerSideStateMachine`] instance.
+    pub fn new(local_node: LocalNode, cookie: &str) -> Self {
+        Self::with_cookie_provider(local_node, cookie.to_owned())
+    }
+
+    /// Makes a new [`ServerSideStateMachine`] instance that asks `cookies` for the cookie of the peer node.
+    pub fn with_cookie_provider<C>(local_node: LocalNode, cookies: C) -> Self
+    where
+        C: CookieProvider + 'static,
+    {
+        Self {
+            local_node,
+            local_challenge: Challenge::new(),
+            cookies: Cookies(Arc::new(cookies)),
+            max_message_size: DEFAULT_MAX_HANDSHAKE_MESSAGE_SIZE,
+            state: ServerSideState::RecvName,
+            input: Vec::new(),
+            output: Vec::new(),
+            output_phases: Vec::new(),
+        }
+    }
 
-        self.send_status(status).await?;
+    /// Sets the maximum number of bytes of a handshake message sent by the peer.
+    ///
+    /// See [`ServerSideHandshake::set_max_message_size()`] for the details.
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = size;
+    }
 
-        self.send_challenge(peer_flags).await?;
+    /// Appends bytes received from the peer to the input of this state machine.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.input.extend_from_slice(bytes);
+    }
 
-        if peer_flags.contains(DistributionFlags::HANDSHAKE_23) && peer_creation.is_none() {
-            self.recv_complement().await?;
+    /// Returns the number of bytes needed to make progress.
+    ///
+    /// See [`ClientSideStateMachine::read_hint()`] for the details.
+    pub fn read_hint(&self) -> usize {
+        match self.state {
+            ServerSideState::RecvName
+            | ServerSideState::RecvAliveReply(_)
+            | ServerSideState::RecvComplement(_)
+            | ServerSideState::RecvChallengeReply(_) => message_read_hint(&self.input),
+            _ => 0,
         }
+    }
 
-        let peer_challenge = self.recv_challenge_reply().await?;
-        self.send_challenge_ack(peer_challenge).await?;
+    /// Takes the bytes to be sent to the peer.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        self.output_phases.clear();
+        std::mem::take(&mut self.output)
+    }
 
-        let peer_node = self.peer_node.take().expect("unreachable");
-        let connection = self.connection.into_inner();
-        Ok((connection, peer_node))
+    fn take_output_messages(&mut self) -> Vec<(&'static str, Vec<u8>)> {
+        let phases = std::mem::take(&mut self.output_phases);
+        split_output(self.take_output(), phases)
     }
 
-    async fn send_status(&mut self, status: HandshakeStatus) -> Result<(), HandshakeError> {
-        let mut writer = self.connection.handshake_message_writer();
-        writer.write_u8(b's')?;
+    /// Queues the status reply and the challenge after [`ServerSideEvent::Name`] has been emitted.
+    ///
+    /// If `status` is a non-ok status, this method fails after queueing the status,
+    /// so the output should be sent to the peer even in that case.
+    ///
+    /// If `status` is [`HandshakeStatus::Alive`], the challenge is queued by
+    /// [`ServerSideStateMachine::poll_event()`] after the peer replies that it continues the handshake.
+    pub fn send_status(&mut self, status: HandshakeStatus) -> Result<(), HandshakeError> {
+        let ServerSideState::NameReceived(peer_node) = &mut self.state else {
+            return Err(HandshakeError::PhaseError {
+                current: "ServerSideStateMachine::send_status()",
+                depends_on: "ServerSideEvent::Name",
+            });
+        };
+
+        let mut message = vec![b's'];
         match &status {
-            HandshakeStatus::Ok => writer.write_all(b"ok")?,
-            HandshakeStatus::OkSimultaneous => writer.write_all(b"ok_simultaneous")?,
-            HandshakeStatus::Nok => writer.write_all(b"nok")?,
-            HandshakeStatus::NotAllowed => writer.write_all(b"not_allowed")?,
-            HandshakeStatus::Alive => writer.write_all(b"alive")?,
+            HandshakeStatus::Ok => message.extend_from_slice(b"ok"),
+            HandshakeStatus::OkSimultaneous => message.extend_from_slice(b"ok_simultaneous"),
+            HandshakeStatus::Nok => message.extend_from_slice(b"nok"),
+            HandshakeStatus::NotAllowed => message.extend_from_slice(b"not_allowed"),
+            HandshakeStatus::Alive => message.extend_from_slice(b"alive"),
             HandshakeStatus::Named { name, creation } => {
-                let peer_node = self.peer_node.as_mut().expect("unreachable");
                 let node_name = NodeName::new(name, peer_node.name.host())?;
-                writer.write_all(b"named:")?;
-                writer.write_u16(node_name.len() as u16)?;
-                writer.write_all(node_name.to_string().as_bytes())?;
-                writer.write_u32(creation.get())?;
+                message.extend_from_slice(b"named:");
+                message.extend_from_slice(&(node_name.len() as u16).to_be_bytes());
+                message.extend_from_slice(node_name.to_string().as_bytes());
+                message.extend_from_slice(&creation.get().to_be_bytes());
 
                 peer_node.name = node_name;
                 peer_node.creation = Some(*creation);
                 self.local_node.flags |= DistributionFlags::NAME_ME;
             }
         }
-        writer.finish().await?;
+        self.write_output("send_status", &message)?;
 
+        let ServerSideState::NameReceived(peer_node) =
+            std::mem::replace(&mut self.state, ServerSideState::Completed)
+        else {
+            unreachable!()
+        };
         match status {
             HandshakeStatus::Nok => Err(HandshakeError::OngoingHandshake),
             HandshakeStatus::NotAllowed => Err(HandshakeError::NotAllowed),
-            _ => Ok(()),
+            HandshakeStatus::Alive => {
+                self.state = ServerSideState::RecvAliveReply(peer_node);
+                Ok(())
+            }
+            _ => self.send_challenge(peer_node),
         }
     }
 
-    async fn send_challenge(
-        &mut self,
-        peer_flags: DistributionFlags,
-    ) -> Result<(), HandshakeError> {
-        let mut writer = self.connection.handshake_message_writer();
+    fn send_challenge(&mut self, peer_node: PeerNode) -> Result<(), HandshakeError> {
+        let peer_flags = peer_node.flags;
+        let mut message = vec![];
         if peer_flags.contains(DistributionFlags::HANDSHAKE_23) {
-            writer.write_u8(b'N')?;
-            writer.write_u64(self.local_node.flags.bits())?;
-            writer.write_u32(self.local_challenge.0)?;
-            writer.write_u32(self.local_node.creation.get())?;
-            writer.write_u16(self.local_node.name.len() as u16)?;
-            writer.write_all(self.local_node.name.to_string().as_bytes())?;
+            let name = self.local_node.name.to_string();
+            message.push(b'N');
+            message.extend_from_slice(&self.local_node.flags.bits().to_be_bytes());
+            message.extend_from_slice(&self.local_challenge.0.to_be_bytes());
+            message.extend_from_slice(&self.local_node.creation.get().to_be_bytes());
+            message.extend_from_slice(&(name.len() as u16).to_be_bytes());
+            message.extend_from_slice(name.as_bytes());
         } else {
-            writer.write_u8(b'n')?;
-            writer.write_u16(5)?;
-            writer.write_u32(self.local_node.flags.bits() as u32)?;
-            writer.write_u32(self.local_challenge.0)?;
-            writer.write_all(self.local_node.name.to_string().as_bytes())?;
+            message.push(b'n');
+            message.extend_from_slice(&NODE_NAME_VERSION.to_be_bytes());
+            message.extend_from_slice(&(self.local_node.flags.bits() as u32).to_be_bytes());
+            message.extend_from_slice(&self.local_challenge.0.to_be_bytes());
+            message.extend_from_slice(self.local_node.name.to_string().as_bytes());
+        }
+        self.write_output("send_challenge", &message)?;
+
+        if peer_flags.contains(DistributionFlags::HANDSHAKE_23) && peer_node.creation.is_none() {
+            self.state = ServerSideState::RecvComplement(peer_node);
+        } else {
+            self.state = ServerSideState::RecvChallengeReply(peer_node);
         }
-        writer.finish().await?;
         Ok(())
     }
 
-    async fn recv_complement(&mut self) -> Result<(), HandshakeError> {
-        let mut reader = self.connection.handshake_message_reader().await?;
-        let tag = reader.read_u8().await?;
-        if tag != b'c' {
-            return Err(HandshakeError::UnexpectedTag {
-                message: "send_complement",
-                tag,
-            });
-        }
-        let flags_high =
-            DistributionFlags::from_bits_truncate(u64::from(reader.read_u32().await?) << 32);
-        let creation = Creation::new(reader.read_u32().await?);
-        reader.finish().await?;
+    /// Processes the input and returns the next event if any.
+    ///
+    /// `None` means that more input is needed (see [`ServerSideStateMachine::read_hint()`])
+    /// or that a method call is needed to continue the handshake.
+    pub fn poll_event(&mut self) -> Result<Option<ServerSideEvent>, HandshakeError> {
+        loop {
+            match &mut self.state {
+                ServerSideState::RecvName => {
+                    let Some(message) = take_message(&mut self.input, self.max_message_size)?
+                    else {
+                        return Ok(None);
+                    };
+                    let peer_node = parse_name(&message)?;
+                    let name = if peer_node.flags.contains(DistributionFlags::NAME_ME) {
+                        None
+                    } else {
+                        Some(peer_node.name.clone())
+                    };
+                    self.state = ServerSideState::NameReceived(peer_node);
+                    return Ok(Some(ServerSideEvent::Name(name)));
+                }
+                ServerSideState::RecvAliveReply(_) => {
+                    let Some(message) = take_message(&mut self.input, self.max_message_size)?
+                    else {
+                        return Ok(None);
+                    };
+                    let mut reader = ByteReader(&message);
+                    let tag = reader.read_u8()?;
+                    if tag != b's' {
+                        return Err(HandshakeError::UnexpectedTag {
+                            message: "STATUS",
+                            tag,
+                        });
+                    }
+                    let ServerSideState::RecvAliveReply(peer_node) =
+                        std::mem::replace(&mut self.state, ServerSideState::Completed)
+                    else {
+                        unreachable!()
+                    };
+                    match reader.0 {
+                        b"true" => self.send_challenge(peer_node)?,
+                        b"false" => return Err(HandshakeError::AlreadyActive),
+                        status => {
+                            let status = String::from_utf8_lossy(status).to_string();
+                            return Err(HandshakeError::UnknownStatus { status });
+                        }
+                    }
+                }
+                ServerSideState::RecvComplement(peer_node) => {
+                    let Some(message) = take_message(&mut self.input, self.max_message_size)?
+                    else {
+                        return Ok(None);
+                    };
+                    let mut reader = ByteReader(&message);
+                    let tag = reader.read_u8()?;
+                    if tag != b'c' {
+                        return Err(HandshakeError::UnexpectedTag {
+                            message: "send_complement",
+                            tag,
+                        });
+                    }
+                    let flags_high =
+                        DistributionFlags::from_bits_truncate(u64::from(reader.read_u32()?) << 32);
+                    let creation = Creation::new(reader.read_u32()?);
+                    peer_node.flags |= flags_high;
+                    peer_node.creation = Some(creation);
+
+                    let ServerSideState::RecvComplement(peer_node) =
+                        std::mem::replace(&mut self.state, ServerSideState::Completed)
+                    else {
+                        unreachable!()
+                    };
+                    self.state = ServerSideState::RecvChallengeReply(peer_node);
+                }
+                ServerSideState::RecvChallengeReply(_) => {
+                    let Some(message) = take_message(&mut self.input, self.max_message_size)?
+                    else {
+                        return Ok(None);
+                    };
+                    let mut reader = ByteReader(&message);
+                    let tag = reader.read_u8()?;
+                    if tag != b'r' {
+                        return Err(HandshakeError::UnexpectedTag {
+                            message: "challenge_reply",
+                            tag,
+                        });
+                    }
+                    let peer_challenge = Challenge(reader.read_u32()?);
+                    let digest = reader.read_bytes(16)?;
+                    let ServerSideState::RecvChallengeReply(peer_node) =
+                        std::mem::replace(&mut self.state, ServerSideState::Completed)
+                    else {
+                        unreachable!()
+                    };
+                    let cookie = self.cookies.0.cookie(&peer_node.name);
+                    if self.local_challenge.digest(&cookie).0 != digest {
+                        return Err(HandshakeError::CookieMismatch);
+                    }
 
-        let peer = self.peer_node.as_mut().expect("unreachable");
-        peer.flags |= flags_high;
-        peer.creation = Some(creation);
+                    let mut ack = vec![b'a'];
+                    ack.extend_from_slice(&peer_challenge.digest(&cookie).0);
+                    self.write_output("send_challenge_ack", &ack)?;
+                    return Ok(Some(ServerSideEvent::Completed(peer_node)));
+                }
+                ServerSideState::NameReceived(_) | ServerSideState::Completed => return Ok(None),
+            }
+        }
+    }
 
+    fn write_output(&mut self, phase: &'static str, message: &[u8]) -> std::io::Result<()> {
+        write_message(&mut self.output, message)?;
+        self.output_phases.push((phase, 2 + message.len()));
         Ok(())
     }
 
-    async fn recv_challenge_reply(&mut self) -> Result<Challenge, HandshakeError> {
-        let mut reader = self.connection.handshake_message_reader().await?;
-        let tag = reader.read_u8().await?;
-        if tag != b'r' {
-            return Err(HandshakeError::UnexpectedTag {
-                message: "challenge_reply",
-                tag,
-            });
+    fn phase(&self) -> &'static str {
+        match self.state {
+            ServerSideState::RecvName => "recv_name",
+            ServerSideState::NameReceived(_) => "send_status",
+            ServerSideState::RecvAliveReply(_) => "recv_status",
+            ServerSideState::RecvComplement(_) => "recv_complement",
+            ServerSideState::RecvChallengeReply(_) => "recv_challenge_reply",
+            ServerSideState::Completed => "send_challenge_ack",
+        }
+    }
+}
+
+fn parse_name(message: &[u8]) -> Result<PeerNode, HandshakeError> {
+    let mut reader = ByteReader(message);
+    match reader.read_u8()? {
+        b'n' => {
+            let version = reader.read_u16()?;
+            if version != NODE_NAME_VERSION {
+                return Err(HandshakeError::InvalidVersionValue { value: version });
+            }
+            let flags = DistributionFlags::from_bits_truncate(u64::from(reader.read_u32()?));
+            let name = utf8_string(reader.0)?.parse()?;
+            Ok(PeerNode {
+                name,
+                flags,
+                creation: None,
+            })
+        }
+        b'N' => {
+            let flags = DistributionFlags::from_bits_truncate(reader.read_u64()?);
+            let creation = Creation::new(reader.read_u32()?);
+            let n = usize::from(reader.read_u16()?);
+            let name = utf8_string(reader.read_bytes(n)?)?;
+            let name = if flags.contains(DistributionFlags::NAME_ME) {
+                NodeName::new("nonode", &name)?
+            } else {
+                name.parse()?
+            };
+            Ok(PeerNode {
+                name,
+                flags,
+                creation: Some(creation),
+            })
         }
-        let peer_challenge = Challenge(reader.read_u32().await?);
-        let mut digest = Digest([0; 16]);
-        reader.read_exact(&mut digest.0).await?;
-        reader.finish().await?;
+        tag => Err(HandshakeError::UnexpectedTag {
+            message: "NAME",
+            tag,
+        }),
+    }
+}
+
+fn message_read_hint(input: &[u8]) -> usize {
+    match input {
+        [a, b, ..] => (2 + usize::from(u16::from_be_bytes([*a, *b]))).saturating_sub(input.len()),
+        _ => 2 - input.len(),
+    }
+}
+
+fn take_message(input: &mut Vec<u8>, max_size: usize) -> Result<Option<Vec<u8>>, HandshakeError> {
+    let [a, b, ..] = input[..] else {
+        return Ok(None);
+    };
+    let size = usize::from(u16::from_be_bytes([a, b]));
+    if size > max_size {
+        return Err(HandshakeError::MessageTooLarge { size, max_size });
+    }
+    if input.len() < 2 + size {
+        return Ok(None);
+    }
+    let message = input[2..2 + size].to_vec();
+    input.drain(..2 + size);
+    Ok(Some(message))
+}
+
+fn write_message(output: &mut Vec<u8>, message: &[u8]) -> std::io::Result<()> {
+    let size = u16::try_from(message.len()).map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "too large bytes: expected less then {}, but got {} bytes",
+                u16::MAX as usize + 1,
+                message.len()
+            ),
+        )
+    })?;
+    output.extend_from_slice(&size.to_be_bytes());
+    output.extend_from_slice(message);
+    Ok(())
+}
 
-        if self.local_challenge.digest(&self.cookie) != digest {
-            return Err(HandshakeError::CookieMismatch);
+fn split_output(
+    mut output: Vec<u8>,
+    phases: Vec<(&'static str, usize)>,
+) -> Vec<(&'static str, Vec<u8>)> {
+    let mut messages = Vec::with_capacity(phases.len());
+    for (phase, size) in phases.into_iter().rev() {
+        let message = output.split_off(output.len() - size);
+        messages.push((phase, message));
+    }
+    messages.reverse();
+    messages
+}
+
+fn utf8_string(bytes: &[u8]) -> std::io::Result<String> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "stream did not contain valid UTF-8",
+        )
+    })
+}
+
+#[derive(Debug)]
+struct HandshakeConnection<T> {
+    connection: Connection<T>,
+    deadline: Deadline,
+}
+
+impl<T> HandshakeConnection<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    fn new(connection: T) -> Self {
+        Self {
+            connection: Connection::new(connection),
+            deadline: Deadline(None),
         }
+    }
+
+    fn into_inner(self) -> T {
+        self.connection.into_inner()
+    }
 
-        Ok(peer_challenge)
+    fn set_deadline<F>(&mut self, timer: F)
+    where
+        F: Future + Send + 'static,
+    {
+        self.deadline.0 = Some(Box::pin(async move {
+            timer.await;
+        }));
     }
 
-    async fn send_challenge_ack(
+    async fn write_output(
         &mut self,
-        peer_challenge: Challenge,
+        messages: Vec<(&'static str, Vec<u8>)>,
     ) -> Result<(), HandshakeError> {
-        let mut writer = self.connection.handshake_message_writer();
-        writer.write_u8(b'a')?;
-        writer.write_all(&peer_challenge.digest(&self.cookie).0)?;
-        writer.finish().await?;
+        for (phase, message) in messages {
+            let connection = &mut self.connection;
+            self.deadline
+                .run(phase, async {
+                    connection.write_all(&message).await?;
+                    connection.flush().await
+                })
+                .await?;
+        }
         Ok(())
     }
+
+    async fn read_input(
+        &mut self,
+        phase: &'static str,
+        size: usize,
+    ) -> Result<Vec<u8>, HandshakeError> {
+        let mut buf = vec![0; size];
+        self.deadline
+            .run(phase, self.connection.read_exact(&mut buf))
+            .await?;
+        Ok(buf)
+    }
+}
+
+struct Deadline(Option<Pin<Box<dyn Future<Output = ()> + Send>>>);
+
+impl Deadline {
+    async fn run<F, R>(&mut self, phase: &'static str, future: F) -> Result<R, HandshakeError>
+    where
+        F: Future<Output = std::io::Result<R>>,
+    {
+        let Some(timer) = &mut self.0 else {
+            return Ok(future.await?);
+        };
+        let mut future = std::pin::pin!(future);
+        let result = futures::future::poll_fn(|cx| {
+            if let Poll::Ready(result) = future.as_mut().poll(cx) {
+                return Poll::Ready(result.map_err(HandshakeError::from));
+            }
+            timer
+                .as_mut()
+                .poll(cx)
+                .map(|()| Err(HandshakeError::Timeout { phase }))
+        })
+        .await;
+        if matches!(result, Err(HandshakeError::Timeout { .. })) {
+            // Expired timers must not be polled again.
+            self.0 = Some(Box::pin(futures::future::pending()));
+        }
+        result
+    }
+}
+
+impl std::fmt::Debug for Deadline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Deadline").field(&self.0.is_some()).finish()
+    }
 }
 
 /// Handshake status.
@@ -584,6 +1234,12 @@ pub enum HandshakeError {
         depends_on: &'static str,
     },
 
+    /// Handshake message larger than the limit.
+    MessageTooLarge { size: usize, max_size: usize },
+
+    /// The handshake didn't complete within the time limit.
+    Timeout { phase: &'static str },
+
     /// Node name error.
     NodeNameError(NodeNameError),
 
@@ -627,6 +1283,13 @@ impl std::fmt::Display for HandshakeError {
                     "{current:?} was unexpectedly executed before {depends_on:?}"
                 )
             }
+            Self::MessageTooLarge { size, max_size } => write!(
+                f,
+                "handshake message size {size} exceeds the limit of {max_size} bytes"
+            ),
+            Self::Timeout { phase } => {
+                write!(f, "handshake timed out in the {phase:?} phase")
+            }
             Self::NodeNameError(error) => write!(f, "{error}"),
             Self::Io(error) => write!(f, "{error}"),
         }
@@ -734,4 +1397,344 @@ mod tests {
             let _ = rx.await;
         })
     }
+
+    #[test]
+    fn state_machines_work() {
+        let client_node = LocalNode::new("foo@localhost".parse().unwrap(), Creation::random());
+        let server_node = LocalNode::new("bar@localhost".parse().unwrap(), Creation::random());
+        let mut client = ClientSideStateMachine::new(client_node, crate::tests::COOKIE);
+        let mut server = ServerSideStateMachine::new(server_node, crate::tests::COOKIE);
+
+        client.send_name(PROTOCOL_VERSION).unwrap();
+        assert_eq!(server.read_hint(), 2);
+        server.feed(&client.take_output());
+        let Some(ServerSideEvent::Name(Some(name))) = server.poll_event().unwrap() else {
+            panic!()
+        };
+        assert_eq!(name.to_string(), "foo@localhost");
+        assert_eq!(server.read_hint(), 0);
+
+        server.send_status(HandshakeStatus::Ok).unwrap();
+        client.feed(&server.take_output());
+        assert_eq!(
+            client.poll_event().unwrap(),
+            Some(ClientSideEvent::Status(HandshakeStatus::Ok))
+        );
+        // The challenge has already been received along with the status.
+        client.proceed(true).unwrap();
+        assert_eq!(client.poll_event().unwrap(), None);
+        assert_eq!(client.read_hint(), 2);
+
+        server.feed(&client.take_output());
+        let Some(ServerSideEvent::Completed(peer)) = server.poll_event().unwrap() else {
+            panic!()
+        };
+        assert_eq!(peer.name.to_string(), "foo@localhost");
+
+        client.feed(&server.take_output());
+        let Some(ClientSideEvent::Completed(peer)) = client.poll_event().unwrap() else {
+            panic!()
+        };
+        assert_eq!(peer.name.to_string(), "bar@localhost");
+        assert_eq!(client.read_hint(), 0);
+    }
+
+    #[test]
+    fn alive_status_works() {
+        let handshake = |do_continue: bool| {
+            let client_node = LocalNode::new("foo@localhost".parse().unwrap(), Creation::random());
+            let server_node = LocalNode::new("bar@localhost".parse().unwrap(), Creation::random());
+            let mut client = ClientSideStateMachine::new(client_node, crate::tests::COOKIE);
+            let mut server = ServerSideStateMachine::new(server_node, crate::tests::COOKIE);
+
+            client.send_name(PROTOCOL_VERSION).unwrap();
+            server.feed(&client.take_output());
+            server.poll_event().unwrap();
+            server.send_status(HandshakeStatus::Alive).unwrap();
+            client.feed(&server.take_output());
+            assert_eq!(
+                client.poll_event().unwrap(),
+                Some(ClientSideEvent::Status(HandshakeStatus::Alive))
+            );
+
+            // The server waits for the reply before sending the challenge.
+            assert_eq!(client.read_hint(), 0);
+            let client_result = client.proceed(do_continue);
+            assert_eq!(client.poll_event().unwrap(), None);
+            server.feed(&client.take_output());
+            let server_result = server.poll_event();
+            if !do_continue {
+                assert!(matches!(client_result, Err(HandshakeError::AlreadyActive)));
+                return server_result;
+            }
+            assert_eq!(server_result.unwrap(), None);
+
+            client.feed(&server.take_output());
+            assert_eq!(client.poll_event().unwrap(), None);
+            server.feed(&client.take_output());
+            let server_result = server.poll_event();
+            client.feed(&server.take_output());
+            assert!(matches!(
+                client.poll_event(),
+                Ok(Some(ClientSideEvent::Completed(_)))
+            ));
+            server_result
+        };
+
+        assert!(matches!(
+            handshake(true),
+            Ok(Some(ServerSideEvent::Completed(_)))
+        ));
+        assert!(matches!(
+            handshake(false),
+            Err(HandshakeError::AlreadyActive)
+        ));
+    }
+
+    #[test]
+    fn cookie_mismatch_is_detected() {
+        let client_node = LocalNode::new("foo@localhost".parse().unwrap(), Creation::random());
+        let server_node = LocalNode::new("bar@localhost".parse().unwrap(), Creation::random());
+        let mut client = ClientSideStateMachine::new(client_node, "foo");
+        let mut server = ServerSideStateMachine::new(server_node, "bar");
+
+        client.send_name(PROTOCOL_VERSION).unwrap();
+        server.feed(&client.take_output());
+        server.poll_event().unwrap();
+        server.send_status(HandshakeStatus::Ok).unwrap();
+        client.feed(&server.take_output());
+        client.poll_event().unwrap();
+        client.proceed(true).unwrap();
+        client.poll_event().unwrap();
+        server.feed(&client.take_output());
+        assert!(matches!(
+            server.poll_event(),
+            Err(HandshakeError::CookieMismatch)
+        ));
+    }
+
+    #[test]
+    fn cookie_provider_works() {
+        // The server bridges two clusters that use different cookies.
+        let cookies = |peer_name: &NodeName| match peer_name.name() {
+            "foo" => "foo-cookie".to_owned(),
+            _ => "default-cookie".to_owned(),
+        };
+        let handshake = |client_name: &str, client_cookie: &str| {
+            let client_node = LocalNode::new(client_name.parse().unwrap(), Creation::random());
+            let server_node = LocalNode::new("bar@localhost".parse().unwrap(), Creation::random());
+            let mut client = ClientSideStateMachine::new(client_node, client_cookie);
+            let mut server = ServerSideStateMachine::with_cookie_provider(server_node, cookies);
+
+            client.send_name(PROTOCOL_VERSION).unwrap();
+            server.feed(&client.take_output());
+            server.poll_event().unwrap();
+            server.send_status(HandshakeStatus::Ok).unwrap();
+            client.feed(&server.take_output());
+            client.poll_event().unwrap();
+            client.proceed(true).unwrap();
+            client.poll_event().unwrap();
+            server.feed(&client.take_output());
+            server.poll_event()?;
+            client.feed(&server.take_output());
+            client.poll_event()
+        };
+
+        assert!(matches!(
+            handshake("foo@localhost", "foo-cookie"),
+            Ok(Some(ClientSideEvent::Completed(_)))
+        ));
+        assert!(matches!(
+            handshake("baz@localhost", "default-cookie"),
+            Ok(Some(ClientSideEvent::Completed(_)))
+        ));
+        assert!(matches!(
+            handshake("baz@localhost", "foo-cookie"),
+            Err(HandshakeError::CookieMismatch)
+        ));
+    }
+
+    #[test]
+    fn cookie_is_looked_up_once_per_handshake() {
+        let lookups = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let cookies = {
+            let lookups = Arc::clone(&lookups);
+            move |_: &NodeName| {
+                lookups.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                crate::tests::COOKIE.to_owned()
+            }
+        };
+        let client_node = LocalNode::new("foo@localhost".parse().unwrap(), Creation::random());
+        let server_node = LocalNode::new("bar@localhost".parse().unwrap(), Creation::random());
+        let mut client = ClientSideStateMachine::with_cookie_provider(client_node, cookies);
+        let mut server = ServerSideStateMachine::new(server_node, crate::tests::COOKIE);
+
+        client.send_name(PROTOCOL_VERSION).unwrap();
+        server.feed(&client.take_output());
+        server.poll_event().unwrap();
+        server.send_status(HandshakeStatus::Ok).unwrap();
+        client.feed(&server.take_output());
+        client.poll_event().unwrap();
+        client.proceed(true).unwrap();
+        client.poll_event().unwrap();
+        server.feed(&client.take_output());
+        server.poll_event().unwrap();
+        client.feed(&server.take_output());
+        assert!(matches!(
+            client.poll_event(),
+            Ok(Some(ClientSideEvent::Completed(_)))
+        ));
+        assert_eq!(lookups.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn handshake_timeout_works() {
+        smol::block_on(async {
+            // The peer connects but never sends anything.
+            let (_client, server) = crate::tests::tcp_stream_pair().await;
+            let local_node = LocalNode::new("bar@localhost".parse().unwrap(), Creation::random());
+            let mut handshake = ServerSideHandshake::new(server, local_node, crate::tests::COOKIE);
+            handshake.set_timeout(Duration::from_millis(50), smol::Timer::after);
+            assert!(matches!(
+                handshake.execute_recv_name().await,
+                Err(HandshakeError::Timeout { phase: "recv_name" })
+            ));
+
+            // The peer accepts but never replies.
+            let (client, _server) = crate::tests::tcp_stream_pair().await;
+            let local_node = LocalNode::new("foo@localhost".parse().unwrap(), Creation::random());
+            let mut handshake = ClientSideHandshake::new(client, local_node, crate::tests::COOKIE);
+            handshake.set_timeout(Duration::from_millis(50), smol::Timer::after);
+            assert!(matches!(
+                handshake
+                    .execute_send_name(crate::LOWEST_DISTRIBUTION_PROTOCOL_VERSION)
+                    .await,
+                Err(HandshakeError::Timeout {
+                    phase: "recv_status"
+                })
+            ));
+        });
+    }
+
+    #[test]
+    fn handshake_write_timeout_works() {
+        // Connection that accepts no more than `writable` bytes.
+        struct Stalled {
+            input: futures::io::Cursor<Vec<u8>>,
+            writable: usize,
+        }
+
+        impl AsyncRead for Stalled {
+            fn poll_read(
+                mut self: Pin<&mut Self>,
+                cx: &mut std::task::Context<'_>,
+                buf: &mut [u8],
+            ) -> Poll<std::io::Result<usize>> {
+                Pin::new(&mut self.input).poll_read(cx, buf)
+            }
+        }
+
+        impl AsyncWrite for Stalled {
+            fn poll_write(
+                mut self: Pin<&mut Self>,
+                _cx: &mut std::task::Context<'_>,
+                buf: &[u8],
+            ) -> Poll<std::io::Result<usize>> {
+                if self.writable == 0 {
+                    return Poll::Pending;
+                }
+                let n = buf.len().min(self.writable);
+                self.writable -= n;
+                Poll::Ready(Ok(n))
+            }
+
+            fn poll_flush(
+                self: Pin<&mut Self>,
+                _cx: &mut std::task::Context<'_>,
+            ) -> Poll<std::io::Result<()>> {
+                Poll::Ready(Ok(()))
+            }
+
+            fn poll_close(
+                self: Pin<&mut Self>,
+                _cx: &mut std::task::Context<'_>,
+            ) -> Poll<std::io::Result<()>> {
+                Poll::Ready(Ok(()))
+            }
+        }
+
+        smol::block_on(async {
+            let connection = Stalled {
+                input: futures::io::Cursor::new(Vec::new()),
+                writable: 0,
+            };
+            let local_node = LocalNode::new("foo@localhost".parse().unwrap(), Creation::random());
+            let mut handshake =
+                ClientSideHandshake::new(connection, local_node, crate::tests::COOKIE);
+            handshake.set_timeout(Duration::from_millis(50), smol::Timer::after);
+            assert!(matches!(
+                handshake.execute_send_name(PROTOCOL_VERSION).await,
+                Err(HandshakeError::Timeout { phase: "send_name" })
+            ));
+
+            // The status and the challenge are queued at once, but each write is labelled separately.
+            let server_handshake = |writable| {
+                let client_node =
+                    LocalNode::new("foo@localhost".parse().unwrap(), Creation::random());
+                let mut client = ClientSideStateMachine::new(client_node, crate::tests::COOKIE);
+                client.send_name(PROTOCOL_VERSION).unwrap();
+                let connection = Stalled {
+                    input: futures::io::Cursor::new(client.take_output()),
+                    writable,
+                };
+                let local_node =
+                    LocalNode::new("bar@localhost".parse().unwrap(), Creation::random());
+                let mut handshake =
+                    ServerSideHandshake::new(connection, local_node, crate::tests::COOKIE);
+                handshake.set_timeout(Duration::from_millis(50), smol::Timer::after);
+                async move {
+                    handshake.execute_recv_name().await.unwrap();
+                    handshake.execute_rest(HandshakeStatus::Ok).await
+                }
+            };
+            assert!(matches!(
+                server_handshake(0).await,
+                Err(HandshakeError::Timeout {
+                    phase: "send_status"
+                })
+            ));
+            // The status message is "\x00\x03sok".
+            assert!(matches!(
+                server_handshake(5).await,
+                Err(HandshakeError::Timeout {
+                    phase: "send_challenge"
+                })
+            ));
+        });
+    }
+
+    #[test]
+    fn too_large_handshake_message_is_rejected() {
+        smol::block_on(async {
+            let (client, server) = crate::tests::tcp_stream_pair().await;
+            smol::spawn(async move {
+                let local_node =
+                    LocalNode::new("foo@localhost".parse().unwrap(), Creation::random());
+                let mut handshake =
+                    ClientSideHandshake::new(client, local_node, crate::tests::COOKIE);
+                let _ = handshake
+                    .execute_send_name(crate::LOWEST_DISTRIBUTION_PROTOCOL_VERSION)
+                    .await;
+            })
+            .detach();
+
+            let local_node = LocalNode::new("bar@localhost".parse().unwrap(), Creation::random());
+            let mut handshake = ServerSideHandshake::new(server, local_node, crate::tests::COOKIE);
+            handshake.set_max_message_size(8);
+            assert!(matches!(
+                handshake.execute_recv_name().await,
+                Err(HandshakeError::MessageTooLarge { max_size: 8, .. })
+            ));
+        })
+    }
 }
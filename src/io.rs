@@ -1,16 +1,15 @@
 use eetf::{DecodeError, EncodeError, FixInteger, Term, Tuple};
 use futures::io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _};
 use std::io::{Read, Write};
+use std::pin::Pin;
+use std::task::{Context, Poll};
 
 #[derive(Debug)]
 pub struct Connection<T> {
     inner: T,
 }
 
-impl<T> Connection<T>
-where
-    T: AsyncRead + AsyncWrite + Unpin,
-{
+impl<T> Connection<T> {
     pub fn new(inner: T) -> Self {
         Self { inner }
     }
@@ -18,37 +17,12 @@ where
     pub fn into_inner(self) -> T {
         self.inner
     }
+}
 
-    pub fn handshake_message_writer(&mut self) -> HandshakeMessageWriter<'_, T> {
-        HandshakeMessageWriter {
-            connection: self,
-            buf: Vec::new(),
-        }
-    }
-
-    #[allow(clippy::needless_lifetimes)]
-    pub async fn handshake_message_reader<'a>(
-        &'a mut self,
-    ) -> std::io::Result<HandshakeMessageReader<'a, T>> {
-        let size = self.read_u16().await? as usize;
-        Ok(HandshakeMessageReader {
-            connection: self,
-            size,
-        })
-    }
-
-    pub async fn write_u8(&mut self, v: u8) -> std::io::Result<()> {
-        self.inner.write_all(&[v]).await
-    }
-
-    pub async fn write_u16(&mut self, v: u16) -> std::io::Result<()> {
-        self.inner.write_all(&v.to_be_bytes()).await
-    }
-
-    pub async fn write_u32(&mut self, v: u32) -> std::io::Result<()> {
-        self.inner.write_all(&v.to_be_bytes()).await
-    }
-
+impl<T> Connection<T>
+where
+    T: AsyncWrite + Unpin,
+{
     pub async fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
         self.inner.write_all(buf).await
     }
@@ -57,197 +31,75 @@ where
         self.inner.flush().await
     }
 
-    pub async fn read_u8(&mut self) -> std::io::Result<u8> {
-        let mut buf = [0; 1];
-        self.inner.read_exact(&mut buf).await?;
-        Ok(buf[0])
+    pub fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
     }
 
-    pub async fn read_u16(&mut self) -> std::io::Result<u16> {
-        let mut buf = [0; 2];
-        self.inner.read_exact(&mut buf).await?;
-        Ok(u16::from_be_bytes(buf))
+    pub fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
     }
 
-    pub async fn read_u32(&mut self) -> std::io::Result<u32> {
-        let mut buf = [0; 4];
-        self.inner.read_exact(&mut buf).await?;
-        Ok(u32::from_be_bytes(buf))
-    }
-
-    pub async fn read_u64(&mut self) -> std::io::Result<u64> {
-        let mut buf = [0; 8];
-        self.inner.read_exact(&mut buf).await?;
-        Ok(u64::from_be_bytes(buf))
-    }
-
-    pub async fn read_exact(&mut self, buf: &mut [u8]) -> std::io::Result<()> {
-        self.inner.read_exact(buf).await
-    }
-
-    pub async fn read_string(&mut self) -> std::io::Result<String> {
-        let mut buf = String::new();
-        self.inner.read_to_string(&mut buf).await?;
-        Ok(buf)
-    }
-
-    pub async fn read_stringn(&mut self, size: usize) -> std::io::Result<String> {
-        let mut buf = vec![0; size];
-        self.inner.read_exact(&mut buf).await?;
-        String::from_utf8(buf).map_err(|_| {
-            std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "stream did not contain valid UTF-8",
-            )
-        })
-    }
-
-    pub async fn read_u16_bytes(&mut self) -> std::io::Result<Vec<u8>> {
-        let mut buf = vec![0; usize::from(self.read_u16().await?)];
-        self.inner.read_exact(&mut buf).await?;
-        Ok(buf)
-    }
-
-    pub async fn read_u16_string(&mut self) -> std::io::Result<String> {
-        let buf = self.read_u16_bytes().await?;
-        String::from_utf8(buf).map_err(|_| {
-            std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "stream did not contain valid UTF-8",
-            )
-        })
+    pub fn poll_close(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
     }
 }
 
-#[derive(Debug)]
-pub struct HandshakeMessageWriter<'a, T> {
-    connection: &'a mut Connection<T>,
-    buf: Vec<u8>,
-}
-
-impl<T> HandshakeMessageWriter<'_, T>
+impl<T> Connection<T>
 where
-    T: AsyncRead + AsyncWrite + Unpin,
+    T: AsyncRead + Unpin,
 {
-    pub async fn finish(self) -> std::io::Result<()> {
-        if self.buf.len() > u16::MAX as usize {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                format!(
-                    "too large bytes: expected less then {}, but got {} bytes",
-                    u16::MAX as usize + 1,
-                    self.buf.len()
-                ),
-            ));
-        }
-        self.connection.write_u16(self.buf.len() as u16).await?;
-        self.connection.write_all(&self.buf).await?;
-        self.connection.flush().await?;
-        Ok(())
-    }
-
-    pub fn write_u8(&mut self, v: u8) -> std::io::Result<()> {
-        self.buf.push(v);
-        Ok(())
-    }
-
-    pub fn write_u16(&mut self, v: u16) -> std::io::Result<()> {
-        self.buf.extend_from_slice(&v.to_be_bytes());
-        Ok(())
-    }
-
-    pub fn write_u32(&mut self, v: u32) -> std::io::Result<()> {
-        self.buf.extend_from_slice(&v.to_be_bytes());
-        Ok(())
+    pub fn poll_read(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
     }
 
-    pub fn write_u64(&mut self, v: u64) -> std::io::Result<()> {
-        self.buf.extend_from_slice(&v.to_be_bytes());
-        Ok(())
+    pub async fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buf).await
     }
 
-    pub fn write_all(&mut self, bytes: &[u8]) -> std::io::Result<()> {
-        self.buf.extend_from_slice(bytes);
-        Ok(())
+    pub async fn read_exact(&mut self, buf: &mut [u8]) -> std::io::Result<()> {
+        self.inner.read_exact(buf).await
     }
 }
 
 #[derive(Debug)]
-pub struct HandshakeMessageReader<'a, T> {
-    connection: &'a mut Connection<T>,
-    size: usize,
-}
-
-impl<T> HandshakeMessageReader<'_, T>
-where
-    T: AsyncRead + AsyncWrite + Unpin,
-{
-    pub async fn read_u8(&mut self) -> std::io::Result<u8> {
-        self.size = self.size.checked_sub(1).ok_or_else(|| {
-            std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "unexpected eof")
-        })?;
-        self.connection.read_u8().await
-    }
-
-    pub async fn read_u16(&mut self) -> std::io::Result<u16> {
-        self.size = self.size.checked_sub(2).ok_or_else(|| {
-            std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "unexpected eof")
-        })?;
-        self.connection.read_u16().await
-    }
-
-    pub async fn read_u32(&mut self) -> std::io::Result<u32> {
-        self.size = self.size.checked_sub(4).ok_or_else(|| {
-            std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "unexpected eof")
-        })?;
-        self.connection.read_u32().await
-    }
-
-    pub async fn read_u64(&mut self) -> std::io::Result<u64> {
-        self.size = self.size.checked_sub(8).ok_or_else(|| {
-            std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "unexpected eof")
-        })?;
-        self.connection.read_u64().await
-    }
-
-    pub async fn read_string(&mut self) -> std::io::Result<String> {
-        let n = self.size;
-        self.size = 0;
-        self.connection.read_stringn(n).await
-    }
+pub struct ByteReader<'a>(pub &'a [u8]);
 
-    pub async fn read_bytes(&mut self) -> std::io::Result<Vec<u8>> {
-        let n = self.size;
-        let mut buf = vec![0; n];
-        self.read_exact(&mut buf).await?;
-        Ok(buf)
+impl<'a> ByteReader<'a> {
+    pub fn read_u8(&mut self) -> std::io::Result<u8> {
+        Ok(self.read_bytes(1)?[0])
     }
 
-    pub async fn read_exact(&mut self, buf: &mut [u8]) -> std::io::Result<()> {
-        let n = buf.len();
-        self.size = self.size.checked_sub(n).ok_or_else(|| {
-            std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "unexpected eof")
-        })?;
-        self.connection.read_exact(buf).await
+    pub fn read_u16(&mut self) -> std::io::Result<u16> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
     }
 
-    pub async fn read_u16_string(&mut self) -> std::io::Result<String> {
-        let n = self.read_u16().await? as usize;
-        self.size = self.size.checked_sub(n).ok_or_else(|| {
-            std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "unexpected eof")
-        })?;
-        self.connection.read_stringn(n).await
+    pub fn read_u32(&mut self) -> std::io::Result<u32> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.read_bytes(4)?);
+        Ok(u32::from_be_bytes(buf))
     }
 
-    pub async fn consume_remaining_bytes(&mut self) -> std::io::Result<()> {
-        let mut buf = vec![0; self.size];
-        self.size = 0;
-        self.connection.read_exact(&mut buf).await?;
-        Ok(())
+    pub fn read_u64(&mut self) -> std::io::Result<u64> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.read_bytes(8)?);
+        Ok(u64::from_be_bytes(buf))
     }
 
-    pub async fn finish(mut self) -> std::io::Result<()> {
-        self.consume_remaining_bytes().await
+    pub fn read_bytes(&mut self, n: usize) -> std::io::Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "unexpected eof",
+            ));
+        }
+        let (bytes, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(bytes)
     }
 }
 
@@ -0,0 +1,207 @@
+use crate::channel::{Receiver, RecvError, SendError, Sender};
+use crate::message::Message;
+use futures::io::{AsyncRead, AsyncWrite};
+use std::future::Future;
+use std::task::Poll;
+use std::time::{Duration, Instant};
+
+/// Default value of the `net_ticktime` of Erlang nodes.
+pub const DEFAULT_NET_TICKTIME: Duration = Duration::from_secs(60);
+
+/// Keepalive driver of a message channel.
+///
+/// [`Keepalive::recv()`] receives messages while sending [`Message::Tick`] every `net_ticktime / 4`
+/// if no other message has been sent, and fails with [`RecvError::TickTimeout`] if nothing has been
+/// received from the peer within `net_ticktime`.
+///
+/// As `erl_dist` doesn't depend on any specific async runtime, a function to make a timer future
+/// (e.g., `smol::Timer::after` or `tokio::time::sleep`) needs to be given.
+/// The deadline of a timer is regarded as reached when the timer fires, even if the wall clock
+/// says otherwise (e.g., timers of coarse granularity may fire slightly early).
+///
+/// Please see [the official `net_ticktime` doc](https://www.erlang.org/doc/man/kernel_app.html#net_ticktime) for more details.
+pub struct Keepalive<F> {
+    net_ticktime: Duration,
+    sleep: F,
+    now: Instant,
+    last_sent: Instant,
+    last_received: Instant,
+}
+
+impl<F, Fut> Keepalive<F>
+where
+    F: FnMut(Duration) -> Fut,
+    Fut: Future,
+{
+    /// Makes a new [`Keepalive`] instance.
+    pub fn new(net_ticktime: Duration, sleep: F) -> Self {
+        let now = Instant::now();
+        Self {
+            net_ticktime,
+            sleep,
+            now,
+            last_sent: now,
+            last_received: now,
+        }
+    }
+
+    /// Returns the `net_ticktime` of this keepalive driver.
+    pub fn net_ticktime(&self) -> Duration {
+        self.net_ticktime
+    }
+
+    /// Receives a message from `rx` while keeping the connection alive through `tx`.
+    ///
+    /// Ticks sent by the peer are consumed internally and never returned.
+    ///
+    /// This method is cancel-safe, so it can be used in a `select!` loop together with other futures.
+    pub async fn recv<R, W>(
+        &mut self,
+        tx: &mut Sender<W>,
+        rx: &mut Receiver<R>,
+    ) -> Result<Message, RecvError>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let tick_interval = self.net_ticktime / 4;
+        loop {
+            // `self.now` can be ahead of the wall clock if the last timer fired early.
+            self.now = self.now.max(Instant::now());
+            let timeout_at = self.last_received.max(rx.last_received()) + self.net_ticktime;
+            let tick_at = self.last_sent.max(tx.last_sent()) + tick_interval;
+            if timeout_at <= self.now {
+                return Err(RecvError::TickTimeout);
+            }
+            if tick_at <= self.now {
+                tx.send(Message::Tick).await.map_err(|e| match e {
+                    SendError::Io(e) => RecvError::Io(e),
+                    e => std::io::Error::other(e).into(),
+                })?;
+                self.last_sent = self.now;
+                continue;
+            }
+
+            let deadline = timeout_at.min(tick_at);
+            let mut sleep = std::pin::pin!((self.sleep)(deadline - self.now));
+            let frame = futures::future::poll_fn(|cx| {
+                if let Poll::Ready(frame) = rx.poll_recv_raw(cx) {
+                    return Poll::Ready(Some(frame));
+                }
+                sleep.as_mut().poll(cx).map(|_| None)
+            })
+            .await;
+            let Some(frame) = frame else {
+                self.now = deadline;
+                continue;
+            };
+            self.last_received = self.now;
+            let frame = frame?;
+            if !frame.is_tick() {
+                return frame.decode();
+            }
+        }
+    }
+}
+
+impl<F> std::fmt::Debug for Keepalive<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Keepalive")
+            .field("net_ticktime", &self.net_ticktime)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DistributionFlags;
+    use crate::message::channel;
+    use crate::term::{Atom, Pid};
+    use futures::StreamExt as _;
+    use futures::channel::{mpsc, oneshot};
+    use futures::future::Either;
+
+    const NET_TICKTIME: Duration = DEFAULT_NET_TICKTIME;
+
+    type Timers = mpsc::UnboundedReceiver<(Duration, oneshot::Sender<()>)>;
+
+    // Timer fired by hand so that the tests don't depend on the wall clock.
+    fn manual_timer() -> (impl FnMut(Duration) -> oneshot::Receiver<()>, Timers) {
+        let (tx, rx) = mpsc::unbounded();
+        let sleep = move |timeout| {
+            let (fire_tx, fire_rx) = oneshot::channel();
+            let _ = tx.unbounded_send((timeout, fire_tx));
+            fire_rx
+        };
+        (sleep, rx)
+    }
+
+    async fn fire_timers(timers: &mut Timers, timeouts: &mut Vec<Duration>) {
+        while let Some((timeout, fire)) = timers.next().await {
+            timeouts.push(timeout);
+            let _ = fire.send(());
+        }
+    }
+
+    #[test]
+    fn ticks_are_sent_while_idle() {
+        smol::block_on(async {
+            let (client, server) = crate::tests::tcp_stream_pair().await;
+            let (mut tx, mut rx) = channel(client, DistributionFlags::mandatory());
+            let (mut peer_tx, mut peer_rx) = channel(server, DistributionFlags::mandatory());
+            let (sleep, mut timers) = manual_timer();
+            let mut keepalive = Keepalive::new(NET_TICKTIME, sleep);
+
+            let pid = Pid::new("foo@localhost", 0, 0, 0);
+            let msg = Message::send(pid, Atom::from("hello").into());
+            let mut timeouts = Vec::new();
+            let peer = async {
+                for _ in 0..2 {
+                    let recv = std::pin::pin!(peer_rx.recv());
+                    let fire = std::pin::pin!(fire_timers(&mut timers, &mut timeouts));
+                    let Either::Left((tick, _)) = futures::future::select(recv, fire).await else {
+                        panic!()
+                    };
+                    assert_eq!(tick.unwrap(), Message::Tick);
+                    peer_tx.send(Message::Tick).await.unwrap();
+                }
+                peer_tx.send(msg.clone()).await.unwrap();
+            };
+            let (result, ()) = futures::future::join(keepalive.recv(&mut tx, &mut rx), peer).await;
+            assert_eq!(result.unwrap(), msg);
+            assert!(!timeouts.is_empty());
+            assert!(timeouts.iter().all(|&t| t <= NET_TICKTIME / 4));
+        });
+    }
+
+    #[test]
+    fn silent_peer_is_detected() {
+        smol::block_on(async {
+            let (client, server) = crate::tests::tcp_stream_pair().await;
+            let (mut tx, mut rx) = channel(client, DistributionFlags::mandatory());
+            let (_, mut peer_rx) = channel(server, DistributionFlags::mandatory());
+            let (sleep, mut timers) = manual_timer();
+            let mut keepalive = Keepalive::new(NET_TICKTIME, sleep);
+
+            let mut timeouts = Vec::new();
+            let result = {
+                let recv = std::pin::pin!(keepalive.recv(&mut tx, &mut rx));
+                let fire = std::pin::pin!(fire_timers(&mut timers, &mut timeouts));
+                let Either::Left((result, _)) = futures::future::select(recv, fire).await else {
+                    panic!()
+                };
+                result
+            };
+            assert!(matches!(result, Err(RecvError::TickTimeout)));
+
+            // Ticks are sent at 1/4, 2/4 and 3/4 of `net_ticktime`, and then the peer times out.
+            assert_eq!(timeouts.len(), 4);
+            assert!(timeouts[0] <= NET_TICKTIME / 4);
+            assert!(timeouts[1..].iter().all(|&t| t == NET_TICKTIME / 4));
+            for _ in 0..3 {
+                assert_eq!(peer_rx.recv().await.unwrap(), Message::Tick);
+            }
+        });
+    }
+}
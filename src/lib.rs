@@ -73,16 +73,32 @@
 //! - Client Node Example: [send_msg.rs](https://github.com/sile/erl_dist/blob/master/examples/send_msg.rs)
 //! - Server Node Example: [recv_msg.rs](https://github.com/sile/erl_dist/blob/master/examples/recv_msg.rs)
 #![warn(missing_docs)]
+pub mod blocking;
+pub mod connection;
 pub mod epmd;
 pub mod handshake;
+#[cfg(unix)]
+pub mod local;
 pub mod message;
 pub mod node;
 pub mod term;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "tls")]
+pub mod tls;
 
+mod atom_cache;
 mod channel;
 mod eetf_ext;
+mod etf;
 mod flags;
 mod io;
+mod keepalive;
+mod raw_frame;
+mod sender_handle;
+mod stats;
+#[cfg(feature = "tokio")]
+mod tokio_compat;
 
 pub use self::flags::DistributionFlags;
 
@@ -108,14 +124,14 @@ mod tests {
     impl TestErlangNode {
         pub async fn new(name: &str) -> Result<Self, BoxError> {
             let child = Command::new("erl")
-                .args(&["-sname", name, "-noshell", "-setcookie", COOKIE])
+                .args(["-sname", name, "-noshell", "-setcookie", COOKIE])
                 .spawn()?;
             let start = std::time::Instant::now();
             loop {
-                if let Ok(client) = try_epmd_client().await {
-                    if client.get_node(name).await?.is_some() {
-                        break;
-                    }
+                if let Ok(client) = try_epmd_client().await
+                    && client.get_node(name).await?.is_some()
+                {
+                    break;
                 }
                 std::thread::sleep(std::time::Duration::from_millis(500));
                 if start.elapsed() > std::time::Duration::from_secs(10) {
@@ -143,4 +159,12 @@ mod tests {
     pub async fn epmd_client() -> crate::epmd::EpmdClient<smol::net::TcpStream> {
         try_epmd_client().await.unwrap()
     }
+
+    pub async fn tcp_stream_pair() -> (smol::net::TcpStream, smol::net::TcpStream) {
+        let listener = smol::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (client, server) =
+            futures::future::join(smol::net::TcpStream::connect(addr), listener.accept()).await;
+        (client.unwrap(), server.unwrap().0)
+    }
 }
@@ -0,0 +1,255 @@
+//! Distribution over Unix domain sockets (`local` address family).
+//!
+//! Nodes in this family listen on socket files in a directory shared by the nodes of the same host,
+//! one file per node named after the node (e.g., `/tmp/erl_dist/foo@localhost`).
+//! As the socket path is derived from the node name, EPMD is not involved
+//! ([`LocalResolver`] plays its role instead).
+//! [`LocalResolver::get_node()`] returns a [`NodeEntry`] whose protocol is [`TransportProtocol::Local`]
+//! and whose `extra` field holds the socket path, and [`LocalResolver::connect_entry()`] connects to it.
+//!
+//! The handshake and the channel are the same as TCP.
+//! The streams returned by this module can be used with [`crate::blocking`] directly,
+//! or with the async API after wrapping them by your async runtime (e.g., `smol::Async::new()` or
+//! `tokio::net::UnixStream::from_std()`).
+//!
+//! This module is available only on Unix platforms.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use erl_dist::blocking::{ClientSideHandshake, channel};
+//! use erl_dist::local::LocalResolver;
+//! use erl_dist::node::{Creation, LocalNode};
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let resolver = LocalResolver::new("/tmp/erl_dist");
+//! let connection = resolver.connect(&"bar@localhost".parse()?)?;
+//!
+//! let local_node = LocalNode::new("foo@localhost".parse()?, Creation::random());
+//! let mut handshake = ClientSideHandshake::new(&connection, local_node.clone(), "cookie");
+//! handshake.execute_send_name(erl_dist::LOWEST_DISTRIBUTION_PROTOCOL_VERSION)?;
+//! let (_, peer_node) = handshake.execute_rest(true)?;
+//! let (mut tx, mut rx) = channel(&connection, local_node.flags & peer_node.flags);
+//! # Ok(())
+//! # }
+//! ```
+use crate::epmd::{NodeEntry, TransportProtocol};
+use crate::node::NodeName;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt as _;
+use std::os::unix::fs::FileTypeExt as _;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+
+/// Resolver of the socket paths of nodes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LocalResolver {
+    dir: PathBuf,
+}
+
+impl LocalResolver {
+    /// Makes a new [`LocalResolver`] instance.
+    ///
+    /// `dir` is the directory where the socket files of nodes are placed.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Returns the directory of the socket files.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the socket path of the given node.
+    pub fn socket_path(&self, node_name: &NodeName) -> PathBuf {
+        self.dir.join(node_name.to_string())
+    }
+
+    /// Connects to the given node.
+    pub fn connect(&self, node_name: &NodeName) -> std::io::Result<UnixStream> {
+        UnixStream::connect(self.socket_path(node_name))
+    }
+
+    /// Gets the entry of the given node.
+    ///
+    /// This corresponds to [`EpmdClient::get_node()`](crate::epmd::EpmdClient::get_node)
+    /// and returns `None` if the node has no socket file in the directory.
+    /// The protocol of the returned entry is [`TransportProtocol::Local`],
+    /// the port is `0` and the `extra` field holds the socket path.
+    pub fn get_node(&self, node_name: &NodeName) -> std::io::Result<Option<NodeEntry>> {
+        let path = self.socket_path(node_name);
+        match std::fs::metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_socket() => {}
+            Ok(_) => return Ok(None),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let mut entry = NodeEntry::new(node_name.name(), 0);
+        entry.protocol = TransportProtocol::Local;
+        entry.extra = path.as_os_str().as_bytes().to_vec();
+        Ok(Some(entry))
+    }
+
+    /// Connects to the node of the given entry returned by [`LocalResolver::get_node()`].
+    ///
+    /// If the protocol of `entry` isn't [`TransportProtocol::Local`],
+    /// this method fails with [`std::io::ErrorKind::InvalidInput`].
+    pub fn connect_entry(entry: &NodeEntry) -> std::io::Result<UnixStream> {
+        if entry.protocol != TransportProtocol::Local {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("node {} doesn't use a local socket", entry.name),
+            ));
+        }
+        UnixStream::connect(OsStr::from_bytes(&entry.extra))
+    }
+
+    /// Starts listening for connections to the given node.
+    ///
+    /// The directory is created if it doesn't exist.
+    /// If a socket file is left by a node that has exited, it is replaced.
+    /// If another node with the same name is listening, this method fails with
+    /// [`std::io::ErrorKind::AddrInUse`].
+    pub fn bind(&self, node_name: &NodeName) -> std::io::Result<LocalListener> {
+        std::fs::create_dir_all(&self.dir)?;
+        let path = self.socket_path(node_name);
+        if path.exists() {
+            if UnixStream::connect(&path).is_ok() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::AddrInUse,
+                    format!("node {node_name} is already listening on {path:?}"),
+                ));
+            }
+            std::fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        Ok(LocalListener { listener, path })
+    }
+
+    /// Gets the names of all nodes that have a socket file in the directory.
+    ///
+    /// This corresponds to [`EpmdClient::get_names()`](crate::epmd::EpmdClient::get_names).
+    /// Note that the socket files of nodes that have exited abnormally may be included.
+    pub fn get_names(&self) -> std::io::Result<Vec<NodeName>> {
+        let mut names = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_socket() {
+                continue;
+            }
+            if let Some(name) = entry.file_name().to_str().and_then(|s| s.parse().ok()) {
+                names.push(name);
+            }
+        }
+        Ok(names)
+    }
+}
+
+/// Listener of distribution connections over a Unix domain socket.
+///
+/// This is made by [`LocalResolver::bind()`].
+/// The socket file is removed when this listener is dropped.
+#[derive(Debug)]
+pub struct LocalListener {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl LocalListener {
+    /// Accepts a connection.
+    ///
+    /// The handshake needs to be done using [`ServerSideHandshake`](crate::handshake::ServerSideHandshake)
+    /// (or its blocking version) after this.
+    pub fn accept(&self) -> std::io::Result<UnixStream> {
+        let (stream, _) = self.listener.accept()?;
+        Ok(stream)
+    }
+
+    /// Returns the path of the socket file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns a reference to the underlying listener (e.g., to make it non-blocking).
+    pub fn get_ref(&self) -> &UnixListener {
+        &self.listener
+    }
+}
+
+impl Drop for LocalListener {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocking::{ClientSideHandshake, ServerSideHandshake, channel};
+    use crate::handshake::HandshakeStatus;
+    use crate::message::Message;
+    use crate::node::{Creation, LocalNode};
+    use crate::term::{Atom, Pid};
+
+    #[test]
+    fn local_connections_work() {
+        let dir = std::env::temp_dir().join(format!("erl_dist_test_{}", std::process::id()));
+        let resolver = LocalResolver::new(&dir);
+        let server_name: NodeName = "bar@localhost".parse().unwrap();
+        let listener = resolver.bind(&server_name).unwrap();
+        assert_eq!(resolver.get_names().unwrap(), vec![server_name.clone()]);
+        let entry = resolver.get_node(&server_name).unwrap().unwrap();
+        assert_eq!(entry.name, "bar");
+        assert_eq!(entry.protocol, TransportProtocol::Local);
+        assert_eq!(entry.extra, listener.path().as_os_str().as_bytes());
+        assert!(
+            resolver
+                .get_node(&"baz@localhost".parse().unwrap())
+                .unwrap()
+                .is_none()
+        );
+
+        let pid = Pid::new("foo@localhost", 0, 0, 0);
+        let msg = Message::send(pid, Atom::from("hello").into());
+        let client = std::thread::spawn({
+            let msg = msg.clone();
+            let resolver = resolver.clone();
+            move || {
+                let entry = resolver.get_node(&server_name).unwrap().unwrap();
+                let connection = LocalResolver::connect_entry(&entry).unwrap();
+                let local_node =
+                    LocalNode::new("foo@localhost".parse().unwrap(), Creation::random());
+                let flags = local_node.flags;
+                let mut handshake =
+                    ClientSideHandshake::new(&connection, local_node, crate::tests::COOKIE);
+                handshake
+                    .execute_send_name(crate::LOWEST_DISTRIBUTION_PROTOCOL_VERSION)
+                    .unwrap();
+                let (_, peer_node) = handshake.execute_rest(true).unwrap();
+                let (mut tx, _) = channel(&connection, flags & peer_node.flags);
+                tx.send(msg).unwrap();
+            }
+        });
+
+        let connection = listener.accept().unwrap();
+        let local_node = LocalNode::new("bar@localhost".parse().unwrap(), Creation::random());
+        let flags = local_node.flags;
+        let mut handshake = ServerSideHandshake::new(&connection, local_node, crate::tests::COOKIE);
+        handshake.execute_recv_name().unwrap();
+        let (_, peer_node) = handshake.execute_rest(HandshakeStatus::Ok).unwrap();
+        let (_, mut rx) = channel(&connection, flags & peer_node.flags);
+        assert_eq!(rx.recv().unwrap(), msg);
+        client.join().unwrap();
+
+        let server_name = "bar@localhost".parse().unwrap();
+        assert_eq!(
+            resolver.bind(&server_name).unwrap_err().kind(),
+            std::io::ErrorKind::AddrInUse
+        );
+
+        let path = listener.path().to_path_buf();
+        std::mem::drop(listener);
+        assert!(!path.exists());
+        let _ = std::fs::remove_dir(&dir);
+    }
+}
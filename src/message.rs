@@ -1,9 +1,6 @@
 //! Messages passed between distributed nodes, and channels for those messages.
 //!
 //! Reference: [Protocol between Connected Nodes](https://www.erlang.org/doc/apps/erts/erl_dist_protocol.html#protocol-between-connected-nodes)
-//!
-//! Note that distribution headers are not supported in the current version.
-#[cfg(doc)]
 use crate::DistributionFlags;
 use crate::eetf_ext;
 use crate::io::{ReadTermExt, WriteTermExt};
@@ -11,7 +8,17 @@ use crate::term::{Atom, FixInteger, List, Mfa, Pid, PidOrAtom, Reference, Term,
 use eetf::{DecodeError, EncodeError};
 use std::io::{Read, Write};
 
-pub use crate::channel::{Receiver, RecvError, SendError, Sender, channel};
+pub use crate::channel::{
+    DEFAULT_FRAGMENT_SIZE, DEFAULT_MAX_FRAGMENT_BUFFER_SIZE, DEFAULT_MAX_FRAGMENTED_MESSAGES,
+    DEFAULT_MAX_FRAME_SIZE, DEFAULT_MAX_TERM_DEPTH, FrameDecoder, FrameEncoder, Receiver,
+    RecvError, SendError, Sender, channel, channel_from_halves, split_channel,
+};
+pub use crate::keepalive::{DEFAULT_NET_TICKTIME, Keepalive};
+pub use crate::raw_frame::RawFrame;
+pub use crate::sender_handle::{DEFAULT_SEND_QUEUE_SIZE, SenderHandle, SenderWriter};
+pub use crate::stats::{TrafficStats, TrafficStatsHandle};
+#[cfg(feature = "tokio")]
+pub use crate::tokio_compat::tokio_channel;
 
 trait DistributionMessage: Sized {
     const OP: i32;
@@ -965,6 +972,21 @@ pub enum Message {
     AliasSend(AliasSend),
     AliasSendTt(AliasSendTt),
 
+    /// Message whose operation is not known by this crate.
+    ///
+    /// This keeps the decoded control message and payload so that the message can be
+    /// forwarded or re-encoded as is.
+    Unknown {
+        /// Operation code.
+        op: i32,
+
+        /// Whole control message (including the operation code as the first element).
+        control: Tuple,
+
+        /// Payload term following the control message if exists.
+        payload: Option<Term>,
+    },
+
     /// Tick message used for keeping alive a connection.
     ///
     /// See also: [`net_ticktime` parameter](https://www.erlang.org/doc/man/kernel_app.html#net_ticktime)
@@ -1282,6 +1304,108 @@ impl Message {
         })
     }
 
+    /// Returns the operation code of this message, or `None` for [`Message::Tick`].
+    pub fn op(&self) -> Option<i32> {
+        let op = match self {
+            Self::Link(_) => Link::OP,
+            Self::Send(_) => Send::OP,
+            Self::Exit(_) => Exit::OP,
+            Self::Unlink(_) => Unlink::OP,
+            Self::NodeLink(_) => NodeLink::OP,
+            Self::RegSend(_) => RegSend::OP,
+            Self::GroupLeader(_) => GroupLeader::OP,
+            Self::Exit2(_) => Exit2::OP,
+            Self::SendTt(_) => SendTt::OP,
+            Self::ExitTt(_) => ExitTt::OP,
+            Self::RegSendTt(_) => RegSendTt::OP,
+            Self::Exit2Tt(_) => Exit2Tt::OP,
+            Self::MonitorP(_) => MonitorP::OP,
+            Self::DemonitorP(_) => DemonitorP::OP,
+            Self::MonitorPExit(_) => MonitorPExit::OP,
+            Self::SendSender(_) => SendSender::OP,
+            Self::SendSenderTt(_) => SendSenderTt::OP,
+            Self::PayloadExit(_) => PayloadExit::OP,
+            Self::PayloadExitTt(_) => PayloadExitTt::OP,
+            Self::PayloadExit2(_) => PayloadExit2::OP,
+            Self::PayloadExit2Tt(_) => PayloadExit2Tt::OP,
+            Self::PayloadMonitorPExit(_) => PayloadMonitorPExit::OP,
+            Self::SpawnRequest(_) => SpawnRequest::OP,
+            Self::SpawnRequestTt(_) => SpawnRequestTt::OP,
+            Self::SpawnReply(_) => SpawnReply::OP,
+            Self::SpawnReplyTt(_) => SpawnReplyTt::OP,
+            Self::UnlinkId(_) => UnlinkId::OP,
+            Self::UnlinkIdAck(_) => UnlinkIdAck::OP,
+            Self::AliasSend(_) => AliasSend::OP,
+            Self::AliasSendTt(_) => AliasSendTt::OP,
+            Self::Unknown { op, .. } => *op,
+            Self::Tick => return None,
+        };
+        Some(op)
+    }
+
+    /// Converts this message into one that can be handled by a peer having the given distribution flags.
+    ///
+    /// If the peer doesn't support this message, the documented fallback message is used if exists.
+    pub(crate) fn fit_to_flags(
+        self,
+        flags: DistributionFlags,
+    ) -> Result<Self, crate::channel::SendError> {
+        let msg = match self {
+            Self::SendSender(x) if !flags.contains(DistributionFlags::SEND_SENDER) => {
+                Self::send(x.to_pid, x.message)
+            }
+            Self::SendSenderTt(x) if !flags.contains(DistributionFlags::SEND_SENDER) => {
+                Self::send_tt(x.to_pid, x.message, x.trace_token)
+            }
+            Self::PayloadExit(x) if !flags.contains(DistributionFlags::EXIT_PAYLOAD) => {
+                Self::exit(x.from_pid, x.to_pid, x.reason)
+            }
+            Self::PayloadExitTt(x) if !flags.contains(DistributionFlags::EXIT_PAYLOAD) => {
+                Self::exit_tt(x.from_pid, x.to_pid, x.reason, x.trace_token)
+            }
+            Self::PayloadExit2(x) if !flags.contains(DistributionFlags::EXIT_PAYLOAD) => {
+                Self::exit2(x.from_pid, x.to_pid, x.reason)
+            }
+            Self::PayloadExit2Tt(x) if !flags.contains(DistributionFlags::EXIT_PAYLOAD) => {
+                Self::exit2_tt(x.from_pid, x.to_pid, x.reason, x.trace_token)
+            }
+            Self::PayloadMonitorPExit(x) if !flags.contains(DistributionFlags::EXIT_PAYLOAD) => {
+                Self::monitor_p_exit(x.from_proc, x.to_pid, x.reference, x.reason)
+            }
+            Self::UnlinkId(x) if !flags.contains(DistributionFlags::UNLINK_ID) => {
+                Self::unlink(x.from_pid, x.to_pid)
+            }
+            msg => msg,
+        };
+
+        let monitor_flags = |proc: &PidOrAtom| match proc {
+            PidOrAtom::Pid(_) => DistributionFlags::DIST_MONITOR,
+            PidOrAtom::Atom(_) => {
+                DistributionFlags::DIST_MONITOR | DistributionFlags::DIST_MONITOR_NAME
+            }
+        };
+        let required_flags = match &msg {
+            Self::MonitorP(x) => monitor_flags(&x.to_proc),
+            Self::DemonitorP(x) => monitor_flags(&x.to_proc),
+            Self::MonitorPExit(x) => monitor_flags(&x.from_proc),
+            Self::PayloadMonitorPExit(x) => monitor_flags(&x.from_proc),
+            Self::SpawnRequest(_)
+            | Self::SpawnRequestTt(_)
+            | Self::SpawnReply(_)
+            | Self::SpawnReplyTt(_) => DistributionFlags::SPAWN,
+            Self::UnlinkIdAck(_) => DistributionFlags::UNLINK_ID,
+            Self::AliasSend(_) | Self::AliasSendTt(_) => DistributionFlags::ALIAS,
+            _ => return Ok(msg),
+        };
+        if !flags.contains(required_flags) {
+            return Err(crate::channel::SendError::UnsupportedByPeer {
+                op: msg.op().expect("unreachable"),
+                required_flags,
+            });
+        }
+        Ok(msg)
+    }
+
     /// Serialize the [Message] into a byte buffer
     pub fn write_into<W: Write>(self, writer: &mut W) -> Result<(), crate::channel::SendError> {
         match self {
@@ -1315,6 +1439,14 @@ impl Message {
             Self::UnlinkIdAck(x) => x.write_into(writer)?,
             Self::AliasSend(x) => x.write_into(writer)?,
             Self::AliasSendTt(x) => x.write_into(writer)?,
+            Self::Unknown {
+                control, payload, ..
+            } => {
+                writer.write_term(control)?;
+                if let Some(payload) = payload {
+                    writer.write_term(payload)?;
+                }
+            }
             Self::Tick => unreachable!(),
         }
         Ok(())
@@ -1323,19 +1455,17 @@ impl Message {
     /// Deserialize a given byte buffer into a [Message]. Returns [Err(_)] if the message
     /// is malformed
     pub fn read_from<R: Read>(reader: &mut R) -> Result<Self, crate::channel::RecvError> {
-        let mut ctrl_msg = reader.read_tuple()?;
-        if ctrl_msg.elements.is_empty() {
-            return Err(DecodeError::UnexpectedType {
-                value: ctrl_msg.into(),
-                expected: "non empty tuple".to_owned(),
-            }
-            .into());
-        }
-        let op: FixInteger = eetf_ext::try_from_term(
-            std::mem::replace(&mut ctrl_msg.elements[0], eetf_ext::nil()),
-            "integer",
-        )?;
-        let msg = match op.value {
+        let ctrl_msg = reader.read_tuple()?;
+        Self::read_with_control(ctrl_msg, reader)
+    }
+
+    fn read_with_control<R: Read>(
+        mut ctrl_msg: Tuple,
+        reader: &mut R,
+    ) -> Result<Self, crate::channel::RecvError> {
+        let op = control_op(&ctrl_msg)?;
+        ctrl_msg.elements[0] = eetf_ext::nil();
+        let msg = match op {
             Link::OP => Link::read_from(reader, ctrl_msg).map(Self::Link)?,
             Send::OP => Send::read_from(reader, ctrl_msg).map(Self::Send)?,
             Exit::OP => Exit::read_from(reader, ctrl_msg).map(Self::Exit)?,
@@ -1384,8 +1514,177 @@ impl Message {
             UnlinkIdAck::OP => UnlinkIdAck::read_from(reader, ctrl_msg).map(Self::UnlinkIdAck)?,
             AliasSend::OP => AliasSend::read_from(reader, ctrl_msg).map(Self::AliasSend)?,
             AliasSendTt::OP => AliasSendTt::read_from(reader, ctrl_msg).map(Self::AliasSendTt)?,
-            op => return Err(crate::channel::RecvError::UnsupportedOp { op }),
+            op => {
+                ctrl_msg.elements[0] = FixInteger::from(op).into();
+                let mut rest = Vec::new();
+                reader.read_to_end(&mut rest)?;
+                let mut rest = rest.as_slice();
+                let payload = if rest.is_empty() {
+                    None
+                } else {
+                    Some(rest.read_term()?)
+                };
+                // Otherwise, the message couldn't be re-encoded as is.
+                if !rest.is_empty() {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "unknown message has extra terms after the payload",
+                    )
+                    .into());
+                }
+                Self::Unknown {
+                    op,
+                    control: ctrl_msg,
+                    payload,
+                }
+            }
         };
         Ok(msg)
     }
 }
+
+fn control_op(ctrl_msg: &Tuple) -> Result<i32, DecodeError> {
+    match ctrl_msg.elements.first() {
+        Some(Term::FixInteger(op)) => Ok(op.value),
+        Some(_) => Err(DecodeError::UnexpectedType {
+            value: ctrl_msg.elements[0].clone(),
+            expected: "integer".to_owned(),
+        }),
+        None => Err(DecodeError::UnexpectedType {
+            value: ctrl_msg.clone().into(),
+            expected: "non empty tuple".to_owned(),
+        }),
+    }
+}
+
+/// Message whose payload has not been decoded yet.
+///
+/// This is useful to route or drop messages by looking at only their control messages.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LazyMessage {
+    control: Option<(i32, Tuple)>,
+    payload: Option<Payload>,
+}
+
+impl LazyMessage {
+    pub(crate) fn from_raw(frame: RawFrame) -> Result<Self, crate::channel::RecvError> {
+        if frame.is_tick() {
+            return Ok(Self {
+                control: None,
+                payload: None,
+            });
+        }
+        let ctrl_msg = frame.as_bytes().read_tuple()?;
+        let op = control_op(&ctrl_msg)?;
+        let control_len = frame.control_bytes().len();
+        let payload = (control_len < frame.as_bytes().len()).then(|| Payload {
+            bytes: frame.into_bytes(),
+            offset: control_len,
+        });
+        Ok(Self {
+            control: Some((op, ctrl_msg)),
+            payload,
+        })
+    }
+
+    /// Returns `true` if this message is a tick.
+    pub fn is_tick(&self) -> bool {
+        self.control.is_none()
+    }
+
+    /// Returns the operation code of this message, or `None` if this message is a tick.
+    pub fn op(&self) -> Option<i32> {
+        self.control.as_ref().map(|(op, _)| *op)
+    }
+
+    /// Returns the control message, or `None` if this message is a tick.
+    pub fn control(&self) -> Option<&Tuple> {
+        self.control.as_ref().map(|(_, ctrl_msg)| ctrl_msg)
+    }
+
+    /// Returns the sender process of this message if the control message contains it.
+    pub fn from_pid(&self) -> Option<&Pid> {
+        let index = match self.op()? {
+            Link::OP | Exit::OP | Unlink::OP | RegSend::OP | GroupLeader::OP | Exit2::OP => 1,
+            ExitTt::OP | RegSendTt::OP | Exit2Tt::OP | MonitorP::OP | DemonitorP::OP => 1,
+            MonitorPExit::OP | SendSender::OP | SendSenderTt::OP => 1,
+            PayloadExit::OP | PayloadExitTt::OP | PayloadExit2::OP | PayloadExit2Tt::OP => 1,
+            PayloadMonitorPExit::OP | AliasSend::OP | AliasSendTt::OP => 1,
+            SpawnRequest::OP | SpawnRequestTt::OP | UnlinkId::OP | UnlinkIdAck::OP => 2,
+            _ => return None,
+        };
+        self.pid_element(index)
+    }
+
+    /// Returns the destination process of this message if the control message contains it.
+    pub fn to_pid(&self) -> Option<&Pid> {
+        let index = match self.op()? {
+            Link::OP | Send::OP | Exit::OP | Unlink::OP | GroupLeader::OP | Exit2::OP => 2,
+            SendTt::OP | ExitTt::OP | Exit2Tt::OP | MonitorP::OP | DemonitorP::OP => 2,
+            MonitorPExit::OP | SendSender::OP | SendSenderTt::OP => 2,
+            PayloadExit::OP | PayloadExitTt::OP | PayloadExit2::OP | PayloadExit2Tt::OP => 2,
+            PayloadMonitorPExit::OP | SpawnReply::OP | SpawnReplyTt::OP => 2,
+            UnlinkId::OP | UnlinkIdAck::OP => 3,
+            _ => return None,
+        };
+        self.pid_element(index)
+    }
+
+    /// Returns the registered name of the destination process if the control message contains it.
+    pub fn to_name(&self) -> Option<&Atom> {
+        let index = match self.op()? {
+            RegSend::OP | RegSendTt::OP => 3,
+            MonitorP::OP | DemonitorP::OP => 2,
+            _ => return None,
+        };
+        match self.control()?.elements.get(index)? {
+            Term::Atom(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Returns the payload of this message if exists.
+    pub fn payload(&self) -> Option<&Payload> {
+        self.payload.as_ref()
+    }
+
+    /// Converts this message into the payload.
+    pub fn into_payload(self) -> Option<Payload> {
+        self.payload
+    }
+
+    /// Decodes the payload and converts this message into a [`Message`].
+    pub fn decode(self) -> Result<Message, crate::channel::RecvError> {
+        let Some((_, ctrl_msg)) = self.control else {
+            return Ok(Message::Tick);
+        };
+        let mut payload = self.payload.as_ref().map_or(&[][..], |p| p.as_bytes());
+        Message::read_with_control(ctrl_msg, &mut payload)
+    }
+
+    fn pid_element(&self, index: usize) -> Option<&Pid> {
+        match self.control()?.elements.get(index)? {
+            Term::Pid(pid) => Some(pid),
+            _ => None,
+        }
+    }
+}
+
+/// Undecoded payload of a [`LazyMessage`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Payload {
+    bytes: Vec<u8>,
+    offset: usize,
+}
+
+impl Payload {
+    /// Returns the encoded bytes of the payload term (starting with the version magic).
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[self.offset..]
+    }
+
+    /// Decodes the payload term.
+    pub fn decode(&self) -> Result<Term, crate::channel::RecvError> {
+        Ok(self.as_bytes().read_term()?)
+    }
+}
@@ -0,0 +1,192 @@
+use crate::channel::{DEFAULT_MAX_TERM_DEPTH, RecvError, SendError};
+use crate::etf;
+use crate::io::ReadTermExt;
+use crate::message::{LazyMessage, Message};
+use eetf::Tuple;
+
+/// Undecoded message.
+///
+/// This is useful to forward messages between connections without decoding and re-encoding them.
+///
+/// A frame holds the message in the pass-through form, that is,
+/// the control message and the optional payload, each of which starts with the version magic `131`.
+/// Atom cache references and fragments in the received frames are resolved by [`Receiver::recv_raw()`],
+/// so the frame doesn't depend on the state of the connection it was received from.
+///
+/// [`Receiver::recv_raw()`]: crate::message::Receiver::recv_raw
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RawFrame {
+    bytes: Vec<u8>,
+}
+
+impl RawFrame {
+    /// Makes a new [`RawFrame`] instance from the bytes of a message in the pass-through form.
+    ///
+    /// The terms in `bytes` are validated using [`DEFAULT_MAX_TERM_DEPTH`].
+    /// Empty `bytes` are rejected as they lack the control message (use [`RawFrame::tick()`] for ticks).
+    pub fn new(bytes: Vec<u8>) -> Result<Self, RecvError> {
+        Self::validate(bytes, DEFAULT_MAX_TERM_DEPTH)
+    }
+
+    /// Makes a [`RawFrame`] instance representing a tick.
+    pub fn tick() -> Self {
+        Self { bytes: Vec::new() }
+    }
+
+    /// Encodes a message into a [`RawFrame`].
+    pub fn from_message(message: Message) -> Result<Self, SendError> {
+        let mut bytes = Vec::new();
+        if !matches!(message, Message::Tick) {
+            message.write_into(&mut bytes)?;
+        }
+        Ok(Self { bytes })
+    }
+
+    pub(crate) fn validate(bytes: Vec<u8>, max_term_depth: usize) -> Result<Self, RecvError> {
+        if bytes.is_empty() {
+            return Err(missing_control_message().into());
+        }
+        let mut offset = 0;
+        while offset < bytes.len() {
+            offset += etf::check_term(&bytes[offset..], max_term_depth)?;
+        }
+        Ok(Self { bytes })
+    }
+
+    /// Makes a [`RawFrame`] instance from bytes that have already been validated.
+    pub(crate) fn from_validated_bytes(bytes: Vec<u8>) -> Self {
+        Self { bytes }
+    }
+
+    /// Returns `true` if this frame is a tick.
+    pub fn is_tick(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Returns the bytes of this frame.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Converts this frame into the bytes.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// Returns the bytes of the control message (empty if this frame is a tick).
+    pub fn control_bytes(&self) -> &[u8] {
+        &self.bytes[..self.control_len()]
+    }
+
+    /// Returns the bytes of the payload if exists.
+    pub fn payload_bytes(&self) -> Option<&[u8]> {
+        let payload = &self.bytes[self.control_len()..];
+        (!payload.is_empty()).then_some(payload)
+    }
+
+    /// Decodes only the control message of this frame.
+    ///
+    /// Returns `None` if this frame is a tick.
+    pub fn decode_control(&self) -> Result<Option<Tuple>, RecvError> {
+        if self.is_tick() {
+            return Ok(None);
+        }
+        Ok(Some(self.control_bytes().read_tuple()?))
+    }
+
+    /// Returns the operation code of the control message.
+    ///
+    /// Returns `None` if this frame is a tick.
+    pub fn op(&self) -> Result<Option<i32>, RecvError> {
+        let Some(ctrl_msg) = self.decode_control()? else {
+            return Ok(None);
+        };
+        match ctrl_msg.elements.first() {
+            Some(eetf::Term::FixInteger(op)) => Ok(Some(op.value)),
+            _ => Err(eetf::DecodeError::UnexpectedType {
+                value: ctrl_msg.into(),
+                expected: "tuple starting with an integer".to_owned(),
+            }
+            .into()),
+        }
+    }
+
+    /// Returns the operation code without decoding the control message if possible.
+    pub(crate) fn peek_op(&self) -> Option<i32> {
+        match self.bytes.as_slice() {
+            [
+                etf::VERSION_MAGIC,
+                etf::SMALL_TUPLE_EXT,
+                _,
+                etf::SMALL_INTEGER_EXT,
+                op,
+                ..,
+            ] => Some(i32::from(*op)),
+            _ => self.op().ok().flatten(),
+        }
+    }
+
+    /// Decodes this frame into a [`Message`].
+    pub fn decode(&self) -> Result<Message, RecvError> {
+        if self.is_tick() {
+            return Ok(Message::Tick);
+        }
+        Message::read_from(&mut self.bytes.as_slice())
+    }
+
+    /// Decodes only the control message of this frame and converts it into a [`LazyMessage`].
+    pub fn decode_lazy(self) -> Result<LazyMessage, RecvError> {
+        LazyMessage::from_raw(self)
+    }
+
+    fn control_len(&self) -> usize {
+        if self.is_tick() {
+            return 0;
+        }
+        etf::check_term(&self.bytes, usize::MAX).expect("unreachable")
+    }
+}
+
+pub(crate) fn missing_control_message() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "frame has no control message",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::term::{Atom, Pid};
+
+    #[test]
+    fn raw_frame_works() {
+        let pid = Pid::new("foo@localhost", 0, 0, 0);
+        let msg = Message::reg_send(pid, Atom::from("bar"), Atom::from("baz").into());
+        let frame = RawFrame::from_message(msg.clone()).unwrap();
+        assert!(!frame.is_tick());
+        assert_eq!(frame.op().unwrap(), Some(6));
+        assert_eq!(frame.decode().unwrap(), msg);
+
+        let payload = frame.payload_bytes().unwrap();
+        assert_eq!(payload, [131, 119, 3, b'b', b'a', b'z']);
+        assert_eq!(
+            frame.control_bytes().len() + payload.len(),
+            frame.as_bytes().len()
+        );
+
+        let frame = RawFrame::new(frame.into_bytes()).unwrap();
+        assert_eq!(frame.decode().unwrap(), msg);
+
+        let tick = RawFrame::from_message(Message::Tick).unwrap();
+        assert_eq!(tick, RawFrame::tick());
+        assert_eq!(tick.decode_control().unwrap(), None);
+        assert_eq!(tick.decode().unwrap(), Message::Tick);
+
+        // Atom cache references are not allowed in the pass-through form.
+        assert!(RawFrame::new(vec![131, 82, 0]).is_err());
+
+        // Only `RawFrame::tick()` makes an empty frame.
+        assert!(RawFrame::new(Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,286 @@
+use crate::DistributionFlags;
+use crate::channel::{SendError, Sender};
+use crate::message::Message;
+use futures::StreamExt as _;
+use futures::channel::mpsc;
+use futures::io::AsyncWrite;
+use futures::task::AtomicWaker;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll, ready};
+
+/// Default value of the `queue_size` argument of [`Sender::into_handle()`].
+pub const DEFAULT_SEND_QUEUE_SIZE: usize = 1024;
+
+// The writer stops draining the queue while this many bytes are waiting to be written.
+const MAX_BATCH_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Default)]
+struct TickState {
+    requested: AtomicBool,
+    waker: AtomicWaker,
+}
+
+/// Cloneable handle to send messages through a [`Sender`] shared by multiple tasks.
+///
+/// Messages are pushed into a bounded queue which is drained by [`SenderWriter::run()`].
+/// Note that, as with [`futures::channel::mpsc`], each handle has a dedicated slot in addition to
+/// the queue size.
+///
+/// This handle is made by [`Sender::into_handle()`].
+#[derive(Debug, Clone)]
+pub struct SenderHandle {
+    queue: mpsc::Sender<Message>,
+    tick: Arc<TickState>,
+    flags: DistributionFlags,
+}
+
+impl SenderHandle {
+    /// Sends a message, waiting for the queue to have room for it.
+    ///
+    /// As with [`Sender::send()`], messages that the peer doesn't support are converted into their
+    /// fallback messages or rejected with [`SendError::UnsupportedByPeer`] before being queued.
+    ///
+    /// Returns [`SendError::Closed`] if the writer has been stopped.
+    pub async fn send(&mut self, message: Message) -> Result<(), SendError> {
+        if matches!(message, Message::Tick) {
+            return self.send_tick();
+        }
+        let message = message.fit_to_flags(self.flags)?;
+        futures::SinkExt::send(&mut self.queue, message)
+            .await
+            .map_err(|_| SendError::Closed)
+    }
+
+    /// Tries to send a message without waiting.
+    ///
+    /// Returns [`SendError::QueueFull`] if the queue is full.
+    /// Otherwise, this behaves the same as [`SenderHandle::send()`].
+    pub fn try_send(&mut self, message: Message) -> Result<(), SendError> {
+        if matches!(message, Message::Tick) {
+            return self.send_tick();
+        }
+        let message = message.fit_to_flags(self.flags)?;
+        self.queue.try_send(message).map_err(|e| {
+            if e.is_full() {
+                SendError::QueueFull
+            } else {
+                SendError::Closed
+            }
+        })
+    }
+
+    /// Requests the writer to send a [`Message::Tick`].
+    ///
+    /// Ticks bypass the queue, so they are sent ahead of the queued messages even if the queue is full.
+    /// Multiple requests made before the writer handles them are coalesced into a single tick.
+    pub fn send_tick(&self) -> Result<(), SendError> {
+        if self.queue.is_closed() {
+            return Err(SendError::Closed);
+        }
+        self.tick.requested.store(true, Ordering::SeqCst);
+        self.tick.waker.wake();
+        Ok(())
+    }
+
+    /// Returns `true` if the writer has been stopped.
+    pub fn is_closed(&self) -> bool {
+        self.queue.is_closed()
+    }
+}
+
+/// Writer that drains the queue of [`SenderHandle`]s.
+///
+/// This is made by [`Sender::into_handle()`] and needs to be driven by [`SenderWriter::run()`]
+/// (e.g., by spawning it as a task).
+#[derive(Debug)]
+pub struct SenderWriter<T> {
+    sender: Sender<T>,
+    queue: mpsc::Receiver<Message>,
+    tick: Arc<TickState>,
+}
+
+impl<T> SenderWriter<T>
+where
+    T: AsyncWrite + Unpin,
+{
+    /// Writes the queued messages to the connection until all the handles are dropped.
+    ///
+    /// Messages that are queued at the same time are written in a batch.
+    /// Messages are written in the order they are dequeued, while ticks are written between
+    /// the fragments of large messages (see [`Sender::set_fragment_size()`]).
+    /// If an error occurs, this method returns it and the subsequent sends of the handles fail with
+    /// [`SendError::Closed`].
+    pub async fn run(mut self) -> Result<(), SendError> {
+        let result = futures::future::poll_fn(|cx| self.poll_run(cx)).await;
+        self.queue.close();
+        result
+    }
+
+    fn poll_run(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), SendError>> {
+        self.tick.waker.register(cx.waker());
+        loop {
+            let mut progress = false;
+            let mut finished = false;
+            // Ticks are written right after the current frame (e.g., a fragment of a large message).
+            if self.tick.requested.swap(false, Ordering::SeqCst) {
+                self.sender.feed(Message::Tick)?;
+                progress = true;
+            }
+            while self.sender.buffered_len() < MAX_BATCH_SIZE {
+                match self.queue.poll_next_unpin(cx) {
+                    Poll::Ready(Some(message)) => {
+                        self.sender.feed(message)?;
+                        progress = true;
+                    }
+                    Poll::Ready(None) => {
+                        finished = true;
+                        break;
+                    }
+                    Poll::Pending => break,
+                }
+            }
+            ready!(self.sender.poll_flush_buf(cx))?;
+            if finished {
+                return Poll::Ready(Ok(()));
+            }
+            if !progress {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+impl<T> Sender<T>
+where
+    T: AsyncWrite + Unpin,
+{
+    /// Converts this sender into a cloneable [`SenderHandle`] and the [`SenderWriter`] that
+    /// writes the messages sent through the handle.
+    ///
+    /// `queue_size` is the capacity of the queue shared by the handles.
+    /// When the queue is full, [`SenderHandle::send()`] waits and [`SenderHandle::try_send()`] fails
+    /// with [`SendError::QueueFull`].
+    /// [`DEFAULT_SEND_QUEUE_SIZE`] is a reasonable default value.
+    pub fn into_handle(self, queue_size: usize) -> (SenderHandle, SenderWriter<T>) {
+        let (tx, rx) = mpsc::channel(queue_size);
+        let tick = Arc::new(TickState::default());
+        let handle = SenderHandle {
+            queue: tx,
+            tick: Arc::clone(&tick),
+            flags: self.flags(),
+        };
+        let writer = SenderWriter {
+            sender: self,
+            queue: rx,
+            tick,
+        };
+        (handle, writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::channel;
+    use crate::term::{Atom, Binary, FixInteger, Pid, Term, Tuple};
+
+    #[test]
+    fn sender_handles_work() {
+        smol::block_on(async {
+            let (client, server) = crate::tests::tcp_stream_pair().await;
+            let (tx, _) = channel(client, DistributionFlags::mandatory());
+            let (_, mut rx) = channel(server, DistributionFlags::mandatory());
+            let (handle, writer) = tx.into_handle(2);
+            let writer = smol::spawn(writer.run());
+
+            let pid = Pid::new("foo@localhost", 0, 0, 0);
+            let tasks = (0..4)
+                .map(|i| {
+                    let mut handle = handle.clone();
+                    let pid = pid.clone();
+                    smol::spawn(async move {
+                        for j in 0..10 {
+                            let msg =
+                                Message::send(pid.clone(), FixInteger::from(i * 10 + j).into());
+                            handle.send(msg).await.unwrap();
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            std::mem::drop(handle);
+
+            let mut received = Vec::new();
+            for _ in 0..40 {
+                let Message::Send(msg) = rx.recv().await.unwrap() else {
+                    panic!()
+                };
+                let Term::FixInteger(i) = msg.message else {
+                    panic!()
+                };
+                received.push(i.value);
+            }
+            for task in tasks {
+                task.await;
+            }
+            writer.await.unwrap();
+
+            received.sort();
+            assert_eq!(received, (0..40).collect::<Vec<_>>());
+        });
+    }
+
+    #[test]
+    fn ticks_jump_the_queue() {
+        smol::block_on(async {
+            let (client, server) = crate::tests::tcp_stream_pair().await;
+            let (tx, _) = channel(client, DistributionFlags::mandatory());
+            let (_, mut rx) = channel(server, DistributionFlags::mandatory());
+            let (mut handle, writer) = tx.into_handle(1);
+
+            let pid = Pid::new("foo@localhost", 0, 0, 0);
+            let msg = Message::send(pid, Atom::from("hello").into());
+            handle.try_send(msg.clone()).unwrap();
+            handle.try_send(msg.clone()).unwrap();
+            assert!(matches!(
+                handle.try_send(msg.clone()),
+                Err(SendError::QueueFull)
+            ));
+            handle.send_tick().unwrap();
+            std::mem::drop(handle);
+
+            writer.run().await.unwrap();
+            assert_eq!(rx.recv().await.unwrap(), Message::Tick);
+            assert_eq!(rx.recv().await.unwrap(), msg);
+            assert_eq!(rx.recv().await.unwrap(), msg);
+        });
+    }
+    #[test]
+    fn large_messages_are_not_overtaken() {
+        smol::block_on(async {
+            let flags = DistributionFlags::mandatory()
+                | DistributionFlags::DIST_HDR_ATOM_CACHE
+                | DistributionFlags::FRAGMENTS;
+            let (client, server) = crate::tests::tcp_stream_pair().await;
+            let (mut tx, _) = channel(client, flags);
+            let (_, mut rx) = channel(server, flags);
+            tx.set_fragment_size(64);
+            let (mut handle, writer) = tx.into_handle(DEFAULT_SEND_QUEUE_SIZE);
+
+            let pid = Pid::new("foo@localhost", 0, 0, 0);
+            let term = Tuple::from(vec![
+                Atom::from("hello").into(),
+                Binary::from(vec![7; 1000]).into(),
+            ]);
+            let large = Message::send(pid.clone(), term.into());
+            let small = Message::send(pid, Atom::from("hello").into());
+            handle.send(large.clone()).await.unwrap();
+            handle.send(small.clone()).await.unwrap();
+            std::mem::drop(handle);
+
+            writer.run().await.unwrap();
+            assert_eq!(rx.recv().await.unwrap(), large);
+            assert_eq!(rx.recv().await.unwrap(), small);
+        });
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Snapshot of the traffic statistics of one direction of a channel.
+///
+/// This is comparable to `erlang:dist_get_stat/1`.
+/// The statistics of the sending side are obtained by [`Sender::stats()`] and those of
+/// the receiving side by [`Receiver::stats()`].
+///
+/// [`Sender::stats()`]: crate::message::Sender::stats
+/// [`Receiver::stats()`]: crate::message::Receiver::stats
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrafficStats {
+    /// Number of bytes written to or read from the connection.
+    pub bytes: u64,
+
+    /// Number of frames, including ticks and fragments.
+    pub frames: u64,
+
+    /// Number of ticks.
+    pub ticks: u64,
+
+    /// Number of messages (excluding ticks) per operation code of the control message.
+    ///
+    /// See [`Message::op()`](crate::message::Message::op) for the operation code of each variant.
+    pub messages: BTreeMap<i32, u64>,
+
+    /// Time of the last write to or read from the connection.
+    pub last_activity: Option<Instant>,
+}
+
+/// Shared handle to the traffic statistics of one direction of a channel.
+///
+/// This is useful to collect the statistics of a [`Sender`] or [`Receiver`] moved into another task
+/// (e.g., by [`Sender::into_handle()`]).
+///
+/// [`Sender`]: crate::message::Sender
+/// [`Receiver`]: crate::message::Receiver
+/// [`Sender::into_handle()`]: crate::message::Sender::into_handle
+#[derive(Debug, Clone, Default)]
+pub struct TrafficStatsHandle {
+    counters: Arc<Counters>,
+}
+
+impl TrafficStatsHandle {
+    /// Returns the current statistics.
+    pub fn snapshot(&self) -> TrafficStats {
+        let counters = &self.counters;
+        let last_activity = counters.last_activity.load(Ordering::Relaxed);
+        TrafficStats {
+            bytes: counters.bytes.load(Ordering::Relaxed),
+            frames: counters.frames.load(Ordering::Relaxed),
+            ticks: counters.ticks.load(Ordering::Relaxed),
+            messages: counters.lock_messages().clone(),
+            last_activity: (last_activity > 0)
+                .then(|| counters.base + Duration::from_nanos(last_activity - 1)),
+        }
+    }
+
+    pub(crate) fn add_bytes(&self, n: usize) {
+        let counters = &self.counters;
+        counters.bytes.fetch_add(n as u64, Ordering::Relaxed);
+        let elapsed = counters.base.elapsed().as_nanos() as u64;
+        counters.last_activity.store(elapsed + 1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_frame(&self, is_tick: bool) {
+        self.counters.frames.fetch_add(1, Ordering::Relaxed);
+        if is_tick {
+            self.counters.ticks.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn add_message(&self, op: Option<i32>) {
+        if let Some(op) = op {
+            *self.counters.lock_messages().entry(op).or_default() += 1;
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Counters {
+    base: Instant,
+    bytes: AtomicU64,
+    frames: AtomicU64,
+    ticks: AtomicU64,
+    messages: Mutex<BTreeMap<i32, u64>>,
+
+    // Nanoseconds since `base` plus one (zero means no activity).
+    last_activity: AtomicU64,
+}
+
+impl Counters {
+    fn lock_messages(&self) -> std::sync::MutexGuard<'_, BTreeMap<i32, u64>> {
+        self.messages.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for Counters {
+    fn default() -> Self {
+        Self {
+            base: Instant::now(),
+            bytes: AtomicU64::new(0),
+            frames: AtomicU64::new(0),
+            ticks: AtomicU64::new(0),
+            messages: Mutex::new(BTreeMap::new()),
+            last_activity: AtomicU64::new(0),
+        }
+    }
+}
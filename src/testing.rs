@@ -0,0 +1,238 @@
+//! In-memory transport for testing distribution without sockets.
+//!
+//! [`duplex()`] makes a pair of connected streams that satisfy what
+//! [`ClientSideHandshake`](crate::handshake::ClientSideHandshake),
+//! [`ServerSideHandshake`](crate::handshake::ServerSideHandshake) and
+//! [`channel()`](crate::message::channel) need, so that a client and a server can be wired
+//! inside one process without any async runtime, EPMD or Erlang node.
+//!
+//! This module is available only if the `testing` feature is enabled.
+//!
+//! # Examples
+//!
+//! ```
+//! use erl_dist::handshake::{ClientSideHandshake, HandshakeStatus, ServerSideHandshake};
+//! use erl_dist::message::{Message, channel};
+//! use erl_dist::node::{Creation, LocalNode};
+//! use erl_dist::term::{Atom, Pid};
+//! use erl_dist::testing::duplex;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! futures::executor::block_on(async {
+//!     let (client, server) = duplex(1024);
+//!     let client_node = LocalNode::new("foo@localhost".parse()?, Creation::random());
+//!     let server_node = LocalNode::new("bar@localhost".parse()?, Creation::random());
+//!     let client = async {
+//!         let mut handshake = ClientSideHandshake::new(client, client_node.clone(), "cookie");
+//!         handshake.execute_send_name(erl_dist::LOWEST_DISTRIBUTION_PROTOCOL_VERSION).await?;
+//!         handshake.execute_rest(true).await
+//!     };
+//!     let server = async {
+//!         let mut handshake = ServerSideHandshake::new(server, server_node.clone(), "cookie");
+//!         handshake.execute_recv_name().await?;
+//!         handshake.execute_rest(HandshakeStatus::Ok).await
+//!     };
+//!     let (client, server) = futures::future::try_join(client, server).await?;
+//!
+//!     let (mut tx, _) = channel(client.0, client_node.flags & client.1.flags);
+//!     let (_, mut rx) = channel(server.0, server_node.flags & server.1.flags);
+//!     let pid = Pid::new("foo@localhost", 0, 0, 0);
+//!     let msg = Message::send(pid, Atom::from("hello").into());
+//!     tx.send(msg.clone()).await?;
+//!     assert_eq!(rx.recv().await?, msg);
+//!     Ok(())
+//! })
+//! # }
+//! ```
+use futures::io::{AsyncRead, AsyncWrite};
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+/// Makes a pair of connected in-memory streams.
+///
+/// Bytes written to one stream can be read from the other.
+/// `capacity` is the maximum number of bytes buffered in each direction;
+/// writes wait while the buffer is full (values smaller than `1` are treated as `1`).
+pub fn duplex(capacity: usize) -> (DuplexStream, DuplexStream) {
+    let a_to_b = Arc::new(Pipe::new(capacity.max(1)));
+    let b_to_a = Arc::new(Pipe::new(capacity.max(1)));
+    let a = DuplexStream::new(Arc::clone(&b_to_a), Arc::clone(&a_to_b));
+    let b = DuplexStream::new(a_to_b, b_to_a);
+    (a, b)
+}
+
+/// In-memory stream made by [`duplex()`].
+///
+/// Clones of a stream share the same buffers (like `&TcpStream`).
+/// When all the clones of a stream are dropped (or [`AsyncWriteExt::close()`](futures::AsyncWriteExt::close)
+/// is called), the peer reads EOF.
+#[derive(Debug, Clone)]
+pub struct DuplexStream {
+    inner: Arc<Endpoint>,
+}
+
+impl DuplexStream {
+    fn new(read: Arc<Pipe>, write: Arc<Pipe>) -> Self {
+        Self {
+            inner: Arc::new(Endpoint { read, write }),
+        }
+    }
+}
+
+impl AsyncRead for DuplexStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let mut state = self.inner.read.lock();
+        if state.buf.is_empty() {
+            if state.write_closed || buf.is_empty() {
+                return Poll::Ready(Ok(0));
+            }
+            state.read_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        let n = buf.len().min(state.buf.len());
+        for (dst, src) in buf.iter_mut().zip(state.buf.drain(..n)) {
+            *dst = src;
+        }
+        if let Some(waker) = state.write_waker.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl AsyncWrite for DuplexStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let mut state = self.inner.write.lock();
+        if state.write_closed || state.read_closed {
+            return Poll::Ready(Err(std::io::ErrorKind::BrokenPipe.into()));
+        }
+        let n = buf.len().min(state.capacity - state.buf.len());
+        if n == 0 && !buf.is_empty() {
+            state.write_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        state.buf.extend(&buf[..n]);
+        if let Some(waker) = state.read_waker.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.inner.write.close_write();
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[derive(Debug)]
+struct Endpoint {
+    read: Arc<Pipe>,
+    write: Arc<Pipe>,
+}
+
+impl Drop for Endpoint {
+    fn drop(&mut self) {
+        self.write.close_write();
+        self.read.close_read();
+    }
+}
+
+#[derive(Debug)]
+struct Pipe {
+    state: Mutex<PipeState>,
+}
+
+#[derive(Debug)]
+struct PipeState {
+    buf: VecDeque<u8>,
+    capacity: usize,
+    write_closed: bool,
+    read_closed: bool,
+    read_waker: Option<Waker>,
+    write_waker: Option<Waker>,
+}
+
+impl Pipe {
+    fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(PipeState {
+                buf: VecDeque::new(),
+                capacity,
+                write_closed: false,
+                read_closed: false,
+                read_waker: None,
+                write_waker: None,
+            }),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, PipeState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn close_write(&self) {
+        let mut state = self.lock();
+        state.write_closed = true;
+        if let Some(waker) = state.read_waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn close_read(&self) {
+        let mut state = self.lock();
+        state.read_closed = true;
+        if let Some(waker) = state.write_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{AsyncReadExt as _, AsyncWriteExt as _};
+
+    #[test]
+    fn duplex_streams_work() {
+        futures::executor::block_on(async {
+            let (mut a, mut b) = duplex(4);
+
+            // Writes larger than the capacity complete as the peer reads.
+            let write = async {
+                a.write_all(b"hello world").await.unwrap();
+                a.close().await.unwrap();
+            };
+            let read = async {
+                let mut buf = Vec::new();
+                b.read_to_end(&mut buf).await.unwrap();
+                buf
+            };
+            let ((), buf) = futures::future::join(write, read).await;
+            assert_eq!(buf, b"hello world");
+
+            // Writes to a dropped peer fail.
+            let (mut a, b) = duplex(4);
+            std::mem::drop(b);
+            assert_eq!(
+                a.write_all(b"foo").await.unwrap_err().kind(),
+                std::io::ErrorKind::BrokenPipe
+            );
+        });
+    }
+}
@@ -0,0 +1,382 @@
+//! TLS distribution carrier compatible with Erlang's `inet_tls_dist`.
+//!
+//! Erlang nodes started with `-proto_dist inet_tls` wrap each distribution connection in TLS
+//! in the same way as OTP's `ssl_dist`:
+//! the TLS handshake is done right after the TCP connection is established,
+//! and then the ordinary [`handshake`](crate::handshake) and [`message`](crate::message)s are
+//! exchanged over the encrypted stream without any other change.
+//! Note that EPMD is still accessed without TLS.
+//!
+//! This module is available only if the `tls` feature is enabled.
+//!
+//! # Examples
+//!
+//! Sends a message to an Erlang node started with `-proto_dist inet_tls`:
+//! ```no_run
+//! # use smol::net::TcpStream;
+//! use erl_dist::message::Message;
+//! use erl_dist::node::{Creation, LocalNode};
+//! use erl_dist::term::{Atom, Pid};
+//! use erl_dist::tls::{self, rustls};
+//! # use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+//!
+//! # async fn run(
+//! #     ca_cert: CertificateDer<'static>,
+//! #     client_cert_chain: Vec<CertificateDer<'static>>,
+//! #     client_key: PrivateKeyDer<'static>,
+//! # ) -> Result<(), Box<dyn std::error::Error>> {
+//! let mut roots = rustls::RootCertStore::empty();
+//! roots.add(ca_cert)?;
+//! let config = tls::client_config(roots, Some((client_cert_chain, client_key)))?;
+//!
+//! let connection = TcpStream::connect(("localhost", 7483)).await?;
+//! let server_name = "localhost".try_into()?;
+//! let local_node = LocalNode::new("foo@localhost".parse()?, Creation::random());
+//! let (mut tx, _, _peer_node) =
+//!     tls::connect(connection, config, server_name, local_node.clone(), "cookie").await?;
+//!
+//! let from_pid = Pid::new(local_node.name.to_string(), 0, 0, local_node.creation.get());
+//! let msg = Message::reg_send(from_pid, Atom::from("bar"), Atom::from("hello").into());
+//! tx.send(msg).await?;
+//! # Ok(())
+//! # }
+//! ```
+use crate::handshake::{ClientSideHandshake, HandshakeError, HandshakeStatus, ServerSideHandshake};
+use crate::message::{Receiver, Sender};
+use crate::node::{LocalNode, PeerNode};
+use futures::io::{AsyncRead, AsyncWrite, ReadHalf, WriteHalf};
+use futures_rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use futures_rustls::rustls::server::{VerifierBuilderError, WebPkiClientVerifier};
+use futures_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use std::sync::Arc;
+
+pub use futures_rustls::client::TlsStream as ClientTlsStream;
+pub use futures_rustls::rustls;
+pub use futures_rustls::server::TlsStream as ServerTlsStream;
+
+/// Makes a TLS client configuration.
+///
+/// `roots` is used to verify the server certificate.
+/// If `client_cert` (a certificate chain and its private key) is given, it is presented to
+/// the server (e.g., a node with `{verify, verify_peer}` and `{fail_if_no_peer_cert, true}` in its `ssl_dist` options).
+///
+/// If you need other settings, please build [`ClientConfig`] by yourself.
+pub fn client_config(
+    roots: RootCertStore,
+    client_cert: Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+) -> Result<Arc<ClientConfig>, TlsError> {
+    let builder = ClientConfig::builder_with_provider(crypto_provider())
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots);
+    let config = if let Some((cert_chain, key)) = client_cert {
+        builder.with_client_auth_cert(cert_chain, key)?
+    } else {
+        builder.with_no_client_auth()
+    };
+    Ok(Arc::new(config))
+}
+
+/// Makes a TLS server configuration.
+///
+/// `cert_chain` and `key` are the certificate chain and the private key of this node.
+/// If `client_roots` is given, clients are required to present a certificate that is verified by it.
+///
+/// If you need other settings, please build [`ServerConfig`] by yourself.
+pub fn server_config(
+    cert_chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+    client_roots: Option<RootCertStore>,
+) -> Result<Arc<ServerConfig>, TlsError> {
+    let builder = ServerConfig::builder_with_provider(crypto_provider())
+        .with_safe_default_protocol_versions()?;
+    let builder = if let Some(roots) = client_roots {
+        let verifier =
+            WebPkiClientVerifier::builder_with_provider(Arc::new(roots), crypto_provider())
+                .build()?;
+        builder.with_client_cert_verifier(verifier)
+    } else {
+        builder.with_no_client_auth()
+    };
+    let config = builder.with_single_cert(cert_chain, key)?;
+    Ok(Arc::new(config))
+}
+
+fn crypto_provider() -> Arc<rustls::crypto::CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+/// Executes the client-side TLS handshake.
+///
+/// The returned stream can be passed to [`ClientSideHandshake::new()`].
+/// This is useful if you need to control the distribution handshake or to inspect the peer certificates.
+pub async fn tls_connect<T>(
+    connection: T,
+    config: Arc<ClientConfig>,
+    server_name: ServerName<'static>,
+) -> std::io::Result<ClientTlsStream<T>>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    futures_rustls::TlsConnector::from(config)
+        .connect(server_name, connection)
+        .await
+}
+
+/// Executes the server-side TLS handshake.
+///
+/// The returned stream can be passed to [`ServerSideHandshake::new()`].
+/// This is useful if you need to control the distribution handshake or to inspect the peer certificates.
+pub async fn tls_accept<T>(
+    connection: T,
+    config: Arc<ServerConfig>,
+) -> std::io::Result<ServerTlsStream<T>>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    futures_rustls::TlsAcceptor::from(config)
+        .accept(connection)
+        .await
+}
+
+/// Connects to a TLS distribution node.
+///
+/// This executes the TLS handshake, the client-side distribution handshake and then makes a channel
+/// over the encrypted stream.
+/// If the peer replies [`HandshakeStatus::Alive`], the handshake continues.
+#[allow(clippy::type_complexity)]
+pub async fn connect<T>(
+    connection: T,
+    config: Arc<ClientConfig>,
+    server_name: ServerName<'static>,
+    local_node: LocalNode,
+    cookie: &str,
+) -> Result<
+    (
+        Sender<WriteHalf<ClientTlsStream<T>>>,
+        Receiver<ReadHalf<ClientTlsStream<T>>>,
+        PeerNode,
+    ),
+    TlsError,
+>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let stream = tls_connect(connection, config, server_name)
+        .await
+        .map_err(TlsError::Tls)?;
+    let flags = local_node.flags;
+    let mut handshake = ClientSideHandshake::new(stream, local_node, cookie);
+    handshake
+        .execute_send_name(crate::LOWEST_DISTRIBUTION_PROTOCOL_VERSION)
+        .await?;
+    let (stream, peer_node) = handshake.execute_rest(true).await?;
+    let (tx, rx) = crate::message::split_channel(stream, flags & peer_node.flags);
+    Ok((tx, rx, peer_node))
+}
+
+/// Accepts a connection from a TLS distribution node.
+///
+/// This executes the TLS handshake, the server-side distribution handshake and then makes a channel
+/// over the encrypted stream.
+/// Peers requesting a dynamic node name are rejected with [`HandshakeStatus::NotAllowed`].
+#[allow(clippy::type_complexity)]
+pub async fn accept<T>(
+    connection: T,
+    config: Arc<ServerConfig>,
+    local_node: LocalNode,
+    cookie: &str,
+) -> Result<
+    (
+        Sender<WriteHalf<ServerTlsStream<T>>>,
+        Receiver<ReadHalf<ServerTlsStream<T>>>,
+        PeerNode,
+    ),
+    TlsError,
+>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let stream = tls_accept(connection, config)
+        .await
+        .map_err(TlsError::Tls)?;
+    let flags = local_node.flags;
+    let mut handshake = ServerSideHandshake::new(stream, local_node, cookie);
+    let status = match handshake.execute_recv_name().await? {
+        Some(_) => HandshakeStatus::Ok,
+        None => HandshakeStatus::NotAllowed,
+    };
+    let (stream, peer_node) = handshake.execute_rest(status).await?;
+    let (tx, rx) = crate::message::split_channel(stream, flags & peer_node.flags);
+    Ok((tx, rx, peer_node))
+}
+
+/// Possible errors of TLS connections.
+#[derive(Debug)]
+#[non_exhaustive]
+#[allow(missing_docs)]
+pub enum TlsError {
+    /// Invalid TLS configuration.
+    Config(rustls::Error),
+
+    /// Invalid client certificate verifier configuration.
+    ClientVerifier(VerifierBuilderError),
+
+    /// TLS handshake failure (including I/O errors).
+    Tls(std::io::Error),
+
+    /// Distribution handshake failure.
+    Handshake(HandshakeError),
+}
+
+impl std::fmt::Display for TlsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Config(error) => write!(f, "invalid TLS configuration: {error}"),
+            Self::ClientVerifier(error) => {
+                write!(f, "invalid client certificate verifier: {error}")
+            }
+            Self::Tls(error) => write!(f, "TLS handshake failed: {error}"),
+            Self::Handshake(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for TlsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Config(error) => Some(error),
+            Self::ClientVerifier(error) => Some(error),
+            Self::Tls(error) => Some(error),
+            Self::Handshake(error) => Some(error),
+        }
+    }
+}
+
+impl From<rustls::Error> for TlsError {
+    fn from(value: rustls::Error) -> Self {
+        Self::Config(value)
+    }
+}
+
+impl From<VerifierBuilderError> for TlsError {
+    fn from(value: VerifierBuilderError) -> Self {
+        Self::ClientVerifier(value)
+    }
+}
+
+impl From<HandshakeError> for TlsError {
+    fn from(value: HandshakeError) -> Self {
+        Self::Handshake(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Message;
+    use crate::node::Creation;
+    use crate::term::{Atom, Pid};
+    use futures_rustls::pki_types::PrivatePkcs8KeyDer;
+
+    struct TestPki {
+        roots: RootCertStore,
+        issuer: rcgen::Issuer<'static, rcgen::KeyPair>,
+    }
+
+    impl TestPki {
+        fn new() -> Self {
+            let mut params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+            params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+            let key = rcgen::KeyPair::generate().unwrap();
+            let cert = params.self_signed(&key).unwrap();
+            let mut roots = RootCertStore::empty();
+            roots.add(cert.der().clone()).unwrap();
+            Self {
+                roots,
+                issuer: rcgen::Issuer::new(params, key),
+            }
+        }
+
+        fn issue(&self, name: &str) -> (Vec<CertificateDer<'static>>, PrivateKeyDer<'static>) {
+            let params = rcgen::CertificateParams::new(vec![name.to_owned()]).unwrap();
+            let key = rcgen::KeyPair::generate().unwrap();
+            let cert = params.signed_by(&key, &self.issuer).unwrap();
+            let key = PrivatePkcs8KeyDer::from(key.serialize_der()).into();
+            (vec![cert.der().clone()], key)
+        }
+    }
+
+    #[test]
+    fn tls_connections_work() {
+        let pki = TestPki::new();
+        let (cert_chain, key) = pki.issue("localhost");
+        let server_config = server_config(cert_chain, key, Some(pki.roots.clone())).unwrap();
+        let client_config = client_config(pki.roots.clone(), Some(pki.issue("foo"))).unwrap();
+
+        smol::block_on(async {
+            let (client, server) = crate::tests::tcp_stream_pair().await;
+            let client = async {
+                let local_node =
+                    LocalNode::new("foo@localhost".parse().unwrap(), Creation::random());
+                let server_name = "localhost".try_into().unwrap();
+                connect(
+                    client,
+                    client_config,
+                    server_name,
+                    local_node,
+                    crate::tests::COOKIE,
+                )
+                .await
+                .unwrap()
+            };
+            let server = async {
+                let local_node =
+                    LocalNode::new("bar@localhost".parse().unwrap(), Creation::random());
+                accept(server, server_config, local_node, crate::tests::COOKIE)
+                    .await
+                    .unwrap()
+            };
+            let ((mut tx, _, peer_node), (_, mut peer_rx, _)) =
+                futures::future::join(client, server).await;
+            assert_eq!(peer_node.name.to_string(), "bar@localhost");
+
+            let pid = Pid::new("foo@localhost", 0, 0, 0);
+            let msg = Message::send(pid, Atom::from("hello").into());
+            tx.send(msg.clone()).await.unwrap();
+            assert_eq!(peer_rx.recv().await.unwrap(), msg);
+        });
+    }
+
+    #[test]
+    fn client_certificate_is_required() {
+        let pki = TestPki::new();
+        let (cert_chain, key) = pki.issue("localhost");
+        let server_config = server_config(cert_chain, key, Some(pki.roots.clone())).unwrap();
+        let client_config = client_config(pki.roots.clone(), None).unwrap();
+
+        smol::block_on(async {
+            let (client, server) = crate::tests::tcp_stream_pair().await;
+            let client = async {
+                let local_node =
+                    LocalNode::new("foo@localhost".parse().unwrap(), Creation::random());
+                let server_name = "localhost".try_into().unwrap();
+                connect(
+                    client,
+                    client_config,
+                    server_name,
+                    local_node,
+                    crate::tests::COOKIE,
+                )
+                .await
+            };
+            let server = async {
+                let local_node =
+                    LocalNode::new("bar@localhost".parse().unwrap(), Creation::random());
+                accept(server, server_config, local_node, crate::tests::COOKIE).await
+            };
+            let (client_result, server_result) = futures::future::join(client, server).await;
+            assert!(client_result.is_err());
+            assert!(matches!(server_result, Err(TlsError::Tls(_))));
+        });
+    }
+}
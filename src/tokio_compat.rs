@@ -0,0 +1,158 @@
+use crate::DistributionFlags;
+use crate::epmd::EpmdClient;
+use crate::handshake::{ClientSideHandshake, ServerSideHandshake};
+use crate::message::{Keepalive, Receiver, Sender};
+use crate::node::LocalNode;
+use futures::io::{ReadHalf, WriteHalf};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::compat::{Compat, TokioAsyncReadCompatExt as _};
+
+impl<T> EpmdClient<Compat<T>>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Makes a new [`EpmdClient`] instance from a [`tokio`] connection.
+    ///
+    /// This is available only if the `tokio` feature is enabled.
+    pub fn new_tokio(connection: T) -> Self {
+        Self::new(connection.compat())
+    }
+}
+
+impl<T> ClientSideHandshake<Compat<T>>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Makes a new [`ClientSideHandshake`] instance from a [`tokio`] connection.
+    ///
+    /// The connection returned by [`ClientSideHandshake::execute_rest()`] is wrapped by [`Compat`].
+    /// Please use [`Compat::into_inner()`] to get the original connection (e.g., to pass it to [`tokio_channel()`]).
+    ///
+    /// This is available only if the `tokio` feature is enabled.
+    pub fn new_tokio(connection: T, local_node: LocalNode, cookie: &str) -> Self {
+        Self::new(connection.compat(), local_node, cookie)
+    }
+}
+
+impl<T> ServerSideHandshake<Compat<T>>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Makes a new [`ServerSideHandshake`] instance from a [`tokio`] connection.
+    ///
+    /// The connection returned by [`ServerSideHandshake::execute_rest()`] is wrapped by [`Compat`].
+    /// Please use [`Compat::into_inner()`] to get the original connection (e.g., to pass it to [`tokio_channel()`]).
+    ///
+    /// This is available only if the `tokio` feature is enabled.
+    pub fn new_tokio(connection: T, local_node: LocalNode, cookie: &str) -> Self {
+        Self::new(connection.compat(), local_node, cookie)
+    }
+}
+
+/// Makes a channel to send/receive messages to/from a connected node using a [`tokio`] connection.
+///
+/// See [`channel()`](crate::message::channel) for the details of the arguments.
+///
+/// This is available only if the `tokio` feature is enabled.
+#[allow(clippy::type_complexity)]
+pub fn tokio_channel<T>(
+    connection: T,
+    flags: DistributionFlags,
+) -> (Sender<WriteHalf<Compat<T>>>, Receiver<ReadHalf<Compat<T>>>)
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    crate::message::split_channel(connection.compat(), flags)
+}
+
+impl<T> ClientSideHandshake<T>
+where
+    T: futures::io::AsyncRead + futures::io::AsyncWrite + Unpin,
+{
+    /// Sets the time limit of the whole handshake using [`tokio::time::sleep()`] as the timer.
+    ///
+    /// See [`ClientSideHandshake::set_timeout()`] for the details.
+    ///
+    /// This is available only if the `tokio` feature is enabled.
+    pub fn set_timeout_tokio(&mut self, timeout: Duration) {
+        self.set_timeout(timeout, tokio::time::sleep);
+    }
+}
+
+impl<T> ServerSideHandshake<T>
+where
+    T: futures::io::AsyncRead + futures::io::AsyncWrite + Unpin,
+{
+    /// Sets the time limit of the whole handshake using [`tokio::time::sleep()`] as the timer.
+    ///
+    /// See [`ServerSideHandshake::set_timeout()`] for the details.
+    ///
+    /// This is available only if the `tokio` feature is enabled.
+    pub fn set_timeout_tokio(&mut self, timeout: Duration) {
+        self.set_timeout(timeout, tokio::time::sleep);
+    }
+}
+
+impl Keepalive<fn(Duration) -> tokio::time::Sleep> {
+    /// Makes a new [`Keepalive`] instance that uses [`tokio::time::sleep()`] as the timer.
+    ///
+    /// This is available only if the `tokio` feature is enabled.
+    pub fn new_tokio(net_ticktime: Duration) -> Self {
+        Self::new(net_ticktime, tokio::time::sleep)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handshake::HandshakeStatus;
+    use crate::message::Message;
+    use crate::node::Creation;
+    use crate::term::{Atom, Pid};
+
+    #[tokio::test]
+    async fn tokio_connections_work() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let client = async {
+            let connection = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let local_node = LocalNode::new("foo@localhost".parse().unwrap(), Creation::random());
+            let flags = local_node.flags;
+            let mut handshake =
+                ClientSideHandshake::new_tokio(connection, local_node, crate::tests::COOKIE);
+            handshake
+                .execute_send_name(crate::LOWEST_DISTRIBUTION_PROTOCOL_VERSION)
+                .await
+                .unwrap();
+            let (connection, peer_node) = handshake.execute_rest(true).await.unwrap();
+            tokio_channel(connection.into_inner(), flags & peer_node.flags)
+        };
+        let server = async {
+            let (connection, _) = listener.accept().await.unwrap();
+            let local_node = LocalNode::new("bar@localhost".parse().unwrap(), Creation::random());
+            let flags = local_node.flags;
+            let mut handshake =
+                ServerSideHandshake::new_tokio(connection, local_node, crate::tests::COOKIE);
+            handshake.execute_recv_name().await.unwrap();
+            let (connection, peer_node) =
+                handshake.execute_rest(HandshakeStatus::Ok).await.unwrap();
+            tokio_channel(connection.into_inner(), flags & peer_node.flags)
+        };
+        let ((mut tx, _), (mut peer_tx, mut peer_rx)) = tokio::join!(client, server);
+
+        let pid = Pid::new("foo@localhost", 0, 0, 0);
+        let msg = Message::send(pid, Atom::from("hello").into());
+        tx.send(msg.clone()).await.unwrap();
+        assert_eq!(peer_rx.recv().await.unwrap(), msg);
+
+        // The client doesn't reply, so the keepalive only sends ticks and then times out.
+        let mut keepalive = Keepalive::new_tokio(Duration::from_millis(200));
+        let result = keepalive.recv(&mut peer_tx, &mut peer_rx).await;
+        assert!(matches!(
+            result,
+            Err(crate::message::RecvError::TickTimeout)
+        ));
+    }
+}